@@ -0,0 +1,649 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::ignore::Ignore;
+use crate::index::{Index, IndexEntry, IndexLock};
+use crate::objects::Object;
+use crate::refs::{
+    current_branch, expand_ref, is_valid_branch_name, list_refs, read_ref, write_ref,
+    write_symbolic_ref,
+};
+use crate::repository::Repository;
+use crate::status::{file_mode, hash_file, worktree_metadata};
+use crate::{
+    loose_objects, read_object, read_tree, set_upstream, short_hash, write_work_file, Hash,
+};
+
+/*
+checkout moves the index and work tree from HEAD's tree to the target's,
+one path at a time:
+
+- a path the same in both trees is left alone, local changes and all
+- a path that differs is replaced (or removed), but only if neither the
+  index nor the file on disk has changed it since HEAD, or the index
+  already holds the new version
+- an untracked file is never overwritten unless it is ignored
+
+If any path cannot be switched nothing is touched. With `force` the index
+and tracked files are reset to the target whatever they held.
+
+HEAD then points at the target branch, or at the commit itself when
+detached.
+*/
+
+type Version = Option<(u32, Hash)>;
+
+#[derive(Debug, Default)]
+pub struct CheckoutOptions {
+    /// Throw away local changes to tracked files.
+    pub force: bool,
+    /// Create this branch at the target and switch to it (`-b`, `-c`).
+    pub new_branch: Option<String>,
+    pub detach: bool,
+    /// `switch` only leaves a branch for a commit with `--detach`.
+    pub require_branch: bool,
+}
+
+/// Switch to a branch or commit. Without a target, HEAD is checked out
+/// again (or detached, with `detach`).
+pub fn checkout(
+    repo: &Repository,
+    target: Option<&str>,
+    options: &CheckoutOptions,
+) -> io::Result<()> {
+    let current = current_branch(repo).ok();
+    let head = read_ref(repo, "HEAD")?;
+    let unborn = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "You are on a branch yet to be born",
+        )
+    };
+
+    // Where HEAD goes: a branch (possibly new, possibly unborn) or a commit
+    let mut created = false;
+    let mut upstream = None;
+    let (branch, commit) = match (&options.new_branch, target) {
+        (Some(name), _) => {
+            let branch = format!("refs/heads/{}", name);
+            if !is_valid_branch_name(name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}' is not a valid branch name", name),
+                ));
+            }
+            if read_ref(repo, &branch)?.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("a branch named '{}' already exists", name),
+                ));
+            }
+            created = true;
+            match target {
+                Some(start) => {
+                    // Starting from a remote-tracking branch tracks it
+                    upstream = remote_branch(repo, &format!("refs/remotes/{}", start))?;
+                    let commit = resolve_commit(repo, start)?.ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!(
+                                "'{}' is not a commit and a branch '{}' cannot be created from it",
+                                start, name
+                            ),
+                        )
+                    })?;
+                    (Some(branch), Some(commit))
+                }
+                None => (Some(branch), head),
+            }
+        }
+        (None, None | Some("HEAD")) if options.detach => (None, Some(head.ok_or_else(unborn)?)),
+        (None, None | Some("HEAD")) => (current.clone(), head),
+        (None, Some(name)) => {
+            let branch = format!("refs/heads/{}", name);
+            if !options.detach && read_ref(repo, &branch)?.is_some() {
+                let commit = read_ref(repo, &branch)?;
+                (Some(branch), commit)
+            } else if let Some((remote, merge)) = guess_remote_branch(repo, name, options)? {
+                // `checkout foo` with only `origin/foo` creates a branch tracking it
+                let tracking = format!("refs/remotes/{}/{}", remote, name);
+                upstream = Some((remote, merge));
+                created = true;
+                (Some(branch), read_ref(repo, &tracking)?)
+            } else if let Some(commit) = resolve_commit(repo, name)? {
+                if options.require_branch && !options.detach {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "a branch is expected, got {} '{}'",
+                            ref_kind(repo, name),
+                            name
+                        ),
+                    ));
+                }
+                (None, Some(commit))
+            } else if options.require_branch {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("invalid reference: {}", name),
+                ));
+            } else {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("pathspec '{}' did not match any file(s) known to git", name),
+                ));
+            }
+        }
+    };
+
+    if let Some(commit) = commit {
+        let old_tree = head.map(|head| commit_tree(repo, &head)).transpose()?;
+        let new_tree = commit_tree(repo, &commit)?;
+        switch_trees(repo, old_tree.as_ref(), &new_tree, options.force)?;
+    }
+
+    // Leaving a detached HEAD mentions where it was
+    let previous =
+        head.filter(|head| current.is_none() && (branch.is_some() || commit != Some(*head)));
+    if let Some(previous) = previous {
+        eprintln!("Previous HEAD position was {}", describe(repo, &previous)?);
+    }
+    match (branch, commit) {
+        (Some(branch), commit) => {
+            let name = branch.trim_start_matches("refs/heads/");
+            if created {
+                if let Some(commit) = commit {
+                    write_ref(repo, &branch, &commit)?;
+                }
+                if let Some((remote, merge)) = upstream {
+                    set_upstream(repo, name, &remote, &merge)?;
+                    println!(
+                        "branch '{}' set up to track '{}/{}'.",
+                        name,
+                        remote,
+                        merge.trim_start_matches("refs/heads/")
+                    );
+                }
+            }
+            write_symbolic_ref(repo, "HEAD", &branch)?;
+            if created {
+                eprintln!("Switched to a new branch '{}'", name);
+            } else if current.as_deref() == Some(branch.as_str()) {
+                eprintln!("Already on '{}'", name);
+            } else {
+                eprintln!("Switched to branch '{}'", name);
+            }
+        }
+        (None, Some(commit)) => {
+            if current.is_some() {
+                eprintln!("Note: switching to '{}'.\n", target.unwrap_or("HEAD"));
+                eprintln!(
+                    "You are in 'detached HEAD' state. You can look around, make experimental"
+                );
+                eprintln!(
+                    "changes and commit them, and you can discard any commits you make in this"
+                );
+                eprintln!("state without impacting any branches by switching back to a branch.\n");
+            }
+            write_ref(repo, "HEAD", &commit)?;
+            eprintln!("HEAD is now at {}", describe(repo, &commit)?);
+        }
+        (None, None) => return Err(unborn()),
+    }
+    Ok(())
+}
+
+/// The remote and branch a remote-tracking ref such as
+/// `refs/remotes/origin/main` stands for, if it exists.
+fn remote_branch(repo: &Repository, name: &str) -> io::Result<Option<(String, String)>> {
+    let Some((remote, branch)) = name
+        .strip_prefix("refs/remotes/")
+        .and_then(|rest| rest.split_once('/'))
+    else {
+        return Ok(None);
+    };
+    if branch == "HEAD" || read_ref(repo, name)?.is_none() {
+        return Ok(None);
+    }
+    Ok(Some((remote.to_string(), format!("refs/heads/{}", branch))))
+}
+
+/// The remote whose branch `name` should become a local branch of the
+/// same name: the only one that has such a branch.
+fn guess_remote_branch(
+    repo: &Repository,
+    name: &str,
+    options: &CheckoutOptions,
+) -> io::Result<Option<(String, String)>> {
+    if options.detach {
+        return Ok(None);
+    }
+    let mut candidates = Vec::new();
+    for (tracking, _) in list_refs(repo, "refs/remotes/")? {
+        if let Some((remote, merge)) = remote_branch(repo, &tracking)? {
+            if merge.strip_prefix("refs/heads/") == Some(name) {
+                candidates.push((remote, merge));
+            }
+        }
+    }
+    Ok(match candidates.len() {
+        1 => candidates.pop(),
+        _ => None,
+    })
+}
+
+/// What kind of ref `name` is, for error messages.
+fn ref_kind(repo: &Repository, name: &str) -> &'static str {
+    let exists = |prefix: &str| {
+        read_ref(repo, &format!("{}{}", prefix, name)).is_ok_and(|hash| hash.is_some())
+    };
+    if exists("refs/tags/") {
+        "tag"
+    } else if exists("refs/remotes/") {
+        "remote branch"
+    } else {
+        "commit"
+    }
+}
+
+/// The commit `name` stands for: a full or abbreviated hash, or a ref
+/// expanded the way git does, with tags peeled. `None` if it names nothing.
+fn resolve_commit(repo: &Repository, name: &str) -> io::Result<Option<Hash>> {
+    let mut hash = Hash::from_hex(name).ok();
+    if hash.is_none() {
+        if let Some(full_name) = expand_ref(repo, name)? {
+            hash = read_ref(repo, &full_name)?;
+        }
+    }
+    if hash.is_none() {
+        hash = expand_abbreviation(repo, name)?;
+    }
+    let Some(mut hash) = hash else {
+        return Ok(None);
+    };
+    loop {
+        match read_object(repo, &hash)? {
+            Object::Commit(_) => return Ok(Some(hash)),
+            Object::Tag(tag) => hash = tag.object,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("reference is not a tree: {}", name),
+                ))
+            }
+        }
+    }
+}
+
+/// The object a unique hash prefix of at least four digits stands for.
+fn expand_abbreviation(repo: &Repository, prefix: &str) -> io::Result<Option<Hash>> {
+    if prefix.len() < 4 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let prefix = prefix.to_ascii_lowercase();
+    let mut matches = BTreeSet::new();
+    for (hash, _) in loose_objects(repo)? {
+        if hash.to_hex().starts_with(&prefix) {
+            matches.insert(hash);
+        }
+    }
+    for pack in repo.packs()?.iter() {
+        for (hash, _, _) in pack.index.entries() {
+            if hash.to_hex().starts_with(&prefix) {
+                matches.insert(hash);
+            }
+        }
+    }
+    if matches.len() > 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("short object ID {} is ambiguous", prefix),
+        ));
+    }
+    Ok(matches.pop_first())
+}
+
+fn commit_tree(repo: &Repository, hash: &Hash) -> io::Result<Hash> {
+    match read_object(repo, hash)? {
+        Object::Commit(commit) => Ok(commit.tree),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is a {}, not a commit", hash.to_hex(), other.kind()),
+        )),
+    }
+}
+
+/// `<short hash> <subject>`, as in "HEAD is now at ...".
+fn describe(repo: &Repository, hash: &Hash) -> io::Result<String> {
+    let subject = match read_object(repo, hash)? {
+        Object::Commit(commit) => String::from_utf8_lossy(&commit.message)
+            .lines()
+            .next()
+            .unwrap_or("")
+            .to_string(),
+        _ => String::new(),
+    };
+    Ok(format!("{} {}", short_hash(hash), subject))
+}
+
+/// Move the index and work tree from tree `old` (HEAD's, `None` when
+/// unborn) to tree `new`.
+pub fn switch_trees(
+    repo: &Repository,
+    old: Option<&Hash>,
+    new: &Hash,
+    force: bool,
+) -> io::Result<()> {
+    let work_tree = repo.work_tree()?;
+    let index_file = repo.path("index");
+    let lock = IndexLock::acquire(&index_file)?;
+    let mut index = Index::load(&index_file)?;
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let mut ignore = Ignore::new(repo)?;
+
+    let mut old_tree = BTreeMap::new();
+    if let Some(old) = old {
+        read_tree(repo, old, "", &mut old_tree)?;
+    }
+    let mut new_tree = BTreeMap::new();
+    read_tree(repo, new, "", &mut new_tree)?;
+    // A tree holding both `a` and `a/x` would write `a/x` through whatever
+    // `a` is, a symlink included
+    for path in new_tree.keys() {
+        if let Some((i, _)) = path
+            .match_indices('/')
+            .find(|(i, _)| new_tree.contains_key(&path[..*i]))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid path '{}': '{}' is not a directory",
+                    path,
+                    &path[..i]
+                ),
+            ));
+        }
+    }
+
+    let mut unmerged: Vec<&str> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage != 0)
+        .map(|entry| entry.path.as_str())
+        .collect();
+    unmerged.dedup();
+    if !force && !unmerged.is_empty() {
+        let paths: Vec<String> = unmerged
+            .iter()
+            .map(|path| format!("{}: needs merge", path))
+            .collect();
+        return Err(io::Error::other(format!(
+            "{}\nyou need to resolve your current index first",
+            paths.join("\n")
+        )));
+    }
+
+    let mut paths: BTreeSet<String> = old_tree.keys().chain(new_tree.keys()).cloned().collect();
+    paths.extend(index.entries().iter().map(|entry| entry.path.clone()));
+
+    let mut updates: Vec<(String, Version)> = Vec::new();
+    let mut local = Vec::new();
+    let mut untracked = Vec::new();
+    let mut lost = Vec::new();
+    for path in paths {
+        let old_version = old_tree.get(&path).copied();
+        let new_version = new_tree.get(&path).copied();
+        let entry = index.get(&path);
+        let index_version = entry.map(|entry| (entry.mode, entry.hash));
+
+        if force {
+            let clean = index_version == new_version
+                && !unmerged.contains(&path.as_str())
+                && match entry {
+                    Some(entry) => matches_index(work_tree, &index, entry, filemode)? == Some(true),
+                    None => true,
+                };
+            if !clean {
+                updates.push((path, new_version));
+            }
+            continue;
+        }
+        if old_version == new_version || index_version == new_version {
+            continue;
+        }
+        match entry {
+            // Staged, or changed on disk since it was staged
+            Some(_) if index_version != old_version => local.push(path.clone()),
+            // Replaced by a directory on disk
+            Some(entry) if entry.mode != 0o160000 && is_dir(work_tree, &path) => {
+                if has_untracked_files(work_tree, &index, &path)? {
+                    lost.push(path.clone());
+                } else {
+                    local.push(path.clone());
+                }
+            }
+            Some(entry) if matches_index(work_tree, &index, entry, filemode)? == Some(false) => {
+                local.push(path.clone())
+            }
+            Some(_) => {}
+            // Deleted from the index: fine if the target deletes it too
+            None if old_version.is_some() => {
+                if new_version.is_none() {
+                    continue;
+                }
+                local.push(path.clone());
+            }
+            // A directory where the target has a file or symlink
+            None if new_version.is_some_and(|(mode, _)| mode != 0o160000)
+                && is_dir(work_tree, &path) =>
+            {
+                lost.extend(has_untracked_files(work_tree, &index, &path)?.then(|| path.clone()))
+            }
+            None if new_version.is_some() => {
+                untracked.extend(in_the_way(work_tree, &index, &mut ignore, &path)?)
+            }
+            None => {}
+        }
+        updates.push((path, new_version));
+    }
+
+    if !local.is_empty() || !untracked.is_empty() || !lost.is_empty() {
+        let list = |paths: &[String]| {
+            paths
+                .iter()
+                .map(|path| format!("\t{}\n", path))
+                .collect::<String>()
+        };
+        let mut message = String::new();
+        if !local.is_empty() {
+            message.push_str(&format!(
+                "Your local changes to the following files would be overwritten by checkout:\n{}Please commit your changes or stash them before you switch branches.\n",
+                list(&local)
+            ));
+        }
+        if !untracked.is_empty() {
+            message.push_str(&format!(
+                "The following untracked working tree files would be overwritten by checkout:\n{}Please move or remove them before you switch branches.\n",
+                list(&untracked)
+            ));
+        }
+        if !lost.is_empty() {
+            message.push_str(&format!(
+                "Updating the following directories would lose untracked files in them:\n{}\n",
+                list(&lost)
+            ));
+        }
+        message.push_str("Aborting");
+        return Err(io::Error::other(message));
+    }
+
+    // Read every blob before the work tree is touched, so a missing or
+    // corrupt object cannot leave it half switched
+    let mut blobs: HashMap<Hash, Vec<u8>> = HashMap::new();
+    for (path, version) in &updates {
+        let Some((mode, hash)) = version else {
+            continue;
+        };
+        if *mode == 0o160000 || blobs.contains_key(hash) {
+            continue;
+        }
+        let Object::Blob(contents) = read_object(repo, hash)? else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} for '{}' is not a blob", hash.to_hex(), path),
+            ));
+        };
+        blobs.insert(*hash, contents);
+    }
+
+    // Removals first, so that files and directories can take each other's place
+    for (path, _) in updates.iter().filter(|(_, version)| version.is_none()) {
+        index.remove(path);
+        // What lies beyond a symlink is not ours to remove
+        if has_symlink_leading_path(work_tree, path) {
+            continue;
+        }
+        let full_path = work_tree.join(path);
+        match fs::symlink_metadata(&full_path) {
+            // Only an empty submodule directory goes
+            Ok(metadata) if metadata.is_dir() => {
+                let _ = fs::remove_dir(&full_path);
+            }
+            Ok(_) => fs::remove_file(&full_path)?,
+            Err(_) => {}
+        }
+        for dir in full_path.ancestors().skip(1) {
+            if dir == work_tree || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+
+    for (path, version) in &updates {
+        let Some((mode, hash)) = version else {
+            continue;
+        };
+        if has_symlink_leading_path(work_tree, path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' is beyond a symbolic link", path),
+            ));
+        }
+        let full_path = work_tree.join(path);
+        make_parent_dirs(work_tree, path)?;
+        let is_dir = fs::symlink_metadata(&full_path).map(|metadata| metadata.is_dir());
+        if *mode == 0o160000 {
+            // Submodules are left as empty directories
+            if matches!(is_dir, Ok(false)) {
+                fs::remove_file(&full_path)?;
+            }
+            fs::create_dir_all(&full_path)?;
+        } else {
+            if matches!(is_dir, Ok(true)) {
+                // Only ignored files are left in it, or anything when forced
+                fs::remove_dir_all(&full_path)?;
+            }
+            write_work_file(&full_path, *mode, &blobs[hash])?;
+        }
+        let metadata = fs::symlink_metadata(&full_path)?;
+        index.add(IndexEntry::new(path.clone(), *mode, *hash, &metadata));
+    }
+
+    lock.commit(&index)
+}
+
+fn is_dir(work_tree: &Path, path: &str) -> bool {
+    fs::symlink_metadata(work_tree.join(path)).is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// Whether the work tree file still holds what the index entry records,
+/// `None` if it is gone.
+fn matches_index(
+    work_tree: &Path,
+    index: &Index,
+    entry: &IndexEntry,
+    filemode: bool,
+) -> io::Result<Option<bool>> {
+    // Only the commit a submodule points at is tracked
+    if entry.mode == 0o160000 {
+        return Ok(Some(true));
+    }
+    let full_path = work_tree.join(&entry.path);
+    let Some(metadata) = worktree_metadata(&full_path)? else {
+        return Ok(None);
+    };
+    if file_mode(&metadata, entry.mode, filemode) != entry.mode {
+        return Ok(Some(false));
+    }
+    if index.stat_matches(entry, &metadata) {
+        return Ok(Some(true));
+    }
+    Ok(Some(hash_file(&full_path, &metadata)? == entry.hash))
+}
+
+/// The untracked, unignored file that writing `path` would overwrite: the
+/// path itself or a file where one of its directories should be.
+fn in_the_way(
+    work_tree: &Path,
+    index: &Index,
+    ignore: &mut Ignore,
+    path: &str,
+) -> io::Result<Option<String>> {
+    for (i, _) in path.match_indices('/') {
+        let dir = &path[..i];
+        match fs::symlink_metadata(work_tree.join(dir)) {
+            Ok(metadata) if metadata.is_dir() => continue,
+            Ok(_) if index.get(dir).is_some() || ignore.is_ignored(dir, false)? => return Ok(None),
+            Ok(_) => return Ok(Some(dir.to_string())),
+            Err(_) => return Ok(None),
+        }
+    }
+    match fs::symlink_metadata(work_tree.join(path)) {
+        Ok(_) if ignore.is_ignored(path, false)? => Ok(None),
+        Ok(_) => Ok(Some(path.to_string())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Whether the directory `dir` holds files not in the index, ignored or not.
+fn has_untracked_files(work_tree: &Path, index: &Index, dir: &str) -> io::Result<bool> {
+    for child in fs::read_dir(work_tree.join(dir))? {
+        let child = child?;
+        let path = format!("{}/{}", dir, child.file_name().to_string_lossy());
+        let untracked = if child.file_type()?.is_dir() {
+            has_untracked_files(work_tree, index, &path)?
+        } else {
+            index.get(&path).is_none()
+        };
+        if untracked {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether one of the directories leading to `path` is a symlink, so that
+/// writing or removing `path` would act on whatever the link points at.
+fn has_symlink_leading_path(work_tree: &Path, path: &str) -> bool {
+    path.match_indices('/').any(|(i, _)| {
+        fs::symlink_metadata(work_tree.join(&path[..i]))
+            .is_ok_and(|metadata| metadata.file_type().is_symlink())
+    })
+}
+
+/// Create the directories leading to `path`, replacing files in the way.
+fn make_parent_dirs(work_tree: &Path, path: &str) -> io::Result<()> {
+    for (i, _) in path.match_indices('/') {
+        let dir = work_tree.join(&path[..i]);
+        match fs::symlink_metadata(&dir) {
+            Ok(metadata) if metadata.is_dir() => continue,
+            Ok(_) => fs::remove_file(&dir)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        fs::create_dir(&dir)?;
+    }
+    Ok(())
+}
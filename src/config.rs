@@ -0,0 +1,432 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+// A git config file is a list of sections:
+//
+// [core]
+//     bare = false
+// [remote "origin"]
+//     url = https://example.com/repo.git
+//     fetch = +refs/heads/*:refs/remotes/origin/*
+//
+// Variables are addressed as `section.subsection.key`. Section and key names
+// are case-insensitive, subsection names are not. A key may appear several
+// times (multi-valued); single-valued lookups use the last one.
+//
+// `set` edits the file as text, so comments and layout survive: an existing
+// value is replaced on its own line, a new one goes at the end of the last
+// matching section, and a new section at the end of the file.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ConfigEntry {
+    section: String,
+    subsection: Option<String>,
+    key: String,
+    value: String,
+    /// Index of the line the entry is on.
+    line: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+    /// Section and subsection of each header, with the index of its line.
+    headers: Vec<(String, Option<String>, usize)>,
+}
+
+fn invalid(line_number: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Bad config line {}: {}", line_number, message),
+    )
+}
+
+/// Split `section.subsection.key` into its parts, normalizing case.
+fn split_name(name: &str) -> Option<(String, Option<String>, String)> {
+    let (section, rest) = name.split_once('.')?;
+    let (subsection, key) = match rest.rsplit_once('.') {
+        Some((subsection, key)) => (Some(subsection.to_string()), key),
+        None => (None, rest),
+    };
+    Some((section.to_lowercase(), subsection, key.to_lowercase()))
+}
+
+/// Parse a value, dropping quotes, comments and surrounding whitespace.
+fn parse_value(raw: &str, line_number: usize) -> io::Result<String> {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut pending_space = String::new();
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(escaped @ ('"' | '\\')) => value.push(escaped),
+                    _ => return Err(invalid(line_number, "invalid escape")),
+                }
+            }
+            ';' | '#' if !in_quotes => break,
+            c if c.is_whitespace() && !in_quotes => pending_space.push(c),
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Err(invalid(line_number, "unterminated quote"));
+    }
+    Ok(value)
+}
+
+impl Config {
+    /// Load a config file; a missing file is an empty config.
+    pub fn load(path: &Path) -> io::Result<Config> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(text: &str) -> io::Result<Config> {
+        let mut entries = Vec::new();
+        let mut headers = Vec::new();
+        let mut section: Option<(String, Option<String>)> = None;
+
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            let mut line = line;
+            if let Some(header) = line.strip_prefix('[') {
+                let end = header
+                    .find(']')
+                    .ok_or_else(|| invalid(line_number, "unterminated section header"))?;
+                let name = &header[..end];
+                section = Some(match name.split_once(' ') {
+                    Some((name, subsection)) => {
+                        let subsection = subsection
+                            .trim()
+                            .strip_prefix('"')
+                            .and_then(|s| s.strip_suffix('"'))
+                            .ok_or_else(|| invalid(line_number, "bad subsection name"))?;
+                        let subsection = subsection.replace("\\\"", "\"").replace("\\\\", "\\");
+                        (name.to_lowercase(), Some(subsection))
+                    }
+                    // Deprecated [section.subsection] syntax
+                    None => match name.split_once('.') {
+                        Some((name, subsection)) => {
+                            (name.to_lowercase(), Some(subsection.to_lowercase()))
+                        }
+                        None => (name.to_lowercase(), None),
+                    },
+                });
+                let (name, subsection) = section.clone().unwrap();
+                headers.push((name, subsection, i));
+                // A variable may follow the header on the same line
+                line = header[end + 1..].trim();
+                if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                    continue;
+                }
+            }
+
+            let (current_section, subsection) = section
+                .clone()
+                .ok_or_else(|| invalid(line_number, "variable outside of a section"))?;
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), parse_value(value, line_number)?),
+                // A key without a value is a boolean true
+                None => (line, "true".to_string()),
+            };
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return Err(invalid(line_number, "bad variable name"));
+            }
+            entries.push(ConfigEntry {
+                section: current_section,
+                subsection,
+                key: key.to_lowercase(),
+                value,
+                line: i,
+            });
+        }
+
+        Ok(Config { entries, headers })
+    }
+
+    /// Last value of `section.subsection.key`, if set.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).pop()
+    }
+
+    /// Last value of `name` read as a boolean the way git reads one: `true`,
+    /// `yes`, `on` and `false`, `no`, `off` in any case, an integer (true
+    /// unless zero), an empty value for false, or a bare key for true.
+    pub fn get_bool(&self, name: &str) -> io::Result<Option<bool>> {
+        let Some(value) = self.get(name) else {
+            return Ok(None);
+        };
+        match value.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" => Ok(Some(true)),
+            "false" | "no" | "off" | "" => Ok(Some(false)),
+            number => number.parse::<i64>().map(|n| Some(n != 0)).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad boolean config value '{}' for '{}'", value, name),
+                )
+            }),
+        }
+    }
+
+    /// Every value of a multi-valued variable, in file order.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.matching(name)
+            .into_iter()
+            .map(|entry| entry.value.as_str())
+            .collect()
+    }
+
+    fn matching(&self, name: &str) -> Vec<&ConfigEntry> {
+        let Some((section, subsection, key)) = split_name(name) else {
+            return Vec::new();
+        };
+        self.entries
+            .iter()
+            .filter(|entry| {
+                entry.section == section && entry.subsection == subsection && entry.key == key
+            })
+            .collect()
+    }
+}
+
+/// Write `value` the way git does, quoted if it would not otherwise read
+/// back the same.
+fn quote_value(value: &str) -> String {
+    let mut quoted = String::new();
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c => quoted.push(c),
+        }
+    }
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    if needs_quotes {
+        format!("\"{}\"", quoted)
+    } else {
+        quoted
+    }
+}
+
+/// `text` with `name` set to `value`; see `set`.
+fn set_in_text(text: &str, name: &str, value: &str) -> io::Result<String> {
+    let (section, subsection, _) = split_name(name)
+        .filter(|(_, _, key)| !key.is_empty())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("key does not contain a section: {}", name),
+            )
+        })?;
+    let config = Config::parse(text)?;
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    // Names are written as given; only lookups ignore their case
+    let key = name.rsplit('.').next().unwrap_or(name);
+    let section_name = name.split('.').next().unwrap_or(name);
+    let line = format!("\t{} = {}", key, quote_value(value));
+
+    match config.matching(name)[..] {
+        [] => {
+            let last_header = config
+                .headers
+                .iter()
+                .rev()
+                .find(|(s, sub, _)| *s == section && *sub == subsection);
+            match last_header {
+                // After the section's last variable, before any comments that follow
+                Some(&(_, _, header)) => {
+                    let next = config
+                        .headers
+                        .iter()
+                        .map(|(_, _, line)| *line)
+                        .find(|&line| line > header)
+                        .unwrap_or(usize::MAX);
+                    let end = config
+                        .entries
+                        .iter()
+                        .map(|entry| entry.line)
+                        .filter(|&line| line > header && line < next)
+                        .max()
+                        .unwrap_or(header);
+                    lines.insert(end + 1, line);
+                }
+                None => {
+                    lines.push(match &subsection {
+                        Some(subsection) => format!(
+                            "[{} \"{}\"]",
+                            section_name,
+                            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                        ),
+                        None => format!("[{}]", section_name),
+                    });
+                    lines.push(line);
+                }
+            }
+        }
+        [entry] => {
+            // Keep a section header that shares the line
+            lines[entry.line] = match lines[entry.line].trim_start().strip_prefix('[') {
+                Some(header) => {
+                    let end = header.find(']').unwrap_or(0);
+                    format!("[{}]\n{}", &header[..end], line)
+                }
+                None => line,
+            };
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot overwrite multiple values of {}", name),
+            ))
+        }
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    Ok(contents)
+}
+
+/// Set `section.subsection.key` to `value` in the config file at `path`,
+/// like `git config`. Fails if the variable has several values.
+pub fn set(path: &Path, name: &str, value: &str) -> io::Result<()> {
+    let mut lock_name = path.as_os_str().to_owned();
+    lock_name.push(".lock");
+    let lock_path = Path::new(&lock_name);
+    let mut lock = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(lock_path)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => io::Error::new(
+                e.kind(),
+                format!("could not lock config file {}", path.display()),
+            ),
+            _ => e,
+        })?;
+
+    let result = match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        read => read,
+    }
+    .and_then(|text| set_in_text(&text, name, value))
+    .and_then(|contents| {
+        lock.write_all(contents.as_bytes())?;
+        lock.sync_all()?;
+        fs::rename(lock_path, path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(lock_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, git_available, ScratchDir};
+
+    #[test]
+    fn booleans_parse_like_git() {
+        let config = Config::parse(
+            "[core]\n\tbare\n\tfilemode = No\n\tsymlinks = ON\n\tignorecase =\n\
+             [a]\n\tyes = YES\n\toff = off\n\tone = 1\n\tzero = 0\n\tbig = 42\n\
+             \tquoted = \"false\"\n\tbad = maybe\n",
+        )
+        .unwrap();
+        let cases = [
+            ("core.bare", Some(true)),
+            ("core.filemode", Some(false)),
+            ("core.symlinks", Some(true)),
+            ("core.ignorecase", Some(false)),
+            ("a.yes", Some(true)),
+            ("a.off", Some(false)),
+            ("a.one", Some(true)),
+            ("a.zero", Some(false)),
+            ("a.big", Some(true)),
+            ("a.quoted", Some(false)),
+            ("a.unset", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(config.get_bool(name).unwrap(), expected, "{}", name);
+        }
+        assert!(config.get_bool("a.bad").is_err());
+    }
+
+    #[test]
+    fn set_edits_files_like_git_config() {
+        if !git_available() {
+            return;
+        }
+        let scratch = ScratchDir::new("config-set");
+        let start = "# kept\n[core]\n\tbare = false\n\n; trailing comment\n\
+                     [branch \"main\"]\n\tremote = origin\n[branch \"other\"] merge = x\n";
+        let sets = [
+            ("core.bare", "true"),
+            ("core.filemode", "false"),
+            ("branch.main.remote", "up stream"),
+            ("branch.main.merge", "refs/heads/main"),
+            ("branch.other.merge", "refs/heads/other"),
+            ("branch.new.remote", "origin"),
+            ("branch.a\"b\\c.remote", "x"),
+            ("remote.origin.url", "https://example.com/a b#c;d"),
+            ("remote.origin.pushurl", " padded\t"),
+            ("remote.origin.fetch", "quote\" and \\ backslash"),
+            ("Section.Key", "line\nbreak"),
+        ];
+
+        let ours = scratch.path().join("ours");
+        let theirs = scratch.path().join("theirs");
+        fs::write(&ours, start).unwrap();
+        fs::write(&theirs, start).unwrap();
+        for (name, value) in sets {
+            set(&ours, name, value).unwrap();
+            git(scratch.path(), &["config", "--file", "theirs", name, value]);
+        }
+        let text = fs::read_to_string(&ours).unwrap();
+        assert_eq!(text, fs::read_to_string(&theirs).unwrap());
+
+        // Every value reads back as it was set
+        let config = Config::parse(&text).unwrap();
+        for (name, value) in sets {
+            assert_eq!(config.get(name), Some(value), "{}", name);
+        }
+
+        // Multi-valued variables are not overwritten
+        fs::write(&ours, "[a]\n\tb = 1\n\tb = 2\n").unwrap();
+        assert!(set(&ours, "a.b", "3").is_err());
+        assert_eq!(
+            fs::read_to_string(&ours).unwrap(),
+            "[a]\n\tb = 1\n\tb = 2\n"
+        );
+        assert!(!scratch.path().join("ours.lock").exists());
+    }
+}
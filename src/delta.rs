@@ -0,0 +1,585 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Seek, SeekFrom};
+
+use crate::objects::{with_header, ObjectKind};
+use crate::packfile::{read_entry, PackEntry, PackEntryKind, MAX_PREALLOC};
+use crate::{compute_sha1, Hash};
+
+/*
+A delta is:
+
+<source size: varint> <target size: varint> <instruction>*
+
+Each instruction is either
+  1xxxxxxx  copy: the low 4 bits select which offset bytes follow, the next
+            3 bits select which size bytes follow (a size of 0 means 0x10000)
+  0nnnnnnn  insert: the next n bytes are copied into the target verbatim
+*/
+
+/// Longest delta chain followed before a pack is considered corrupt.
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+/// An object rebuilt from a pack entry, with its real type.
+#[derive(Debug, Clone)]
+pub struct ResolvedObject {
+    pub kind: ObjectKind,
+    pub data: Vec<u8>,
+    pub hash: Hash,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_size(delta: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let mut size = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *delta
+            .get(*pos)
+            .ok_or_else(|| invalid("Truncated delta header"))?;
+        *pos += 1;
+        if shift > 57 {
+            return Err(invalid("Delta size is too large"));
+        }
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/// Apply a delta to `base`, checking both sizes encoded in the delta header.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let mut pos = 0;
+    let source_size = read_size(delta, &mut pos)?;
+    let target_size = read_size(delta, &mut pos)?;
+    if source_size != base.len() {
+        return Err(invalid(&format!(
+            "Delta expects a base of {} bytes, found {}",
+            source_size,
+            base.len()
+        )));
+    }
+
+    let truncated = || invalid("Truncated delta instruction");
+    let mut target = Vec::with_capacity(target_size.min(MAX_PREALLOC));
+    while pos < delta.len() {
+        let cmd = delta[pos];
+        pos += 1;
+
+        if cmd & 0x80 != 0 {
+            // Copy a range of the base
+            let mut offset = 0usize;
+            for i in 0..4 {
+                if cmd & (1 << i) != 0 {
+                    offset |= (*delta.get(pos).ok_or_else(truncated)? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            let mut size = 0usize;
+            for i in 0..3 {
+                if cmd & (0x10 << i) != 0 {
+                    size |= (*delta.get(pos).ok_or_else(truncated)? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let chunk = offset
+                .checked_add(size)
+                .and_then(|end| base.get(offset..end))
+                .ok_or_else(|| invalid("Delta copy is out of the base bounds"))?;
+            target.extend_from_slice(chunk);
+        } else if cmd != 0 {
+            // Insert literal data
+            let chunk = delta.get(pos..pos + cmd as usize).ok_or_else(truncated)?;
+            target.extend_from_slice(chunk);
+            pos += cmd as usize;
+        } else {
+            return Err(invalid("Reserved delta instruction 0"));
+        }
+
+        if target.len() > target_size {
+            return Err(invalid("Delta produces more data than announced"));
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(invalid(&format!(
+            "Delta expects a result of {} bytes, produced {}",
+            target_size,
+            target.len()
+        )));
+    }
+    Ok(target)
+}
+
+/// Length of the blocks of the base that `create_delta` indexes.
+const BLOCK_SIZE: usize = 16;
+/// Most bytes a single copy instruction emitted by `create_delta` covers.
+const MAX_COPY: usize = 0x10000;
+/// Base positions remembered per block, to bound matching on repetitive data.
+const MAX_CANDIDATES: usize = 64;
+
+fn write_size(out: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        out.push((size & 0x7f) as u8 | 0x80);
+        size >>= 7;
+    }
+    out.push(size as u8);
+}
+
+fn flush_insert(out: &mut Vec<u8>, literal: &mut Vec<u8>) {
+    for chunk in literal.chunks(0x7f) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+    literal.clear();
+}
+
+fn write_copy(out: &mut Vec<u8>, mut offset: usize, mut size: usize) {
+    while size > 0 {
+        let chunk = size.min(MAX_COPY);
+        let cmd_pos = out.len();
+        let mut cmd = 0x80u8;
+        out.push(0);
+        for i in 0..4 {
+            let byte = ((offset >> (8 * i)) & 0xff) as u8;
+            if byte != 0 {
+                cmd |= 1 << i;
+                out.push(byte);
+            }
+        }
+        for i in 0..3 {
+            let byte = ((chunk >> (8 * i)) & 0xff) as u8;
+            if byte != 0 {
+                cmd |= 0x10 << i;
+                out.push(byte);
+            }
+        }
+        out[cmd_pos] = cmd;
+        offset += chunk;
+        size -= chunk;
+    }
+}
+
+/// Encode `target` as a delta against `base`.
+///
+/// Returns `None` when the delta would be larger than `max_size`, so that
+/// callers trying several bases can give up on a poor one early.
+pub fn create_delta(base: &[u8], target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+    if base.len() > u32::MAX as usize {
+        return None;
+    }
+
+    let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (i, block) in base.chunks_exact(BLOCK_SIZE).enumerate() {
+        let offsets = blocks.entry(block).or_default();
+        if offsets.len() < MAX_CANDIDATES {
+            offsets.push(i * BLOCK_SIZE);
+        }
+    }
+
+    let mut out = Vec::new();
+    write_size(&mut out, base.len());
+    write_size(&mut out, target.len());
+
+    let mut literal = Vec::new();
+    let mut pos = 0;
+    while pos < target.len() {
+        let best = target
+            .get(pos..pos + BLOCK_SIZE)
+            .and_then(|block| blocks.get(block))
+            .and_then(|offsets| {
+                offsets
+                    .iter()
+                    .map(|&offset| {
+                        let length = base[offset..]
+                            .iter()
+                            .zip(&target[pos..])
+                            .take_while(|(a, b)| a == b)
+                            .count();
+                        (offset, length)
+                    })
+                    .max_by_key(|&(_, length)| length)
+            });
+
+        match best {
+            Some((mut offset, forward)) => {
+                // Grow the match backwards over literal bytes it also covers
+                let mut length = forward;
+                while offset > 0 && literal.last() == Some(&base[offset - 1]) {
+                    literal.pop();
+                    offset -= 1;
+                    length += 1;
+                }
+                flush_insert(&mut out, &mut literal);
+                write_copy(&mut out, offset, length);
+                pos += forward;
+            }
+            None => {
+                literal.push(target[pos]);
+                pos += 1;
+            }
+        }
+        if out.len() + literal.len() > max_size {
+            return None;
+        }
+    }
+    flush_insert(&mut out, &mut literal);
+
+    (out.len() <= max_size).then_some(out)
+}
+
+/// Rebuild every object of a pack, in entry order.
+///
+/// `external` is asked for REF_DELTA bases that are not part of the pack
+/// (thin packs); it returns `None` when the object is unknown.
+pub fn resolve_pack<F>(entries: &[PackEntry], mut external: F) -> io::Result<Vec<ResolvedObject>>
+where
+    F: FnMut(&Hash) -> io::Result<Option<(ObjectKind, Vec<u8>)>>,
+{
+    let index_by_offset: HashMap<u64, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| (entry.offset, i))
+        .collect();
+    let mut resolved: Vec<Option<ResolvedObject>> = vec![None; entries.len()];
+    let mut index_by_hash: HashMap<Hash, usize> = HashMap::new();
+    let mut external_bases: HashMap<Hash, (ObjectKind, Vec<u8>)> = HashMap::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        if let PackEntryKind::Base(kind) = entry.kind {
+            let hash = compute_sha1(&with_header(kind, &entry.data));
+            index_by_hash.insert(hash, i);
+            resolved[i] = Some(ResolvedObject {
+                kind,
+                data: entry.data.clone(),
+                hash,
+            });
+        }
+    }
+
+    // REF_DELTA bases may appear after the delta itself, so keep making
+    // passes until every entry is resolved or no more progress is possible.
+    loop {
+        let mut progress = false;
+        let mut missing = None;
+
+        for i in 0..entries.len() {
+            if resolved[i].is_some() {
+                continue;
+            }
+
+            // Walk down the chain until we reach an object we already know
+            let mut chain = vec![i];
+            let mut visited = HashSet::from([i]);
+            let mut current = i;
+            let base = loop {
+                match &entries[current].kind {
+                    PackEntryKind::OfsDelta { base_offset } => {
+                        if *base_offset >= entries[current].offset {
+                            return Err(invalid("Delta base offset is not before the delta"));
+                        }
+                        let base_index = *index_by_offset
+                            .get(base_offset)
+                            .ok_or_else(|| invalid("Delta base offset is not an entry"))?;
+                        if let Some(object) = &resolved[base_index] {
+                            break Some((object.kind, object.data.clone()));
+                        }
+                        if !visited.insert(base_index) {
+                            return Err(invalid("Delta chain loops back on itself"));
+                        }
+                        if chain.len() >= MAX_DELTA_DEPTH {
+                            return Err(invalid("Delta chain is too long"));
+                        }
+                        chain.push(base_index);
+                        current = base_index;
+                    }
+                    PackEntryKind::RefDelta { base } => {
+                        if let Some(object) =
+                            index_by_hash.get(base).and_then(|&b| resolved[b].as_ref())
+                        {
+                            break Some((object.kind, object.data.clone()));
+                        }
+                        if !external_bases.contains_key(base) {
+                            if let Some(object) = external(base)? {
+                                external_bases.insert(*base, object);
+                            }
+                        }
+                        match external_bases.get(base) {
+                            Some((kind, data)) => break Some((*kind, data.clone())),
+                            None => {
+                                missing = Some(*base);
+                                break None;
+                            }
+                        }
+                    }
+                    PackEntryKind::Base(_) => unreachable!("base entries are resolved up front"),
+                }
+            };
+            let Some((kind, mut data)) = base else {
+                continue;
+            };
+
+            for &j in chain.iter().rev() {
+                data = apply_delta(&data, &entries[j].data)?;
+                let hash = compute_sha1(&with_header(kind, &data));
+                index_by_hash.insert(hash, j);
+                resolved[j] = Some(ResolvedObject {
+                    kind,
+                    data: data.clone(),
+                    hash,
+                });
+            }
+            progress = true;
+        }
+
+        if let Some(base) = missing {
+            if !progress {
+                return Err(invalid(&format!("Delta base {} is missing", base.to_hex())));
+            }
+        } else {
+            break;
+        }
+    }
+
+    Ok(resolved.into_iter().map(|object| object.unwrap()).collect())
+}
+
+/// Bytes of rebuilt delta bases kept per pack before the cache starts over.
+const DELTA_BASE_CACHE_LIMIT: usize = 16 << 20;
+
+/// Objects of one pack already rebuilt from their delta chains, by offset,
+/// so that objects sharing a base do not inflate and apply it again.
+#[derive(Debug, Default)]
+pub struct DeltaBaseCache {
+    objects: HashMap<u64, (ObjectKind, Vec<u8>)>,
+    size: usize,
+}
+
+impl DeltaBaseCache {
+    fn get(&self, offset: u64) -> Option<(ObjectKind, Vec<u8>)> {
+        self.objects.get(&offset).cloned()
+    }
+
+    fn insert(&mut self, offset: u64, kind: ObjectKind, data: &[u8]) {
+        if data.len() > DELTA_BASE_CACHE_LIMIT / 4 || self.objects.contains_key(&offset) {
+            return;
+        }
+        if self.size + data.len() > DELTA_BASE_CACHE_LIMIT {
+            self.objects.clear();
+            self.size = 0;
+        }
+        self.size += data.len();
+        self.objects.insert(offset, (kind, data.to_vec()));
+    }
+}
+
+/// Rebuild the object stored at `offset` in a pack, following its delta
+/// chain down to the base. `find` gives the offset of a REF_DELTA base that
+/// lives in the same pack; `external` provides the other bases by hash.
+/// Bases met on the way are looked up in and added to `cache`.
+pub fn read_packed_object<R, L, F>(
+    pack: &mut R,
+    offset: u64,
+    cache: &mut DeltaBaseCache,
+    mut find: L,
+    mut external: F,
+) -> io::Result<(ObjectKind, Vec<u8>)>
+where
+    R: BufRead + Seek,
+    L: FnMut(&Hash) -> Option<u64>,
+    F: FnMut(&Hash) -> io::Result<(ObjectKind, Vec<u8>)>,
+{
+    // Each delta with the offset of its entry
+    let mut deltas = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = offset;
+    let (kind, mut data) = loop {
+        if !visited.insert(current) {
+            return Err(invalid("Delta chain loops back on itself"));
+        }
+        if deltas.len() >= MAX_DELTA_DEPTH {
+            return Err(invalid("Delta chain is too long"));
+        }
+        if current != offset {
+            if let Some(object) = cache.get(current) {
+                break object;
+            }
+        }
+        pack.seek(SeekFrom::Start(current))?;
+        let entry = read_entry(pack, current)?;
+        match entry.kind {
+            PackEntryKind::Base(kind) => {
+                if current != offset {
+                    cache.insert(current, kind, &entry.data);
+                }
+                break (kind, entry.data);
+            }
+            PackEntryKind::OfsDelta { base_offset } => {
+                if base_offset >= current {
+                    return Err(invalid("Delta base offset is not before the delta"));
+                }
+                deltas.push((current, entry.data));
+                current = base_offset;
+            }
+            PackEntryKind::RefDelta { base } => {
+                deltas.push((current, entry.data));
+                match find(&base) {
+                    Some(base_offset) => current = base_offset,
+                    None => break external(&base)?,
+                }
+            }
+        }
+    };
+
+    for (entry_offset, delta) in deltas.iter().rev() {
+        data = apply_delta(&data, delta)?;
+        if *entry_offset != offset {
+            cache.insert(*entry_offset, kind, &data);
+        }
+    }
+    Ok((kind, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob_entry(offset: u64, kind: PackEntryKind, data: &[u8]) -> PackEntry {
+        PackEntry {
+            offset,
+            packed_size: 0,
+            kind,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn applies_copy_and_insert_instructions() {
+        // base 11 bytes, target 11 bytes: copy "hello " then insert "there"
+        let delta = b"\x0b\x0b\x90\x06\x05there";
+        assert_eq!(apply_delta(b"hello world", delta).unwrap(), b"hello there");
+    }
+
+    #[test]
+    fn copy_size_zero_means_64k() {
+        let base = vec![7u8; 0x10000];
+        let delta = b"\x80\x80\x04\x80\x80\x04\x80";
+        assert_eq!(apply_delta(&base, delta).unwrap(), base);
+    }
+
+    #[test]
+    fn rejects_mismatched_sizes() {
+        assert!(apply_delta(b"short", b"\x0b\x0b\x90\x06\x05there").is_err());
+        // Claims a 12-byte target but only produces 11 bytes
+        assert!(apply_delta(b"hello world", b"\x0b\x0c\x90\x06\x05there").is_err());
+        // Copy past the end of the base
+        assert!(apply_delta(b"hello world", b"\x0b\x0c\x90\x0c").is_err());
+        assert!(apply_delta(b"hello world", b"\x0b\x05\x03ab").is_err());
+    }
+
+    #[test]
+    fn created_deltas_apply_back_to_the_target() {
+        let base: Vec<u8> = (0..300)
+            .flat_map(|i| format!("line {}\n", i).into_bytes())
+            .collect();
+        let mut target = base.clone();
+        target.splice(1000..1010, b"something else entirely".iter().copied());
+        target.extend_from_slice(b"trailer\n");
+
+        let delta = create_delta(&base, &target, usize::MAX).unwrap();
+        assert!(delta.len() < target.len() / 10);
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+
+        assert!(create_delta(&base, &target, 4).is_none());
+        let unrelated = vec![0xffu8; 100];
+        let delta = create_delta(&base, &unrelated, usize::MAX).unwrap();
+        assert_eq!(apply_delta(&base, &delta).unwrap(), unrelated);
+    }
+
+    #[test]
+    fn resolves_offset_deltas_against_earlier_entries() {
+        let delta = b"\x0b\x0b\x90\x06\x05there";
+        let entries = [
+            blob_entry(12, PackEntryKind::Base(ObjectKind::Blob), b"hello world"),
+            blob_entry(40, PackEntryKind::OfsDelta { base_offset: 12 }, delta),
+        ];
+        let objects = resolve_pack(&entries, |_| Ok(None)).unwrap();
+        assert_eq!(objects[1].kind, ObjectKind::Blob);
+        assert_eq!(objects[1].data, b"hello there");
+        assert_eq!(
+            objects[1].hash,
+            compute_sha1(&with_header(ObjectKind::Blob, b"hello there"))
+        );
+    }
+
+    #[test]
+    fn rejects_deltas_based_on_themselves() {
+        let delta = b"\x0b\x0b\x90\x06\x05there";
+        let entries = [
+            blob_entry(12, PackEntryKind::Base(ObjectKind::Blob), b"hello world"),
+            blob_entry(40, PackEntryKind::OfsDelta { base_offset: 40 }, delta),
+        ];
+        let error = resolve_pack(&entries, |_| Ok(None)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let first = Hash([1u8; 20]);
+        let second = Hash([2u8; 20]);
+        let entries = [
+            blob_entry(12, PackEntryKind::RefDelta { base: second }, delta),
+            blob_entry(40, PackEntryKind::RefDelta { base: first }, delta),
+        ];
+        assert!(resolve_pack(&entries, |_| Ok(None)).is_err());
+    }
+
+    #[test]
+    fn packed_reads_reuse_cached_bases() {
+        use crate::packfile::{pack_objects, parse_pack, DEFAULT_DEPTH, DEFAULT_WINDOW};
+
+        let mut text: Vec<u8> = (0..300)
+            .flat_map(|i| format!("line {}\n", i).into_bytes())
+            .collect();
+        let mut blobs = Vec::new();
+        for i in 0..4 {
+            text.extend_from_slice(format!("version {}\n", i).as_bytes());
+            let hash = compute_sha1(&with_header(ObjectKind::Blob, &text));
+            blobs.push((hash, text.clone()));
+        }
+        let names: Vec<(Hash, String)> = blobs
+            .iter()
+            .map(|(hash, _)| (*hash, "file".to_string()))
+            .collect();
+        let pack = pack_objects(&names, DEFAULT_WINDOW, DEFAULT_DEPTH, true, |hash| {
+            let (_, data) = blobs.iter().find(|(h, _)| h == hash).unwrap();
+            Ok((ObjectKind::Blob, data.clone()))
+        })
+        .unwrap();
+        let entries = parse_pack(&pack).unwrap().entries;
+        let objects = resolve_pack(&entries, |_| Ok(None)).unwrap();
+        assert!(entries
+            .iter()
+            .any(|entry| matches!(entry.kind, PackEntryKind::OfsDelta { .. })));
+
+        let mut cache = DeltaBaseCache::default();
+        let mut reader = io::Cursor::new(&pack[..]);
+        for _ in 0..2 {
+            for (entry, object) in entries.iter().zip(&objects) {
+                let read = read_packed_object(
+                    &mut reader,
+                    entry.offset,
+                    &mut cache,
+                    |_| None,
+                    |_| unreachable!(),
+                )
+                .unwrap();
+                assert_eq!(read, (object.kind, object.data.clone()));
+            }
+        }
+        assert!(!cache.objects.is_empty());
+    }
+}
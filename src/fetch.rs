@@ -0,0 +1,251 @@
+use std::collections::{HashSet, VecDeque};
+use std::io;
+
+use crate::objects::Object;
+use crate::refs::{list_refs, read_ref, read_symbolic_ref, shorten_ref, write_ref};
+use crate::refspec::Refspec;
+use crate::repository::Repository;
+use crate::transport;
+use crate::{has_object, is_ancestor, read_object, remote_url, short_hash, store_pack, Hash};
+
+/*
+fetch asks the remote for the refs its refspecs can match, downloads a pack
+of whatever the wanted tips need that we lack, then moves the local refs:
+
+- a new ref is created
+- an existing ref is fast-forwarded, or with `+` on the refspec forced
+- tags are never moved unless forced, and anything else is rejected
+
+The pack is stored before any ref moves, so a fetch that fails part way
+never leaves a ref pointing at objects we do not have.
+*/
+
+/// A remote ref selected by a refspec, and where to store it locally.
+struct RefUpdate {
+    src: String,
+    dst: Option<String>,
+    hash: Hash,
+    force: bool,
+}
+
+/// Fetch `cli_refspecs` (or the configured ones) from `remote`. The branch
+/// HEAD points at is only updated with `update_head_ok`, as its index and
+/// work tree would no longer match it.
+pub fn fetch(
+    repo: &Repository,
+    remote: &str,
+    cli_refspecs: &[&str],
+    update_head_ok: bool,
+) -> io::Result<()> {
+    let config = repo.config()?;
+    let url = remote_url(&config, remote)?;
+    let configured = config
+        .get_all(&format!("remote.{}.fetch", remote))
+        .into_iter()
+        .map(Refspec::parse)
+        .collect::<io::Result<Vec<_>>>()?;
+    let refspecs = if cli_refspecs.is_empty() {
+        configured.clone()
+    } else {
+        cli_refspecs
+            .iter()
+            .map(|spec| Refspec::parse(spec))
+            .collect::<io::Result<Vec<_>>>()?
+    };
+
+    // Only ask for the refs the refspecs can match
+    let mut prefixes = Vec::new();
+    for spec in &refspecs {
+        match spec.src.split_once('*') {
+            Some((prefix, _)) => prefixes.push(prefix.to_string()),
+            None if spec.src.starts_with("refs/") || spec.src == "HEAD" => {
+                prefixes.push(spec.src.clone())
+            }
+            None => {
+                for prefix in ["", "refs/tags/", "refs/heads/"] {
+                    prefixes.push(format!("{}{}", prefix, spec.src));
+                }
+            }
+        }
+    }
+    let prefixes: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+    let advertisement = transport::discover_refs(&url, &prefixes)?;
+
+    // Work out which remote refs we want and where they go
+    let mut updates = Vec::new();
+    for spec in &refspecs {
+        if spec.is_glob() {
+            for remote_ref in &advertisement.refs {
+                if let Some(dst) = spec.map_to_dst(&remote_ref.name) {
+                    updates.push(RefUpdate {
+                        src: remote_ref.name.clone(),
+                        dst: Some(dst),
+                        hash: remote_ref.hash,
+                        force: spec.force,
+                    });
+                }
+            }
+            continue;
+        }
+
+        // Short names are looked up the way git does: as-is, as a tag, then as a branch
+        let remote_ref = ["", "refs/tags/", "refs/heads/"]
+            .iter()
+            .find_map(|prefix| {
+                let name = format!("{}{}", prefix, spec.src);
+                advertisement.refs.iter().find(|r| r.name == name)
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("couldn't find remote ref {}", spec.src),
+                )
+            })?;
+        let (dst, force) = match &spec.dst {
+            Some(dst) if dst.starts_with("refs/") => (Some(dst.clone()), spec.force),
+            Some(dst) => (Some(format!("refs/heads/{}", dst)), spec.force),
+            // Without a destination, update the remote-tracking ref if one is configured
+            None => match configured.iter().find(|c| c.matches_src(&remote_ref.name)) {
+                Some(c) => (c.map_to_dst(&remote_ref.name), c.force),
+                None => (None, false),
+            },
+        };
+        updates.push(RefUpdate {
+            src: remote_ref.name.clone(),
+            dst,
+            hash: remote_ref.hash,
+            force,
+        });
+    }
+
+    if !update_head_ok {
+        if let (Ok(work_tree), Some(head)) = (repo.work_tree(), read_symbolic_ref(repo, "HEAD")?) {
+            let checked_out = updates
+                .iter()
+                .any(|update| update.dst.as_ref() == Some(&head));
+            if checked_out && read_ref(repo, &head)?.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "refusing to fetch into branch '{}' checked out at '{}'",
+                        head,
+                        work_tree.display()
+                    ),
+                ));
+            }
+        }
+    }
+
+    let mut wants: Vec<Hash> = updates
+        .iter()
+        .map(|update| update.hash)
+        .filter(|hash| !has_object(repo, hash))
+        .collect();
+    wants.sort();
+    wants.dedup();
+    if !wants.is_empty() {
+        let haves = local_haves(repo, 256)?;
+        let pack_data = transport::fetch_pack(&url, &advertisement, &wants, &haves)?;
+        if !pack_data.is_empty() {
+            store_pack(repo, &pack_data)?;
+        }
+    }
+
+    // Update the local refs, reporting each change like git does
+    let mut rejected = false;
+    let mut report = Vec::new();
+    for update in &updates {
+        let Some(dst) = &update.dst else {
+            continue;
+        };
+        let old = read_ref(repo, dst)?;
+        let short_src = shorten_ref(&update.src);
+        let short_dst = shorten_ref(dst);
+        let (summary, note) = match old {
+            Some(old) if old == update.hash => continue,
+            None => {
+                let kind = if dst.starts_with("refs/tags/") {
+                    "[new tag]"
+                } else {
+                    "[new branch]"
+                };
+                (format!(" * {:<17}", kind), "")
+            }
+            Some(old)
+                if !dst.starts_with("refs/tags/") && is_ancestor(repo, &old, &update.hash)? =>
+            {
+                (
+                    format!(
+                        "   {:<17}",
+                        format!("{}..{}", short_hash(&old), short_hash(&update.hash))
+                    ),
+                    "",
+                )
+            }
+            Some(old) if update.force => (
+                format!(
+                    " + {:<17}",
+                    format!("{}...{}", short_hash(&old), short_hash(&update.hash))
+                ),
+                "  (forced update)",
+            ),
+            Some(_) => {
+                rejected = true;
+                report.push(format!(
+                    " ! {:<17} {:<10} -> {}  (non-fast-forward)",
+                    "[rejected]", short_src, short_dst
+                ));
+                continue;
+            }
+        };
+        write_ref(repo, dst, &update.hash)?;
+        report.push(format!(
+            "{} {:<10} -> {}{}",
+            summary, short_src, short_dst, note
+        ));
+    }
+
+    if !report.is_empty() {
+        eprintln!("From {}", url);
+        for line in report {
+            eprintln!("{}", line);
+        }
+    }
+    if rejected {
+        return Err(io::Error::other("some local refs could not be updated"));
+    }
+    Ok(())
+}
+
+/// Recent local commits, newest refs first, to advertise as `have` lines.
+fn local_haves(repo: &Repository, limit: usize) -> io::Result<Vec<Hash>> {
+    let mut queue: VecDeque<Hash> = VecDeque::new();
+    for prefix in ["refs/heads/", "refs/remotes/", "refs/tags/"] {
+        for (_, hash) in list_refs(repo, prefix)? {
+            queue.push_back(hash);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut haves = Vec::new();
+    while let Some(hash) = queue.pop_front() {
+        if haves.len() >= limit {
+            break;
+        }
+        if !seen.insert(hash) {
+            continue;
+        }
+        match read_object(repo, &hash) {
+            Ok(Object::Commit(commit)) => {
+                haves.push(hash);
+                queue.extend(commit.parents);
+            }
+            Ok(Object::Tag(tag)) => queue.push_back(tag.object),
+            Ok(_) => {}
+            // Shallow or broken history: stop walking this branch
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(haves)
+}
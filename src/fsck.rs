@@ -0,0 +1,494 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+
+use crate::delta::{self, DeltaBaseCache};
+use crate::index::Index;
+use crate::object_headers::GitObjectHeader;
+use crate::objects::{tree_order, verify_path, with_header, BadPath, Object, ObjectKind};
+use crate::repository::Repository;
+use crate::{compute_sha1, GitTreeEntry, Hash};
+
+/*
+fsck walks the whole object store in three passes:
+
+1. every loose and packed object is inflated, hashed and parsed; objects that
+   fail are corrupt, objects that parse but break git's rules are reported
+   with the rule they break
+2. every link (tree entry, commit tree and parents, tag target) must point at
+   an object of the right type
+3. objects not reachable from any ref or from the index that nothing else
+   points at are dangling
+
+Problems found in 1 and 2 make the command fail; dangling objects are only
+reported. Only the type and links of each object are kept between passes,
+so blob contents never pile up in memory.
+*/
+
+const TREE_MODES: [&str; 5] = ["100644", "100755", "120000", "40000", "160000"];
+
+/// Problems found in a tree's entries.
+fn check_tree(entries: &[GitTreeEntry]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for entry in entries {
+        let name = entry.name.to_string_lossy();
+        if !TREE_MODES.contains(&entry.mode.as_str()) {
+            problems.push(format!("badMode: '{}' has mode {}", name, entry.mode));
+        }
+        match verify_path(entry.name.as_bytes()) {
+            Err(BadPath::EmptyName) => {
+                problems.push("emptyName: contains empty pathname".to_string())
+            }
+            Err(BadPath::HasDot) => problems.push(format!("hasDot: contains '{}'", name)),
+            Err(BadPath::HasDotgit) => problems.push("hasDotgit: contains '.git'".to_string()),
+            Err(BadPath::FullPathname) => {
+                problems.push(format!("fullPathname: contains '{}'", name))
+            }
+            Ok(()) => {}
+        }
+    }
+    for pair in entries.windows(2) {
+        if pair[0].name == pair[1].name {
+            problems.push(format!(
+                "duplicateEntries: '{}' appears twice",
+                pair[0].name.to_string_lossy()
+            ));
+        } else if tree_order(&pair[0], &pair[1]) != Ordering::Less {
+            problems.push("treeNotSorted: not properly sorted".to_string());
+        }
+    }
+    problems
+}
+
+/// Check an identity line such as `A U Thor <author@example.com> 1700000000 +0100`.
+fn check_ident(header: &str, ident: &[u8]) -> Option<String> {
+    // Names may be in any encoding; only the ASCII structure is checked
+    let ident = String::from_utf8_lossy(ident);
+    let bad = |what: &str| Some(format!("bad{}: invalid {} line", what, header));
+    let Some((name, rest)) = ident.split_once('<') else {
+        return bad("Email");
+    };
+    if name.contains('>') || (!name.is_empty() && !name.ends_with(' ')) {
+        return bad("Name");
+    }
+    let Some((email, date)) = rest.split_once("> ") else {
+        return bad("Email");
+    };
+    if email.contains('<') || email.contains('>') {
+        return bad("Email");
+    }
+    let Some((timestamp, zone)) = date.split_once(' ') else {
+        return bad("Date");
+    };
+    let zone_ok = zone.len() == 5
+        && (zone.starts_with('+') || zone.starts_with('-'))
+        && zone[1..].bytes().all(|b| b.is_ascii_digit());
+    if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) || !zone_ok {
+        return bad("Date");
+    }
+    None
+}
+
+/// Problems in the header block of a commit or tag. `expected` lists the
+/// headers that must come first, in order; `optional` may follow them.
+fn check_headers(body: &[u8], expected: &[&str], optional: &[&str]) -> Vec<String> {
+    let text = String::from_utf8_lossy(body);
+    let keys: Vec<&str> = text
+        .split('\n')
+        .take_while(|line| !line.is_empty())
+        .filter(|line| !line.starts_with(' '))
+        .map(|line| line.split(' ').next().unwrap_or(""))
+        .collect();
+
+    let mut problems = Vec::new();
+    let mut keys = keys.into_iter().peekable();
+    for header in expected {
+        // Any number of parents sit between tree and author
+        if *header == "parent" {
+            while keys.next_if_eq(&"parent").is_some() {}
+            continue;
+        }
+        if keys.next() != Some(*header) {
+            problems.push(format!(
+                "missing{}: {} line missing or out of order",
+                header, header
+            ));
+            return problems;
+        }
+    }
+    for header in optional {
+        keys.next_if_eq(header);
+    }
+    problems
+}
+
+/// Inflate, hash and parse one object, reporting what is wrong with it.
+fn check_object(hash: &Hash, data: &[u8]) -> Result<(Object, Vec<String>), String> {
+    let computed = compute_sha1(data);
+    if computed != *hash {
+        return Err(format!("hash mismatch (computed {})", computed.to_hex()));
+    }
+    let (header, body) = GitObjectHeader::from_bytes(data).map_err(|e| e.to_string())?;
+    if header.size != body.len() {
+        return Err(format!(
+            "size mismatch: header says {}, object has {}",
+            header.size,
+            body.len()
+        ));
+    }
+    let object = Object::parse(data).map_err(|e| e.to_string())?;
+
+    let mut problems = match &object {
+        Object::Blob(_) => Vec::new(),
+        Object::Tree(entries) => check_tree(entries),
+        Object::Commit(_) => check_headers(body, &["tree", "parent", "author", "committer"], &[]),
+        Object::Tag(_) => check_headers(body, &["object", "type", "tag"], &["tagger"]),
+    };
+    match &object {
+        Object::Commit(commit) => {
+            problems.extend(check_ident("author", &commit.author));
+            problems.extend(check_ident("committer", &commit.committer));
+        }
+        Object::Tag(tag) => {
+            if let Some(tagger) = &tag.tagger {
+                problems.extend(check_ident("tagger", tagger));
+            }
+        }
+        _ => {}
+    }
+    Ok((object, problems))
+}
+
+/// The objects `object` points at, with the type each must have.
+fn links(object: &Object) -> Vec<(Hash, ObjectKind)> {
+    match object {
+        Object::Blob(_) => Vec::new(),
+        Object::Tree(entries) => entries
+            .iter()
+            .filter_map(|entry| match entry.mode.as_str() {
+                "40000" => Some((entry.hash, ObjectKind::Tree)),
+                // Submodule commits live in another repository
+                "160000" => None,
+                _ => Some((entry.hash, ObjectKind::Blob)),
+            })
+            .collect(),
+        Object::Commit(commit) => std::iter::once((commit.tree, ObjectKind::Tree))
+            .chain(
+                commit
+                    .parents
+                    .iter()
+                    .map(|parent| (*parent, ObjectKind::Commit)),
+            )
+            .collect(),
+        Object::Tag(tag) => vec![(tag.object, tag.kind)],
+    }
+}
+
+/// The type of an object and the objects it points at.
+type Node = (ObjectKind, Vec<(Hash, ObjectKind)>);
+
+/// Print what `check_object` found and keep the object's node if it parsed,
+/// returning the number of problems.
+fn record(
+    objects: &mut HashMap<Hash, Node>,
+    hash: &Hash,
+    storage: &str,
+    result: Result<(Object, Vec<String>), String>,
+) -> usize {
+    match result {
+        Ok((object, problems)) => {
+            for problem in &problems {
+                eprintln!("error in {} {}: {}", object.kind(), hash.to_hex(), problem);
+            }
+            objects.insert(*hash, (object.kind(), links(&object)));
+            problems.len()
+        }
+        Err(message) => {
+            eprintln!(
+                "error: {} object {} is corrupt: {}",
+                storage,
+                hash.to_hex(),
+                message
+            );
+            1
+        }
+    }
+}
+
+fn inflate(path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(fs::File::open(path)?).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Verify the object store, printing what is wrong. Fails if any object is
+/// corrupt or missing, or a ref points nowhere.
+pub fn fsck(repo: &Repository) -> io::Result<()> {
+    let mut objects: HashMap<Hash, Node> = HashMap::new();
+    let mut errors = 0;
+    for (hash, path) in crate::loose_objects(repo)? {
+        let result = inflate(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| check_object(&hash, &data));
+        errors += record(&mut objects, &hash, "loose", result);
+    }
+
+    for pack_file in repo.packs()?.iter() {
+        let (index, pack_path) = (&pack_file.index, &pack_file.path);
+        let pack_data = fs::read(pack_path)?;
+        let intact = pack_data.len() >= 32 && {
+            let (body, trailer) = pack_data.split_at(pack_data.len() - 20);
+            compute_sha1(body).as_bytes() == trailer
+        };
+        if !intact {
+            eprintln!("error: {}: pack checksum mismatch", pack_path.display());
+            errors += 1;
+        }
+
+        let mut pack = io::Cursor::new(pack_data.as_slice());
+        let mut bases = DeltaBaseCache::default();
+        for (hash, offset, _) in index.entries() {
+            let result = delta::read_packed_object(
+                &mut pack,
+                offset,
+                &mut bases,
+                |base| index.find(base),
+                |base| crate::read_raw_object(repo, base),
+            )
+            .map_err(|e| e.to_string())
+            .and_then(|(kind, body)| check_object(&hash, &with_header(kind, &body)));
+            errors += record(&mut objects, &hash, "packed", result);
+        }
+    }
+
+    // Every link must point at an object of the right type
+    let mut referenced = HashSet::new();
+    let mut missing = HashSet::new();
+    let mut sorted: Vec<(&Hash, &Node)> = objects.iter().collect();
+    sorted.sort_by_key(|(hash, _)| **hash);
+    for (hash, (object_kind, links)) in &sorted {
+        for &(target, kind) in links {
+            referenced.insert(target);
+            let found = objects.get(&target).map(|(kind, _)| *kind);
+            if found == Some(kind) {
+                continue;
+            }
+            errors += 1;
+            match found {
+                Some(found) => eprintln!(
+                    "error: {} {} points at {} {} as a {}",
+                    object_kind,
+                    hash.to_hex(),
+                    found,
+                    target.to_hex(),
+                    kind
+                ),
+                None => {
+                    println!("broken link from {:>7} {}", object_kind, hash.to_hex());
+                    println!("              to {:>7} {}", kind, target.to_hex());
+                    if missing.insert(target) {
+                        println!("missing {} {}", kind, target.to_hex());
+                    }
+                }
+            }
+        }
+    }
+
+    // Refs and what the index stages are the roots of reachability
+    let mut tips = Vec::new();
+    let mut refs = crate::refs::list_refs(repo, "refs/")?;
+    refs.extend(crate::refs::read_ref(repo, "HEAD")?.map(|hash| ("HEAD".to_string(), hash)));
+    for (name, hash) in refs {
+        if objects.contains_key(&hash) {
+            tips.push(hash);
+        } else {
+            eprintln!("error: {}: invalid sha1 pointer {}", name, hash.to_hex());
+            errors += 1;
+        }
+    }
+    let index = Index::load(&repo.path("index"))?;
+    let staged = index
+        .entries()
+        .iter()
+        // Submodule commits live in another repository, and intent-to-add
+        // entries have no object yet
+        .filter(|entry| entry.mode != 0o160000 && !entry.is_intent_to_add())
+        .map(|entry| (entry.hash, "index"));
+    let cached = index
+        .cached_trees()?
+        .into_iter()
+        .map(|hash| (hash, "cache tree"));
+    for (hash, source) in staged.chain(cached) {
+        if objects.contains_key(&hash) {
+            tips.push(hash);
+        } else {
+            eprintln!(
+                "error: {}: invalid sha1 pointer in {}",
+                hash.to_hex(),
+                source
+            );
+            errors += 1;
+        }
+    }
+
+    let mut reachable = HashSet::new();
+    let mut queue: VecDeque<Hash> = tips.into_iter().collect();
+    while let Some(hash) = queue.pop_front() {
+        if !reachable.insert(hash) {
+            continue;
+        }
+        if let Some((_, links)) = objects.get(&hash) {
+            queue.extend(links.iter().map(|(target, _)| *target));
+        }
+    }
+    for (hash, (kind, _)) in &sorted {
+        if !reachable.contains(*hash) && !referenced.contains(*hash) {
+            println!("dangling {} {}", kind, hash.to_hex());
+        }
+    }
+
+    if errors > 0 {
+        return Err(io::Error::other(format!(
+            "fsck found {} problem(s)",
+            errors
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(mode: &str, name: &str) -> GitTreeEntry {
+        GitTreeEntry {
+            mode: mode.to_string(),
+            name: name.into(),
+            hash: Hash([1u8; 20]),
+        }
+    }
+
+    /// The rule names in `problems`, the part before the colon.
+    fn rules(problems: &[String]) -> Vec<&str> {
+        problems
+            .iter()
+            .map(|problem| problem.split(':').next().unwrap())
+            .collect()
+    }
+
+    /// Tree entries as (mode, name) pairs, and the rules they break.
+    type TreeCase<'a> = (&'a [(&'a str, &'a str)], &'a [&'a str]);
+
+    #[test]
+    fn tree_entries_follow_git_rules() {
+        let cases: &[TreeCase] = &[
+            (&[("100644", "a.c"), ("40000", "a"), ("100644", "b")], &[]),
+            (&[("120000", "link"), ("160000", "sub")], &[]),
+            (&[("100664", "file")], &["badMode"]),
+            (&[("40755", "dir")], &["badMode"]),
+            (&[("100644", ".")], &["hasDot"]),
+            (&[("40000", "..")], &["hasDot"]),
+            (&[("40000", ".git")], &["hasDotgit"]),
+            (&[("40000", ".GiT")], &["hasDotgit"]),
+            (&[("100644", "")], &["emptyName"]),
+            (&[("100644", "a/b")], &["fullPathname"]),
+            (&[("100644", "a"), ("100644", "a")], &["duplicateEntries"]),
+            (&[("100644", "b"), ("100644", "a")], &["treeNotSorted"]),
+            // Directories sort as if their name ended in '/'
+            (&[("40000", "a"), ("100644", "a.c")], &["treeNotSorted"]),
+        ];
+        for (entries, expected) in cases {
+            let entries: Vec<GitTreeEntry> = entries
+                .iter()
+                .map(|(mode, name)| entry(mode, name))
+                .collect();
+            let problems = check_tree(&entries);
+            assert_eq!(rules(&problems), *expected, "{:?}", problems);
+        }
+    }
+
+    #[test]
+    fn ident_lines_follow_git_rules() {
+        let good = [
+            "A U Thor <author@example.com> 1700000000 +0100",
+            "A U Thor <> 0 -0000",
+            "<author@example.com> 1700000000 +0000",
+        ];
+        for ident in good {
+            assert_eq!(check_ident("author", ident.as_bytes()), None, "{}", ident);
+        }
+
+        let bad = [
+            ("A U Thor author@example.com 1700000000 +0100", "badEmail"),
+            ("A U Thor <author@example.com 1700000000 +0100", "badEmail"),
+            ("A U Thor <a<b@example.com> 1700000000 +0100", "badEmail"),
+            ("A U Thor<author@example.com> 1700000000 +0100", "badName"),
+            ("A > Thor <author@example.com> 1700000000 +0100", "badName"),
+            ("A U Thor <author@example.com> 1700000000", "badDate"),
+            ("A U Thor <author@example.com>  +0100", "badDate"),
+            ("A U Thor <author@example.com> 17e8 +0100", "badDate"),
+            ("A U Thor <author@example.com> 1700000000 0100", "badDate"),
+            ("A U Thor <author@example.com> 1700000000 +01:00", "badDate"),
+        ];
+        for (ident, rule) in bad {
+            assert_eq!(
+                check_ident("author", ident.as_bytes()),
+                Some(format!("{}: invalid author line", rule)),
+                "{}",
+                ident
+            );
+        }
+    }
+
+    #[test]
+    fn headers_come_in_git_order() {
+        let commit = ["tree", "parent", "author", "committer"];
+        let tree = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n";
+        let parent = "parent 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n";
+        let author = "author A <a@example.com> 0 +0000\n";
+        let committer = "committer A <a@example.com> 0 +0000\n";
+        let cases = [
+            (format!("{}{}{}\nmessage\n", tree, author, committer), None),
+            (
+                format!("{}{}{}{}{}\n", tree, parent, parent, author, committer),
+                None,
+            ),
+            // Continuation lines and later headers are not checked
+            (
+                format!("{}{}{}gpgsig a\n b\n\n", tree, author, committer),
+                None,
+            ),
+            (format!("{}{}\n", author, committer), Some("missingtree")),
+            (format!("{}{}\n", tree, committer), Some("missingauthor")),
+            (
+                format!("{}{}{}\n", tree, committer, author),
+                Some("missingauthor"),
+            ),
+            (format!("{}{}\n", tree, author), Some("missingcommitter")),
+            // A header after the blank line is part of the message
+            (
+                format!("{}{}\n{}", tree, author, committer),
+                Some("missingcommitter"),
+            ),
+        ];
+        for (body, expected) in &cases {
+            let problems = check_headers(body.as_bytes(), &commit, &[]);
+            let expected: Vec<&str> = expected.iter().copied().collect();
+            assert_eq!(rules(&problems), expected, "{}", body);
+        }
+
+        let tag = ["object", "type", "tag"];
+        let head = "object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype tree\ntag v1\n";
+        let tagger = "tagger A <a@example.com> 0 +0000\n";
+        for body in [format!("{}\n", head), format!("{}{}\n", head, tagger)] {
+            assert!(check_headers(body.as_bytes(), &tag, &["tagger"]).is_empty());
+        }
+        let problems = check_headers(b"type tree\ntag v1\n\n", &tag, &["tagger"]);
+        assert_eq!(rules(&problems), ["missingobject"]);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use crate::index::Index;
+use crate::objects::with_header;
+use crate::packfile;
+use crate::refs::{list_refs, pack_refs, read_ref};
+use crate::repository::Repository;
+use crate::{
+    has_loose_object, list_objects, loose_objects, read_raw_object, store_pack, write_object, Hash,
+};
+
+/*
+Housekeeping for the object store. repack writes the reachable objects into
+a new pack, gc packs refs and repacks everything before pruning unreachable
+loose objects older than the expiry, and prune_packed drops loose objects a
+pack already holds.
+*/
+
+/// Tips every object worth keeping hangs off: all refs, a detached `HEAD`,
+/// and whatever is staged in the index, including its cached trees.
+fn reachable_tips(repo: &Repository) -> io::Result<Vec<Hash>> {
+    let mut tips: Vec<Hash> = list_refs(repo, "refs/")?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+    tips.extend(read_ref(repo, "HEAD")?);
+    let index = Index::load(&repo.path("index"))?;
+    tips.extend(
+        index
+            .entries()
+            .iter()
+            // Submodule commits live in another repository, and intent-to-add
+            // entries have no object yet
+            .filter(|entry| entry.mode != 0o160000 && !entry.is_intent_to_add())
+            .map(|entry| entry.hash),
+    );
+    tips.extend(index.cached_trees()?);
+    tips.sort();
+    tips.dedup();
+    Ok(tips)
+}
+
+/// Pack reachable objects into a new pack.
+///
+/// With `all`, every reachable object goes in, so the pack replaces all
+/// existing ones; otherwise only loose objects are packed. With `delete`,
+/// packs made redundant and loose objects now packed are removed. Objects
+/// only those packs held are unreachable, and are kept as loose objects
+/// dated like their pack so they get the usual grace period before pruning.
+pub fn repack(repo: &Repository, all: bool, delete: bool) -> io::Result<()> {
+    let loose: HashSet<Hash> = loose_objects(repo)?
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect();
+    let mut objects = list_objects(repo, &reachable_tips(repo)?, &[])?;
+    if !all {
+        objects.retain(|(hash, _)| loose.contains(hash));
+    }
+    if objects.is_empty() {
+        eprintln!("Nothing new to pack.");
+        return Ok(());
+    }
+
+    let pack = packfile::pack_objects(
+        &objects,
+        packfile::DEFAULT_WINDOW,
+        packfile::DEFAULT_DEPTH,
+        true,
+        |hash| read_raw_object(repo, hash),
+    )?;
+    let checksum = store_pack(repo, &pack)?;
+    if !delete {
+        return Ok(());
+    }
+
+    let pack_dir = repo.pack_dir();
+    let new_pack = format!("pack-{}", checksum.to_hex());
+    if all {
+        let kept: HashSet<Hash> = objects.iter().map(|(hash, _)| *hash).collect();
+        for pack in repo.packs()?.iter() {
+            if pack
+                .path
+                .file_stem()
+                .is_some_and(|stem| stem == new_pack.as_str())
+            {
+                continue;
+            }
+            let modified = fs::metadata(&pack.path)?.modified()?;
+            for (hash, _, _) in pack.index.entries() {
+                if kept.contains(&hash) || has_loose_object(repo, &hash) {
+                    continue;
+                }
+                let (kind, body) = read_raw_object(repo, &hash)?;
+                let path = write_object(repo, &hash, &with_header(kind, &body))?;
+                fs::File::options()
+                    .write(true)
+                    .open(path)?
+                    .set_modified(modified)?;
+            }
+        }
+
+        for entry in fs::read_dir(&pack_dir)? {
+            let path = entry?.path();
+            let redundant = path
+                .file_stem()
+                .is_some_and(|stem| stem != new_pack.as_str())
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext == "pack" || ext == "idx");
+            if redundant {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+    prune_packed(repo)
+}
+
+/// Remove loose objects that a pack also holds.
+fn prune_packed(repo: &Repository) -> io::Result<()> {
+    let packs = repo.reload_packs()?;
+    for (hash, path) in loose_objects(repo)? {
+        if packs.iter().any(|pack| pack.index.find(&hash).is_some()) {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse an expiry such as `now`, `never` or `2.weeks.ago` into the time
+/// before which unreachable objects may go; `None` keeps them forever.
+pub fn parse_expiry(expiry: &str) -> io::Result<Option<SystemTime>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid expiry '{}'", expiry),
+        )
+    };
+    match expiry {
+        "never" => return Ok(None),
+        "now" => return Ok(Some(SystemTime::now())),
+        _ => {}
+    }
+
+    let mut parts = expiry.split('.');
+    let (Some(count), Some(unit), Some("ago"), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let seconds = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    Ok(SystemTime::now().checked_sub(Duration::from_secs(count * seconds)))
+}
+
+/// Pack refs, repack everything and drop unreachable loose objects last
+/// modified before `prune_before`.
+pub fn gc(repo: &Repository, prune_before: Option<SystemTime>) -> io::Result<()> {
+    pack_refs(repo)?;
+    repack(repo, true, true)?;
+
+    let Some(prune_before) = prune_before else {
+        return Ok(());
+    };
+    // Whatever is still loose after repacking is unreachable
+    for (_, path) in loose_objects(repo)? {
+        if fs::metadata(&path)?.modified()? < prune_before {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::is_not_a_directory;
+use crate::repository::Repository;
+
+/*
+Ignore rules come from, most important first:
+
+1. `.gitignore` files, a deeper one beating those of its parent directories
+2. `.git/info/exclude`
+3. the file named by `core.excludesFile`, by default `~/.config/git/ignore`
+
+Within one file the last matching line wins. A line is a pattern:
+
+- `#` starts a comment, `!` negates (re-includes), `\` escapes either
+- a trailing `/` only matches directories
+- a pattern containing any other `/` is anchored to the directory holding
+  the file; one without matches a name at any depth below it
+- `*` and `?` never match `/`; `**` between slashes matches any number of
+  directories, and `[...]` is a character class
+
+Nothing below an ignored directory can be re-included, since git never
+looks inside it.
+*/
+
+/// One line of an ignore file.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    /// The line as written, e.g. `!/build/`.
+    pub text: String,
+    /// Where the pattern comes from, e.g. `src/.gitignore`.
+    pub source: String,
+    pub line: usize,
+    pub negated: bool,
+    glob: String,
+    dir_only: bool,
+    anchored: bool,
+    /// Directory of the ignore file relative to the work tree, empty or
+    /// ending in `/`.
+    base: String,
+}
+
+pub struct Ignore {
+    work_tree: PathBuf,
+    /// `.git/info/exclude` followed by `core.excludesFile`.
+    exclude: Vec<Vec<Pattern>>,
+    /// `.gitignore` patterns by directory, read as directories are visited.
+    per_directory: HashMap<String, Vec<Pattern>>,
+}
+
+fn parse_patterns(text: &str, source: &str, base: &str) -> Vec<Pattern> {
+    let mut patterns = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let mut line = line.strip_suffix('\r').unwrap_or(line);
+        // Trailing spaces are dropped unless escaped
+        let trimmed = line.trim_end_matches(' ');
+        line = if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+            &line[..trimmed.len() + 1]
+        } else {
+            trimmed
+        };
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let text = line.to_string();
+        let (negated, mut glob) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                line.strip_prefix('\\')
+                    .filter(|rest| rest.starts_with(['#', '!']))
+                    .unwrap_or(line),
+            ),
+        };
+        let dir_only = glob.ends_with('/');
+        glob = glob.trim_end_matches('/');
+        let anchored = glob.contains('/');
+        glob = glob.strip_prefix('/').unwrap_or(glob);
+        if glob.is_empty() {
+            continue;
+        }
+        patterns.push(Pattern {
+            text,
+            source: source.to_string(),
+            line: i + 1,
+            negated,
+            glob: glob.to_string(),
+            dir_only,
+            anchored,
+            base: base.to_string(),
+        });
+    }
+    patterns
+}
+
+/// Read the patterns of an ignore file, none if it does not exist.
+fn read_patterns(path: &Path, source: &str, base: &str) -> io::Result<Vec<Pattern>> {
+    match fs::read(path) {
+        Ok(data) => Ok(parse_patterns(
+            &String::from_utf8_lossy(&data),
+            source,
+            base,
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        // A directory where the file should be is not an ignore file
+        Err(e) if path.is_dir() || is_not_a_directory(&e) => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The default `core.excludesFile`.
+fn default_excludes_file() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(config_home) => Some(PathBuf::from(config_home).join("git/ignore")),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/git/ignore")),
+    }
+}
+
+impl Pattern {
+    /// Whether the pattern matches `path`, relative to the work tree.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Some(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        if self.anchored {
+            wildmatch(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// Match `text` against a glob where `*`, `?` and classes stop at `/` and
+/// `**` between slashes crosses them.
+fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match_from(pattern, text, true)
+}
+
+/// `wildmatch`, where `segment_start` says whether `pattern` begins right
+/// after a `/` (or at the very start).
+fn match_from(pattern: &[u8], text: &[u8], segment_start: bool) -> bool {
+    let Some((&first, rest)) = pattern.split_first() else {
+        return text.is_empty();
+    };
+    match first {
+        b'*' => {
+            let stars = pattern.iter().take_while(|&&c| c == b'*').count();
+            let after = &pattern[stars..];
+            let double = segment_start && stars == 2;
+            match after.first() {
+                // Trailing `**` matches everything that is left
+                None if double => true,
+                // `**/` matches no directory or any number of them
+                Some(b'/') if double => {
+                    let after = &after[1..];
+                    match_from(after, text, true)
+                        || text
+                            .iter()
+                            .enumerate()
+                            .any(|(i, &c)| c == b'/' && match_from(after, &text[i + 1..], true))
+                }
+                // Anywhere else stars are a single `*`
+                _ => star(after, text),
+            }
+        }
+        b'?' => {
+            matches!(text.first(), Some(&c) if c != b'/') && match_from(rest, &text[1..], false)
+        }
+        b'[' => match (text.first(), match_class(rest, text.first().copied())) {
+            (Some(&c), Some((true, after))) if c != b'/' => match_from(after, &text[1..], false),
+            (_, None) => text.first() == Some(&b'[') && match_from(rest, &text[1..], false),
+            _ => false,
+        },
+        b'\\' if !rest.is_empty() => {
+            text.first() == Some(&rest[0]) && match_from(&rest[1..], &text[1..], rest[0] == b'/')
+        }
+        c => text.first() == Some(&c) && match_from(rest, &text[1..], c == b'/'),
+    }
+}
+
+/// A `*` followed by `pattern`: try every split of `text` up to the next `/`.
+fn star(pattern: &[u8], text: &[u8]) -> bool {
+    for i in 0..=text.len() {
+        if match_from(pattern, &text[i..], false) {
+            return true;
+        }
+        if text.get(i) == Some(&b'/') {
+            break;
+        }
+    }
+    false
+}
+
+/// Match a character against the class after `[`, returning whether it
+/// matched and the rest of the pattern; `None` if the class is unterminated.
+fn match_class(pattern: &[u8], c: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negated, mut rest) = match pattern.first() {
+        Some(b'!' | b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    let c = c.unwrap_or(0);
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let (&next, after) = rest.split_first()?;
+        if next == b']' && !first {
+            return Some((matched != negated, after));
+        }
+        first = false;
+        let (low, after) = match next {
+            b'\\' => {
+                let (&escaped, after) = after.split_first()?;
+                (escaped, after)
+            }
+            _ => (next, after),
+        };
+        match after {
+            [b'-', high, after @ ..] if *high != b']' => {
+                matched |= (low..=*high).contains(&c);
+                rest = after;
+            }
+            _ => {
+                matched |= low == c;
+                rest = after;
+            }
+        }
+    }
+}
+
+impl Ignore {
+    pub fn new(repo: &Repository) -> io::Result<Ignore> {
+        let work_tree = repo.work_tree()?.to_path_buf();
+        // Sources are shown relative to the work tree where possible
+        let display = |path: &Path| {
+            path.strip_prefix(&work_tree)
+                .unwrap_or(path)
+                .display()
+                .to_string()
+        };
+        let info_exclude = repo.path("info/exclude");
+        let mut exclude = vec![read_patterns(&info_exclude, &display(&info_exclude), "")?];
+
+        let config = repo.config()?;
+        let excludes_file = match config.get("core.excludesfile") {
+            Some(path) => match path.strip_prefix("~/") {
+                Some(rest) => env::var_os("HOME").map(|home| PathBuf::from(home).join(rest)),
+                None => Some(work_tree.join(path)),
+            },
+            None => default_excludes_file(),
+        };
+        if let Some(path) = excludes_file {
+            exclude.push(read_patterns(&path, &display(&path), "")?);
+        }
+
+        Ok(Ignore {
+            work_tree,
+            exclude,
+            per_directory: HashMap::new(),
+        })
+    }
+
+    /// The `.gitignore` patterns of a directory (empty or ending in `/`).
+    fn directory_patterns(&mut self, dir: &str) -> io::Result<&[Pattern]> {
+        if !self.per_directory.contains_key(dir) {
+            let source = format!("{}.gitignore", dir);
+            let patterns = read_patterns(&self.work_tree.join(&source), &source, dir)?;
+            self.per_directory.insert(dir.to_string(), patterns);
+        }
+        Ok(&self.per_directory[dir])
+    }
+
+    /// The last pattern matching `path` itself, ignoring its parents.
+    fn last_match(&mut self, path: &str, is_dir: bool) -> io::Result<Option<Pattern>> {
+        // Directories holding `path`, deepest first
+        let mut dirs: Vec<&str> = path.match_indices('/').map(|(i, _)| &path[..=i]).collect();
+        dirs.reverse();
+        dirs.push("");
+
+        for dir in dirs {
+            let patterns = self.directory_patterns(dir)?;
+            if let Some(pattern) = patterns.iter().rev().find(|p| p.matches(path, is_dir)) {
+                return Ok(Some(pattern.clone()));
+            }
+        }
+        for patterns in &self.exclude {
+            if let Some(pattern) = patterns.iter().rev().find(|p| p.matches(path, is_dir)) {
+                return Ok(Some(pattern.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The pattern deciding whether `path` (relative to the work tree, `/`
+    /// separated) is ignored: that of an ignored parent directory, else the
+    /// last one matching the path. A negated pattern means not ignored.
+    pub fn matching(&mut self, path: &str, is_dir: bool) -> io::Result<Option<Pattern>> {
+        for (i, _) in path.match_indices('/') {
+            if let Some(pattern) = self.last_match(&path[..i], true)? {
+                if !pattern.negated {
+                    return Ok(Some(pattern));
+                }
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> io::Result<bool> {
+        Ok(self
+            .matching(path, is_dir)?
+            .is_some_and(|pattern| !pattern.negated))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, git_available, ScratchDir};
+
+    #[test]
+    fn wildmatch_follows_git_rules() {
+        let cases = [
+            ("*.o", "main.o", true),
+            ("*.o", "dir/main.o", false),
+            ("a?c", "abc", true),
+            ("a?c", "a/c", false),
+            ("a*", "a/b", false),
+            ("**/foo", "foo", true),
+            ("**/foo", "a/b/foo", true),
+            ("**/foo", "afoo", false),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**/b", "ab", false),
+            ("abc/**", "abc/x/y", true),
+            ("abc/**", "abc", false),
+            ("a**b", "axxb", true),
+            ("a**b", "a/b", false),
+            ("[a-c]x", "bx", true),
+            ("[a-c]x", "dx", false),
+            ("[!a-c]x", "dx", true),
+            ("[^a]x", "ax", false),
+            ("[]]", "]", true),
+            ("[a", "[a", true),
+            ("\\*", "*", true),
+            ("\\*", "x", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                wildmatch(pattern.as_bytes(), text.as_bytes()),
+                expected,
+                "{} against {}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn parses_pattern_flags() {
+        let text =
+            "# comment\n\n!keep.o\n/root\nbuild/\ndoc/*.txt\n\\#hash\n\\!bang\ntrail  \nesc\\ \n";
+        let patterns = parse_patterns(text, ".gitignore", "sub/");
+        let summary: Vec<(&str, bool, bool, bool)> = patterns
+            .iter()
+            .map(|p| (p.glob.as_str(), p.negated, p.dir_only, p.anchored))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("keep.o", true, false, false),
+                ("root", false, false, true),
+                ("build", false, true, false),
+                ("doc/*.txt", false, false, true),
+                ("#hash", false, false, false),
+                ("!bang", false, false, false),
+                ("trail", false, false, false),
+                ("esc\\ ", false, false, false),
+            ]
+        );
+        assert_eq!(patterns[0].line, 3);
+        assert_eq!(patterns[0].text, "!keep.o");
+        assert!(patterns.iter().all(|p| p.base == "sub/"));
+    }
+
+    #[test]
+    fn ignored_files_match_git() {
+        if !git_available() {
+            return;
+        }
+        let dir = ScratchDir::new("ignore-vs-git");
+        let work_tree = dir.path();
+        git(work_tree, &["init", "-q"]);
+        git(work_tree, &["config", "core.excludesFile", "/nonexistent"]);
+
+        let files = [
+            ("a.o", ""),
+            ("keep.o", ""),
+            ("src/b.o", ""),
+            ("src/keep.o", ""),
+            ("src/gen/out.txt", ""),
+            ("src/gen/keep.txt", ""),
+            ("build/x", ""),
+            ("sub/build", ""),
+            ("root.log", ""),
+            ("docs/root.log", ""),
+            ("docs/a/b/deep.tmp", ""),
+            ("deep.tmp", ""),
+            ("notes/todo.md", ""),
+            ("notes/todo.txt", ""),
+            ("local.cfg", ""),
+            ("logs/today/app.log", ""),
+            (
+                ".gitignore",
+                "*.o\n!keep.o\nbuild/\n/root.log\ndocs/**/*.tmp\n!gen/keep.txt\nlogs/**\n",
+            ),
+            ("src/.gitignore", "gen/\n!keep.o\n"),
+            ("notes/.gitignore", "*\n!*.md\n!.gitignore\n"),
+        ];
+        for (path, contents) in files {
+            let path = work_tree.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        fs::write(work_tree.join(".git/info/exclude"), "local.cfg\n").unwrap();
+
+        let listed = git(
+            work_tree,
+            &["ls-files", "--others", "--ignored", "--exclude-standard"],
+        );
+        let expected: Vec<&str> = std::str::from_utf8(&listed).unwrap().lines().collect();
+
+        let repo = Repository::at_work_tree(work_tree).unwrap();
+        let mut ignore = Ignore::new(&repo).unwrap();
+        let mut ignored: Vec<&str> = files
+            .iter()
+            .map(|(path, _)| *path)
+            .filter(|path| ignore.is_ignored(path, false).unwrap())
+            .collect();
+        ignored.sort();
+        assert_eq!(ignored, expected);
+        // Sanity check that the rules under test are in play
+        assert!(expected.contains(&"src/gen/keep.txt"));
+        assert!(!expected.contains(&"keep.o"));
+    }
+}
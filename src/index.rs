@@ -0,0 +1,618 @@
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::{compute_sha1, Hash};
+
+/*
+The index (.git/index) is laid out as:
+
+DIRC <version: u32 = 2 or 3> <entry count: u32>
+<entry>*                   sorted by path, then stage
+<extension>*               <signature: 4 bytes> <size: u32> <data>
+<20 byte SHA-1 of everything above>
+
+Each entry is:
+
+<ctime s> <ctime ns> <mtime s> <mtime ns> <dev> <ino> <mode> <uid> <gid> <size>
+                           all u32
+<hash: 20 bytes>
+<flags: u16>               assume-valid, extended, stage (2 bits), name length
+<extended flags: u16>      version 3 only, when the extended flag is set
+<path> NUL padding         1-8 NULs, so the entry length is a multiple of 8
+
+Writers take `index.lock` (created exclusively) before reading the index and
+rename it over `index` once done, so concurrent commands fail instead of
+losing each other's updates.
+
+Paths are UTF-8 here. Trees keep names as raw bytes, but an index (or a work
+tree) path that is not UTF-8 is refused rather than stored mangled, so add,
+status, rm and checkout cannot handle such names.
+*/
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE: u16 = 0x3000;
+/// Extended flag of entries added with `git add -N`.
+const FLAG_INTENT_TO_ADD: u16 = 0x2000;
+const NAME_MASK: u16 = 0x0fff;
+const NAME_MASK_USIZE: usize = NAME_MASK as usize;
+
+/// Size of an entry up to the path, without extended flags.
+const ENTRY_HEADER_SIZE: usize = 62;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub hash: Hash,
+    pub assume_valid: bool,
+    /// 0 for a normal entry; 1-3 for the sides of a merge conflict.
+    pub stage: u8,
+    /// skip-worktree and intent-to-add (version 3).
+    pub extended_flags: u16,
+    pub path: String,
+}
+
+impl IndexEntry {
+    /// A stage 0 entry for a file with the given stat data.
+    pub fn new(path: String, mode: u32, hash: Hash, metadata: &Metadata) -> IndexEntry {
+        // Timestamps and sizes are truncated to 32 bits, as git does
+        IndexEntry {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+            hash,
+            assume_valid: false,
+            stage: 0,
+            extended_flags: 0,
+            path,
+        }
+    }
+
+    /// Whether the path is only recorded as going to be added; such
+    /// entries are left out of trees.
+    pub fn is_intent_to_add(&self) -> bool {
+        self.extended_flags & FLAG_INTENT_TO_ADD != 0
+    }
+
+    /// Whether the file's stat data is the same as when the entry was made.
+    fn same_stat(&self, metadata: &Metadata) -> bool {
+        self.mtime == (metadata.mtime() as u32, metadata.mtime_nsec() as u32)
+            && self.ctime == (metadata.ctime() as u32, metadata.ctime_nsec() as u32)
+            && self.ino == metadata.ino() as u32
+            && self.size == metadata.size() as u32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    pub version: u32,
+    entries: Vec<IndexEntry>,
+    /// Extensions kept verbatim, e.g. the `TREE` cache.
+    extensions: Vec<([u8; 4], Vec<u8>)>,
+    /// Modification time of the index file this was loaded from.
+    timestamp: Option<(u32, u32)>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u32(data: &[u8], pos: usize) -> io::Result<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or_else(|| invalid("Truncated index"))?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u16(data: &[u8], pos: usize) -> io::Result<u16> {
+    let bytes = data
+        .get(pos..pos + 2)
+        .ok_or_else(|| invalid("Truncated index"))?;
+    Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index {
+            version: 2,
+            entries: Vec::new(),
+            extensions: Vec::new(),
+            timestamp: None,
+        }
+    }
+}
+
+impl Index {
+    /// Load an index file; a missing file is an empty index.
+    pub fn load(path: &Path) -> io::Result<Index> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Index::default()),
+            Err(e) => return Err(e),
+        };
+        let metadata = file.metadata()?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let mut index = Index::parse(&data)?;
+        index.timestamp = Some((metadata.mtime() as u32, metadata.mtime_nsec() as u32));
+        Ok(index)
+    }
+
+    pub fn parse(data: &[u8]) -> io::Result<Index> {
+        if data.len() < 12 + 20 || &data[..4] != b"DIRC" {
+            return Err(invalid("Not an index file"));
+        }
+        let version = read_u32(data, 4)?;
+        if version != 2 && version != 3 {
+            return Err(invalid(&format!("Unsupported index version {}", version)));
+        }
+        let (body, trailer) = data.split_at(data.len() - 20);
+        if compute_sha1(body) != Hash::from_bytes(trailer)? {
+            return Err(invalid("Index checksum mismatch"));
+        }
+
+        let count = read_u32(data, 8)?;
+        // The count is untrusted, so it does not size the allocation
+        let mut entries = Vec::new();
+        let mut pos = 12;
+        for _ in 0..count {
+            let field = |i: usize| read_u32(body, pos + i * 4);
+            let hash = body
+                .get(pos + 40..pos + 60)
+                .ok_or_else(|| invalid("Truncated index entry"))?;
+            let flags = read_u16(body, pos + 60)?;
+            let mut path_start = pos + ENTRY_HEADER_SIZE;
+            let mut extended_flags = 0;
+            if flags & FLAG_EXTENDED != 0 {
+                if version < 3 {
+                    return Err(invalid("Extended index entry in a version 2 index"));
+                }
+                extended_flags = read_u16(body, path_start)?;
+                path_start += 2;
+            }
+
+            // Names longer than the 12 bit field are NUL terminated instead
+            let path_end = match (flags & NAME_MASK) as usize {
+                NAME_MASK_USIZE => body[path_start..]
+                    .iter()
+                    .position(|&b| b == 0)
+                    .map(|len| path_start + len),
+                len => Some(path_start + len),
+            }
+            .filter(|&end| end < body.len())
+            .ok_or_else(|| invalid("Truncated index entry"))?;
+            let path = std::str::from_utf8(&body[path_start..path_end])
+                .map_err(|_| invalid("Index path is not valid UTF-8"))?
+                .to_string();
+
+            entries.push(IndexEntry {
+                ctime: (field(0)?, field(1)?),
+                mtime: (field(2)?, field(3)?),
+                dev: field(4)?,
+                ino: field(5)?,
+                mode: field(6)?,
+                uid: field(7)?,
+                gid: field(8)?,
+                size: field(9)?,
+                hash: Hash::from_bytes(hash)?,
+                assume_valid: flags & FLAG_ASSUME_VALID != 0,
+                stage: ((flags & FLAG_STAGE) >> 12) as u8,
+                extended_flags,
+                path,
+            });
+            pos += padded_len(path_end - pos);
+        }
+
+        let mut extensions = Vec::new();
+        while pos < body.len() {
+            let signature: [u8; 4] = body
+                .get(pos..pos + 4)
+                .ok_or_else(|| invalid("Truncated index extension"))?
+                .try_into()
+                .unwrap();
+            let size = read_u32(body, pos + 4)? as usize;
+            let data = body
+                .get(pos + 8..pos + 8 + size)
+                .ok_or_else(|| invalid("Truncated index extension"))?;
+            // Lowercase signatures mark extensions a reader must understand
+            if !signature[0].is_ascii_uppercase() {
+                return Err(invalid(&format!(
+                    "Unsupported index extension '{}'",
+                    String::from_utf8_lossy(&signature)
+                )));
+            }
+            extensions.push((signature, data.to_vec()));
+            pos += 8 + size;
+        }
+
+        Ok(Index {
+            version,
+            entries,
+            extensions,
+            timestamp: None,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        // Extended flags need version 3
+        let version = if self.entries.iter().any(|e| e.extended_flags != 0) {
+            self.version.max(3)
+        } else {
+            self.version
+        };
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"DIRC");
+        out.extend_from_slice(&version.to_be_bytes());
+        out.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            let start = out.len();
+            for field in [
+                entry.ctime.0,
+                entry.ctime.1,
+                entry.mtime.0,
+                entry.mtime.1,
+                entry.dev,
+                entry.ino,
+                entry.mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                out.extend_from_slice(&field.to_be_bytes());
+            }
+            out.extend_from_slice(entry.hash.as_bytes());
+
+            let mut flags = (entry.path.len().min(NAME_MASK_USIZE)) as u16;
+            flags |= (entry.stage as u16) << 12 & FLAG_STAGE;
+            if entry.assume_valid {
+                flags |= FLAG_ASSUME_VALID;
+            }
+            if entry.extended_flags != 0 {
+                flags |= FLAG_EXTENDED;
+            }
+            out.extend_from_slice(&flags.to_be_bytes());
+            if entry.extended_flags != 0 {
+                out.extend_from_slice(&entry.extended_flags.to_be_bytes());
+            }
+            out.extend_from_slice(entry.path.as_bytes());
+
+            let len = out.len() - start;
+            out.resize(start + padded_len(len), 0);
+        }
+        for (signature, data) in &self.extensions {
+            out.extend_from_slice(signature);
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(data);
+        }
+
+        let checksum = compute_sha1(&out);
+        out.extend_from_slice(checksum.as_bytes());
+        out
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Whether the file's stat data shows it unchanged since `entry` was
+    /// made, meaning its contents need not be hashed again.
+    ///
+    /// A file modified no earlier than the index was written may have been
+    /// changed again within the same timestamp tick without its stat data
+    /// showing it, so such racily clean entries never match.
+    pub fn stat_matches(&self, entry: &IndexEntry, metadata: &Metadata) -> bool {
+        let racy = !self
+            .timestamp
+            .is_some_and(|timestamp| entry.mtime < timestamp);
+        entry.same_stat(metadata) && !racy
+    }
+
+    /// Trees recorded in the `TREE` cache extension, skipping invalidated
+    /// nodes. Each node is `<path>\0<entry count> <subtree count>\n`,
+    /// followed by the tree's hash unless the entry count is -1.
+    pub fn cached_trees(&self) -> io::Result<Vec<Hash>> {
+        let Some((_, data)) = self.extensions.iter().find(|(name, _)| name == b"TREE") else {
+            return Ok(Vec::new());
+        };
+        let mut trees = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let null = data[pos..]
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| invalid("Truncated cache tree"))?;
+            pos += null + 1;
+            let newline = data[pos..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or_else(|| invalid("Truncated cache tree"))?;
+            let counts = &data[pos..pos + newline];
+            pos += newline + 1;
+            if counts.starts_with(b"-") {
+                continue;
+            }
+            let hash = data
+                .get(pos..pos + 20)
+                .ok_or_else(|| invalid("Truncated cache tree"))?;
+            trees.push(Hash::from_bytes(hash)?);
+            pos += 20;
+        }
+        Ok(trees)
+    }
+
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.path == path && entry.stage == 0)
+    }
+
+    /// Add or replace the entry for a path. Every stage of the path goes, as
+    /// do entries it collides with: a file where the path needs a directory
+    /// and anything below the path if it was a directory.
+    pub fn add(&mut self, entry: IndexEntry) {
+        let path = entry.path.as_str();
+        self.entries.retain(|existing| {
+            let other = existing.path.as_str();
+            other != path && !is_parent(other, path) && !is_parent(path, other)
+        });
+        let position = self.entries.partition_point(|existing| {
+            (existing.path.as_bytes(), existing.stage) < (path.as_bytes(), entry.stage)
+        });
+        self.entries.insert(position, entry);
+        self.extensions.clear();
+    }
+
+    /// Remove every stage of a path, returning whether it was present.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let count = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        if self.entries.len() == count {
+            return false;
+        }
+        // Cached trees and the like no longer describe the entries
+        self.extensions.clear();
+        true
+    }
+}
+
+/// Whether `dir` is a leading directory of `path`.
+fn is_parent(dir: &str, path: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The `index.lock` file held while the index is being updated. Dropping
+/// the lock without committing leaves the index untouched.
+pub struct IndexLock {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl IndexLock {
+    pub fn acquire(path: &Path) -> io::Result<IndexLock> {
+        let mut lock_name = path.as_os_str().to_owned();
+        lock_name.push(".lock");
+        let lock_path = PathBuf::from(lock_name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Unable to create '{}': File exists. Another git process seems to be running in this repository",
+                        lock_path.display()
+                    ),
+                ),
+                _ => e,
+            })?;
+        Ok(IndexLock {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// Write `index` to the lock file and move it into place.
+    pub fn commit(mut self, index: &Index) -> io::Result<()> {
+        let file = self.file.as_mut().unwrap();
+        file.write_all(&index.serialize())?;
+        file.sync_all()?;
+        fs::rename(&self.lock_path, &self.path)?;
+        // The lock file is now the index, so there is nothing to clean up
+        self.file = None;
+        Ok(())
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Length of an entry once padded with 1-8 NULs to a multiple of 8.
+fn padded_len(len: usize) -> usize {
+    (len + 8) & !7
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, git_available, git_with_stdin, ScratchDir};
+
+    fn entry(path: &str, stage: u8, extended_flags: u16) -> IndexEntry {
+        IndexEntry {
+            ctime: (1_700_000_000, 5),
+            mtime: (1_700_000_001, 6),
+            dev: 2049,
+            ino: 42,
+            mode: 0o100644,
+            uid: 1000,
+            gid: 1000,
+            size: 12,
+            hash: Hash::from_hex("3b18e512dba79e4c8300dd08aeb37f8e728b8dad").unwrap(),
+            assume_valid: false,
+            stage,
+            extended_flags,
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn serialized_entries_parse_back_unchanged() {
+        let long_name = format!("dir/{}", "x".repeat(5000));
+        let mut index = Index::default();
+        index.add(entry("b.txt", 0, 0));
+        index.add(entry("a/nested.txt", 0, 0));
+        index.add(entry(&long_name, 0, 0));
+        index.add(entry("new.txt", 0, FLAG_INTENT_TO_ADD));
+
+        let data = index.serialize();
+        let parsed = Index::parse(&data).unwrap();
+        assert_eq!(parsed.version, 3);
+        assert_eq!(parsed.entries(), index.entries());
+        assert!(parsed.get("new.txt").unwrap().is_intent_to_add());
+        assert_eq!(parsed.get(&long_name).unwrap().path.len(), long_name.len());
+        assert_eq!(parsed.serialize(), data);
+    }
+
+    #[test]
+    fn rejects_corrupt_indexes() {
+        let mut index = Index::default();
+        index.add(entry("a.txt", 0, 0));
+        let mut data = index.serialize();
+
+        assert!(Index::parse(&data[..data.len() - 1]).is_err());
+        data[20] ^= 1;
+        let error = Index::parse(&data).unwrap_err();
+        assert_eq!(error.to_string(), "Index checksum mismatch");
+    }
+
+    #[test]
+    fn huge_entry_counts_fail_without_allocating() {
+        let mut data = b"DIRC\0\0\0\x02\xff\xff\xff\xff".to_vec();
+        let checksum = compute_sha1(&data);
+        data.extend_from_slice(checksum.as_bytes());
+        let error = Index::parse(&data).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_and_writes_indexes_git_understands() {
+        if !git_available() {
+            return;
+        }
+        let dir = ScratchDir::new("index-round-trip");
+        let work_tree = dir.path();
+        git(work_tree, &["init", "-q"]);
+        fs::create_dir(work_tree.join("src")).unwrap();
+        fs::write(work_tree.join("README"), "hello\n").unwrap();
+        fs::write(work_tree.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(work_tree.join("later.txt"), "later\n").unwrap();
+        git(work_tree, &["add", "README", "src"]);
+        let tree = git(work_tree, &["write-tree"]);
+        git(work_tree, &["add", "-N", "later.txt"]);
+        let blob = git(work_tree, &["hash-object", "-w", "README"]);
+        let blob = String::from_utf8(blob).unwrap();
+        let conflict = format!(
+            "100644 {0} 1\tboth.txt\n100644 {0} 2\tboth.txt\n",
+            blob.trim()
+        );
+        git_with_stdin(work_tree, &["update-index", "--index-info"], &conflict);
+
+        // git's own index survives a parse and serialize byte for byte
+        let index_path = work_tree.join(".git/index");
+        let original = fs::read(&index_path).unwrap();
+        let index = Index::load(&index_path).unwrap();
+        assert_eq!(index.serialize(), original);
+        let stages: Vec<(&str, u8)> = index
+            .entries()
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.stage))
+            .collect();
+        assert_eq!(
+            stages,
+            [
+                ("README", 0),
+                ("both.txt", 1),
+                ("both.txt", 2),
+                ("later.txt", 0),
+                ("src/main.rs", 0),
+            ]
+        );
+        assert!(index.get("later.txt").unwrap().is_intent_to_add());
+
+        // and git reads back an index we changed
+        let mut index = index;
+        index.remove("both.txt");
+        index.remove("later.txt");
+        fs::write(&index_path, index.serialize()).unwrap();
+        let listed = git(work_tree, &["ls-files", "--stage"]);
+        assert_eq!(
+            String::from_utf8(listed).unwrap(),
+            format!(
+                "100644 {} 0\tREADME\n100644 {} 0\tsrc/main.rs\n",
+                blob.trim(),
+                index.get("src/main.rs").unwrap().hash.to_hex()
+            )
+        );
+        assert_eq!(git(work_tree, &["write-tree"]), tree);
+    }
+
+    #[test]
+    fn cached_trees_match_git() {
+        if !git_available() {
+            return;
+        }
+        let dir = ScratchDir::new("index-cached-trees");
+        let work_tree = dir.path();
+        git(work_tree, &["init", "-q"]);
+        fs::create_dir(work_tree.join("src")).unwrap();
+        fs::write(work_tree.join("README"), "hello\n").unwrap();
+        fs::write(work_tree.join("src/lib.rs"), "\n").unwrap();
+        git(work_tree, &["add", "."]);
+        let root = git(work_tree, &["write-tree"]);
+        let src = git(
+            work_tree,
+            &[
+                "rev-parse",
+                &format!("{}:src", String::from_utf8_lossy(&root).trim()),
+            ],
+        );
+
+        let index = Index::load(&work_tree.join(".git/index")).unwrap();
+        let mut cached: Vec<String> = index
+            .cached_trees()
+            .unwrap()
+            .iter()
+            .map(|hash| hash.to_hex())
+            .collect();
+        cached.sort();
+        let mut expected = vec![
+            String::from_utf8(root).unwrap().trim().to_string(),
+            String::from_utf8(src).unwrap().trim().to_string(),
+        ];
+        expected.sort();
+        assert_eq!(cached, expected);
+    }
+}
@@ -2,15 +2,55 @@ use chrono::{DateTime, Utc};
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
-use hex;
 use sha1::{Digest, Sha1};
-use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io::prelude::*;
 use std::io::{self, Read};
-use std::path::Path;
-use std::process::Command;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+mod checkout;
+mod config;
+mod delta;
+mod fetch;
+mod fsck;
+mod gc;
+mod ignore;
+mod index;
+mod object_headers;
+mod objects;
+mod pack_index;
+mod packfile;
+mod pkt_line;
+mod push;
+mod refs;
+mod refspec;
+mod repository;
+mod status;
+mod transport;
+mod worktree;
+
+#[cfg(test)]
+mod test_support;
+
+use checkout::CheckoutOptions;
+use ignore::Ignore;
+use index::{Index, IndexEntry, IndexLock};
+use object_headers::GitObjectHeader;
+use objects::{with_header, Commit, Object, ObjectKind};
+use pack_index::Pack;
+use refs::{
+    check_ref_format, delete_ref, is_valid_branch_name, read_ref, read_symbolic_ref, shorten_ref,
+    write_ref, write_symbolic_ref, RefFormat, ShowRefOptions,
+};
+use repository::{InitOptions, Repository};
+use status::{Format, StatusOptions, Untracked};
+use worktree::RmOptions;
 
 /*
 Tests
@@ -31,11 +71,12 @@ Read commit object attributes from the .git directory
 
 pub struct GitTreeEntry {
     pub mode: String,
-    pub name: String,
+    /// Raw bytes, as git does not require names to be UTF-8.
+    pub name: OsString,
     pub hash: Hash,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Hash([u8; 20]);
 
 impl Hash {
@@ -52,6 +93,12 @@ impl Hash {
         }
     }
 
+    pub fn from_hex(hex: &str) -> io::Result<Self> {
+        let bytes = hex::decode(hex)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid hex in hash"))?;
+        Hash::from_bytes(&bytes)
+    }
+
     pub fn to_hex(&self) -> String {
         hex::encode(self.0)
     }
@@ -59,6 +106,11 @@ impl Hash {
     pub fn as_bytes(&self) -> &[u8; 20] {
         &self.0
     }
+
+    /// The all-zero hash the wire protocol uses for a missing ref.
+    pub fn zero() -> Self {
+        Hash([0u8; 20])
+    }
 }
 
 fn main() -> io::Result<()> {
@@ -72,13 +124,45 @@ fn main() -> io::Result<()> {
 
     match args[1].as_str() {
         "init" => {
-            fs::create_dir(".git")?;
-            fs::create_dir(".git/objects")?;
-            fs::create_dir(".git/refs")?;
-            fs::create_dir(".git/refs/heads")?;
-            fs::create_dir(".git/refs/tags")?;
-            fs::write(".git/HEAD", "ref: refs/heads/master\n")?;
-            println!("Initialized empty Git repository in .git/");
+            let mut options = InitOptions::default();
+            let mut directory = ".";
+            let mut template = env::var_os("GIT_TEMPLATE_DIR").map(std::path::PathBuf::from);
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--bare" => options.bare = true,
+                    "-b" | "--initial-branch" => {
+                        options.initial_branch = rest.next().map_or("", String::as_str)
+                    }
+                    "--template" => template = rest.next().map(std::path::PathBuf::from),
+                    "--object-format" => {
+                        options.object_format = Some(rest.next().map_or("", String::as_str))
+                    }
+                    arg => {
+                        if let Some(branch) = arg.strip_prefix("--initial-branch=") {
+                            options.initial_branch = branch;
+                        } else if let Some(dir) = arg.strip_prefix("--template=") {
+                            template = Some(dir.into());
+                        } else if let Some(format) = arg.strip_prefix("--object-format=") {
+                            options.object_format = Some(format);
+                        } else {
+                            directory = arg;
+                        }
+                    }
+                }
+            }
+            // An empty template directory means no template at all
+            options.template = template
+                .as_deref()
+                .filter(|dir| !dir.as_os_str().is_empty());
+
+            let (repo, existed) = Repository::init(Path::new(directory), &options)?;
+            let action = if existed {
+                "Reinitialized existing"
+            } else {
+                "Initialized empty"
+            };
+            println!("{} Git repository in {}/", action, repo.git_dir().display());
         }
         "cat-file" => {
             // read the blob object
@@ -92,51 +176,20 @@ fn main() -> io::Result<()> {
                 return Ok(());
             }
 
+            let repo = &Repository::discover()?;
             let blob_sha = &args[3];
-            cat_file(blob_sha)?;
+            cat_file(repo, blob_sha)?;
         }
         "hash-object" => {
-            // First I need to read the contents of the file
-            // store = header + content
-            // header seems to be "blob <size>\0"
-            // size = length of content
-            // content = actual file content which is given input as test.txt
-            // then hash the stored content
-
             // Check if the user has provided the file name
             if args.len() < 3 {
                 eprintln!("Usage: {} hash-object -w <file>", args[0]);
                 return Ok(());
             }
 
-            // Read the file content
-            let file_name = &args[3];
-            let content = fs::read(file_name)?;
-
-            let header = format!("blob {}\0", content.len());
-            let blob_data = format!("{}{}", header, String::from_utf8_lossy(&content));
-            // print!("{}", blob_data);
-
-            // Compute the SHA-1 hash of the blob_data
-            let mut hasher = Sha1::new();
-            hasher.update(blob_data.as_bytes());
-            let hash = hasher.finalize();
-            let hash_hex = format!("{:x}", hash);
-            print!("{}", hash_hex);
-
-            // Create the directory structure in .git/objects
-            let (dir, file) = hash_hex.split_at(2);
-            let object_dir = format!(".git/objects/{}", dir);
-            fs::create_dir_all(&object_dir)?;
-
-            // Compress the blob data
-            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-            encoder.write_all(blob_data.as_bytes())?;
-            let compressed_data = encoder.finish()?;
-
-            // Write the compressed data to the object file
-            let object_path = format!("{}/{}", object_dir, file);
-            fs::write(object_path, compressed_data)?;
+            let repo = &Repository::discover()?;
+            let hash = create_blob(repo, Path::new(&args[3]))?;
+            print!("{}", hash.to_hex());
         }
         "ls-tree" => {
             // Check if the user has provided the tree sha
@@ -145,23 +198,12 @@ fn main() -> io::Result<()> {
                 return Ok(());
             }
 
-            let tree_sha = &args[3];
-            let (dir, file) = tree_sha.split_at(2);
-            let path = format!(".git/objects/{}/{}", dir, file);
-            let content = fs::read(path)?;
-
-            // Decompress the data
-            let mut decompressed_data = ZlibDecoder::new(&content[..]);
-            let mut tree_file_contents_vec = Vec::new();
-            decompressed_data.read_to_end(&mut tree_file_contents_vec)?;
-
-            // Convert the tree file contents to a readable string
-            let readable_tree = String::from_utf8_lossy(&tree_file_contents_vec);
-
-            let cow_str: Cow<str> = Cow::Borrowed(&readable_tree);
+            let repo = &Repository::discover()?;
+            let tree_sha = Hash::from_hex(&args[3])?;
+            let tree_object = read_object_data(repo, &tree_sha)?;
 
             // Extract the tree entries
-            let names = extract_names_from_tree_entries(cow_str.as_bytes());
+            let names = extract_names_from_tree_entries(&tree_object)?;
             for name in names {
                 println!("{}", name);
             }
@@ -173,7 +215,34 @@ fn main() -> io::Result<()> {
                 return Ok(());
             }
 
-            let tree_sha = write_tree(Path::new("."))?;
+            let mut missing_ok = false;
+            let mut prefix = "";
+            let mut from_work_tree = false;
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--missing-ok" => missing_ok = true,
+                    // Hash the files on disk rather than the staged entries
+                    "--from-work-tree" => from_work_tree = true,
+                    arg => match arg.strip_prefix("--prefix=") {
+                        Some(value) => prefix = value,
+                        None => {
+                            eprintln!(
+                                "Usage: {} write-tree [--missing-ok] [--prefix=<prefix>/] [--from-work-tree]",
+                                args[0]
+                            );
+                            return Ok(());
+                        }
+                    },
+                }
+            }
+
+            let repo = &Repository::discover()?;
+            let tree_sha = if from_work_tree {
+                write_tree(repo)?
+            } else {
+                let index = Index::load(&repo.path("index"))?;
+                write_index_tree(repo, &index, prefix, missing_ok)?
+            };
             print!("{}", tree_sha.to_hex());
         }
         "commit-tree" => {
@@ -186,29 +255,565 @@ fn main() -> io::Result<()> {
                 return Ok(());
             }
 
-            let tree_sha = &args[2];
-            let parent_sha = Some(&args[4]);
+            let repo = &Repository::discover()?;
+            let tree_sha = Hash::from_hex(&args[2])?;
+            let parent_sha = Hash::from_hex(&args[4])?;
             let message = &args[6];
             let author = "Rohit Paul <Rohit.paul@gmail.com>";
             let committer = "Kishor Kumar Paroi <kishor.ruet.cse@gmail.com>";
 
             let commit_data =
-                create_commit_object(tree_sha, parent_sha, author, committer, message);
-            let commit_hash = write_commit_object(&commit_data)?;
+                create_commit_object(tree_sha, Some(parent_sha), author, committer, message);
+            let commit_hash = write_commit_object(repo, &commit_data)?;
 
             println!("{}", commit_hash.to_hex());
         }
         "clone" => {
-            // Check if the user has provided the repository URL
-            if args.len() < 3 {
-                eprintln!("Usage: {} clone <repository_url> <directory>", args[0]);
+            let mut branch = None;
+            let mut positional = Vec::new();
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-b" | "--branch" => branch = rest.next().map(String::as_str),
+                    _ => positional.push(arg.as_str()),
+                }
+            }
+
+            // Check if the user has provided the repository URL and directory
+            if positional.len() < 2 {
+                eprintln!(
+                    "Usage: {} clone [-b <branch>] <repository_url> <directory>",
+                    args[0]
+                );
                 return Ok(());
             }
 
-            let repository_url = &args[2];
-            let directory = &args[3];
+            clone_repository(positional[0], positional[1], branch)?;
+        }
+        "fetch" => {
+            let mut update_head_ok = false;
+            let mut positional = Vec::new();
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "-u" | "--update-head-ok" => update_head_ok = true,
+                    other if other.starts_with('-') => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("unknown fetch option '{}'", other),
+                        ))
+                    }
+                    other => positional.push(other),
+                }
+            }
+            let remote = positional.first().copied().unwrap_or("origin");
+            let refspecs = positional.get(1..).unwrap_or_default();
+            fetch::fetch(&Repository::discover()?, remote, refspecs, update_head_ok)?;
+        }
+        "push" => {
+            let remote = args.get(2).map(String::as_str).unwrap_or("origin");
+            let refspecs: Vec<&str> = args.iter().skip(3).map(String::as_str).collect();
+            push::push(&Repository::discover()?, remote, &refspecs)?;
+        }
+        "ls-files" => {
+            let stage = args
+                .iter()
+                .skip(2)
+                .any(|arg| arg == "-s" || arg == "--stage");
+            let repo = &Repository::discover()?;
+            let index = Index::load(&repo.path("index"))?;
+            for entry in index.entries() {
+                if stage {
+                    println!(
+                        "{:06o} {} {}\t{}",
+                        entry.mode,
+                        entry.hash.to_hex(),
+                        entry.stage,
+                        entry.path
+                    );
+                } else {
+                    println!("{}", entry.path);
+                }
+            }
+        }
+        "update-index" => {
+            // Rewrite the index, optionally switching its format version
+            let repo = &Repository::discover()?;
+            let path = repo.path("index");
+            let lock = IndexLock::acquire(&path)?;
+            let mut index = Index::load(&path)?;
+            if let Some(position) = args.iter().position(|arg| arg == "--index-version") {
+                index.version = match args.get(position + 1).map(String::as_str) {
+                    Some("2") => 2,
+                    Some("3") => 3,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "--index-version expects 2 or 3",
+                        ))
+                    }
+                };
+            }
+            lock.commit(&index)?;
+        }
+        "add" => {
+            let force = args.iter().any(|arg| arg == "-f" || arg == "--force");
+            let pathspecs: Vec<&str> = args
+                .iter()
+                .skip(2)
+                .map(String::as_str)
+                .filter(|arg| *arg != "-f" && *arg != "--force")
+                .collect();
+            if pathspecs.is_empty() {
+                eprintln!("Nothing specified, nothing added.");
+                return Ok(());
+            }
+            worktree::add(&Repository::discover()?, &pathspecs, force)?;
+        }
+        "rm" => {
+            let mut options = RmOptions::default();
+            let mut paths = Vec::new();
+            let mut rest = args.iter().skip(2);
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--cached" => options.cached = true,
+                    "-r" => options.recursive = true,
+                    "-f" | "--force" => options.force = true,
+                    "-q" | "--quiet" => options.quiet = true,
+                    "--" => paths.extend(rest.by_ref().map(String::as_str)),
+                    path => paths.push(path),
+                }
+            }
+            if paths.is_empty() {
+                eprintln!("Usage: {} rm [--cached] [-r] [-f] <path>...", args[0]);
+                return Ok(());
+            }
+            worktree::rm(&Repository::discover()?, &paths, &options)?;
+        }
+        "mv" => {
+            let force = args.iter().any(|arg| arg == "-f" || arg == "--force");
+            let paths: Vec<&str> = args
+                .iter()
+                .skip(2)
+                .map(String::as_str)
+                .filter(|arg| *arg != "-f" && *arg != "--force")
+                .collect();
+            let Some((destination, sources)) = paths.split_last().filter(|(_, s)| !s.is_empty())
+            else {
+                eprintln!("Usage: {} mv [-f] <source>... <destination>", args[0]);
+                return Ok(());
+            };
+            worktree::mv(&Repository::discover()?, sources, destination, force)?;
+        }
+        "status" => {
+            let mut options = StatusOptions {
+                format: Format::Long,
+                branch: false,
+                untracked: Untracked::Normal,
+                ignored: false,
+            };
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "-s" | "--short" => options.format = Format::Short,
+                    "--porcelain" | "--porcelain=v1" => options.format = Format::PorcelainV1,
+                    "--porcelain=v2" => options.format = Format::PorcelainV2,
+                    "--long" => options.format = Format::Long,
+                    "-b" | "--branch" => options.branch = true,
+                    "-sb" | "-bs" => {
+                        options.format = Format::Short;
+                        options.branch = true;
+                    }
+                    "-u" | "-uall" | "--untracked-files" | "--untracked-files=all" => {
+                        options.untracked = Untracked::All
+                    }
+                    "-unormal" | "--untracked-files=normal" => {
+                        options.untracked = Untracked::Normal
+                    }
+                    "-uno" | "--untracked-files=no" => options.untracked = Untracked::No,
+                    "--ignored" => options.ignored = true,
+                    other => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("unknown status option '{}'", other),
+                        ))
+                    }
+                }
+            }
+            status::status(&Repository::discover()?, &options)?;
+        }
+        "checkout" | "switch" => {
+            let switch = args[1] == "switch";
+            let mut options = CheckoutOptions {
+                require_branch: switch,
+                ..CheckoutOptions::default()
+            };
+            let mut target = None;
+            let mut rest = args.iter().skip(2);
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "-f" | "--force" => options.force = true,
+                    "--discard-changes" if switch => options.force = true,
+                    "-b" if !switch => options.new_branch = rest.next().cloned(),
+                    "-c" | "--create" if switch => options.new_branch = rest.next().cloned(),
+                    "-d" | "--detach" => options.detach = true,
+                    other if other.starts_with('-') => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("unknown {} option '{}'", args[1], other),
+                        ))
+                    }
+                    other if target.is_none() => target = Some(other),
+                    other => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("only one reference expected, got {}", other),
+                        ))
+                    }
+                }
+            }
+            if target.is_none() && options.new_branch.is_none() && !options.detach {
+                if switch {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "missing branch or commit argument",
+                    ));
+                }
+                // Plain `checkout` has nothing to do unless forced
+                if !options.force {
+                    return Ok(());
+                }
+            }
+            checkout::checkout(&Repository::discover()?, target, &options)?;
+        }
+        "update-ref" => {
+            let mut delete = false;
+            let mut deref = true;
+            let mut values = Vec::new();
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "-d" => delete = true,
+                    "--no-deref" => deref = false,
+                    value => values.push(value),
+                }
+            }
+            let (name, new, old) = match (delete, values.as_slice()) {
+                (true, [name]) => (*name, None, None),
+                (true, [name, old]) => (*name, None, Some(*old)),
+                (false, [name, new]) => (*name, Some(*new), None),
+                (false, [name, new, old]) => (*name, Some(*new), Some(*old)),
+                _ => {
+                    eprintln!(
+                        "Usage: {} update-ref [--no-deref] (-d <ref> [<old>] | <ref> <new> [<old>])",
+                        args[0]
+                    );
+                    return Ok(());
+                }
+            };
+            update_ref_command(&Repository::discover()?, name, new, old, deref)?;
+        }
+        "symbolic-ref" => {
+            let mut quiet = false;
+            let mut short = false;
+            let mut delete = false;
+            let mut names = Vec::new();
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "-q" | "--quiet" => quiet = true,
+                    "--short" => short = true,
+                    "-d" | "--delete" => delete = true,
+                    name => names.push(name),
+                }
+            }
+            let repo = &Repository::discover()?;
+            match (delete, names.as_slice()) {
+                (false, [name]) => match read_symbolic_ref(repo, name)? {
+                    Some(target) if short => println!("{}", shorten_ref(&target)),
+                    Some(target) => println!("{}", target),
+                    None if quiet => std::process::exit(1),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("ref {} is not a symbolic ref", name),
+                        ))
+                    }
+                },
+                (false, [name, target]) => {
+                    if *name == "HEAD" && !target.starts_with("refs/") {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "Refusing to point HEAD outside of refs/",
+                        ));
+                    }
+                    if !check_ref_format(target, RefFormat::default()) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Refusing to set '{}' to invalid ref '{}'", name, target),
+                        ));
+                    }
+                    write_symbolic_ref(repo, name, target)?;
+                }
+                (true, [name]) => {
+                    if *name == "HEAD" {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "deleting 'HEAD' is not allowed",
+                        ));
+                    }
+                    if read_symbolic_ref(repo, name)?.is_none() {
+                        if quiet {
+                            std::process::exit(1);
+                        }
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Cannot delete {}, not a symbolic ref", name),
+                        ));
+                    }
+                    delete_ref(repo, name)?;
+                }
+                _ => eprintln!(
+                    "Usage: {} symbolic-ref [-q] [--short] [-d] <name> [<ref>]",
+                    args[0]
+                ),
+            }
+        }
+        "show-ref" => {
+            let mut options = ShowRefOptions::default();
+            let mut patterns = Vec::new();
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--heads" | "--branches" => options.heads = true,
+                    "--tags" => options.tags = true,
+                    "--head" => options.head = true,
+                    "-d" | "--dereference" => options.dereference = true,
+                    "-s" | "--hash" => options.hash_only = Some(40),
+                    "--verify" => options.verify = true,
+                    "-q" | "--quiet" => options.quiet = true,
+                    other => match other.strip_prefix("--hash=") {
+                        Some(digits) => {
+                            let digits = digits.parse::<usize>().map_err(|_| {
+                                io::Error::new(
+                                    io::ErrorKind::InvalidInput,
+                                    format!("invalid --hash length '{}'", digits),
+                                )
+                            })?;
+                            options.hash_only = Some(digits.clamp(4, 40));
+                        }
+                        None => patterns.push(other),
+                    },
+                }
+            }
+            if !refs::show_ref(&Repository::discover()?, &patterns, &options)? {
+                // Like git, nothing matched is exit status 1
+                std::process::exit(1);
+            }
+        }
+        "check-ref-format" => {
+            let mut format = RefFormat::default();
+            let mut normalize = false;
+            let mut branch = false;
+            let mut names = Vec::new();
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--allow-onelevel" => format.allow_onelevel = true,
+                    "--no-allow-onelevel" => format.allow_onelevel = false,
+                    "--refspec-pattern" => format.refspec_pattern = true,
+                    "--normalize" | "--print" => normalize = true,
+                    "--branch" => branch = true,
+                    name => names.push(name),
+                }
+            }
+            let [name] = names.as_slice() else {
+                eprintln!(
+                    "Usage: {} check-ref-format [--normalize] [--allow-onelevel] [--refspec-pattern] <refname>",
+                    args[0]
+                );
+                return Ok(());
+            };
+            if branch {
+                if !is_valid_branch_name(name) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("'{}' is not a valid branch name", name),
+                    ));
+                }
+                println!("{}", name);
+                return Ok(());
+            }
+            // Normalizing drops leading slashes and collapses repeated ones
+            let name = match normalize {
+                true => {
+                    let mut normalized = String::new();
+                    for c in name.trim_start_matches('/').chars() {
+                        if !(c == '/' && normalized.ends_with('/')) {
+                            normalized.push(c);
+                        }
+                    }
+                    normalized
+                }
+                false => name.to_string(),
+            };
+            if !check_ref_format(&name, format) {
+                std::process::exit(1);
+            }
+            if normalize {
+                println!("{}", name);
+            }
+        }
+        "check-ignore" => {
+            let mut options = CheckIgnoreOptions::default();
+            let mut paths = Vec::new();
+            let mut from_stdin = false;
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "-v" | "--verbose" => options.verbose = true,
+                    "-q" | "--quiet" => options.quiet = true,
+                    "-n" | "--non-matching" => options.non_matching = true,
+                    "--no-index" => options.no_index = true,
+                    "--stdin" => from_stdin = true,
+                    path => paths.push(path.to_string()),
+                }
+            }
+            if from_stdin {
+                paths.extend(io::stdin().lines().collect::<io::Result<Vec<_>>>()?);
+            }
+            if paths.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no path specified",
+                ));
+            }
+            let repo = &Repository::discover()?;
+            if !check_ignore(repo, &paths, &options)? {
+                // Like git, nothing ignored is exit status 1 without a message
+                std::process::exit(1);
+            }
+        }
+        "show-index" => {
+            // Read a pack index from stdin and dump its entries
+            let mut index_data = Vec::new();
+            io::stdin().read_to_end(&mut index_data)?;
+            let index = pack_index::PackIndex::parse(&index_data)?;
+            for (hash, offset, crc32) in index.entries() {
+                println!("{} {} ({:08x})", offset, hash.to_hex(), crc32);
+            }
+        }
+        "index-pack" => {
+            let mut from_stdin = false;
+            let mut verify = false;
+            let mut index_path = None;
+            let mut pack_path = None;
+
+            let usage = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "usage: {} index-pack [--verify] [-o <index-file>] <pack-file>\n   \
+                         or: {} index-pack --stdin [-o <index-file>] [<pack-file>]",
+                        args[0], args[0]
+                    ),
+                )
+            };
+            let mut rest = args[2..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--stdin" => from_stdin = true,
+                    "--verify" => verify = true,
+                    "-o" => index_path = Some(Path::new(rest.next().ok_or_else(usage)?)),
+                    other if other.starts_with('-') => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("unknown index-pack option '{}'", other),
+                        ))
+                    }
+                    other if pack_path.is_none() => pack_path = Some(Path::new(other)),
+                    _ => return Err(usage()),
+                }
+            }
+
+            // --verify checks an existing pack and index, which --stdin has neither
+            // of, and a pack stored in the repository is always indexed next to it
+            let stored = from_stdin && pack_path.is_none();
+            if (pack_path.is_none() && !from_stdin)
+                || (verify && from_stdin)
+                || (stored && index_path.is_some())
+            {
+                return Err(usage());
+            }
 
-            clone_repository(repository_url, directory)?;
+            // A repository is only needed to store the pack or complete a thin one
+            let repo = Repository::discover().ok();
+            index_pack(repo.as_ref(), pack_path, index_path, from_stdin, verify)?;
+        }
+        "pack-objects" => {
+            // Pack the objects listed on stdin, one `<hash> [<path>]` per line
+            let stdout = args.iter().skip(2).any(|arg| arg == "--stdout");
+            let mut window = packfile::DEFAULT_WINDOW;
+            let mut depth = packfile::DEFAULT_DEPTH;
+            let mut base_name = None;
+            for arg in &args[2..] {
+                if let Some(value) = arg.strip_prefix("--window=") {
+                    window = value.parse().map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "Invalid --window")
+                    })?;
+                } else if let Some(value) = arg.strip_prefix("--depth=") {
+                    depth = value.parse().map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "Invalid --depth")
+                    })?;
+                } else if !arg.starts_with("--") {
+                    base_name = Some(arg.as_str());
+                }
+            }
+            if base_name.is_none() && !stdout {
+                eprintln!(
+                    "Usage: {} pack-objects [--window=<n>] [--depth=<n>] (--stdout | <base-name>)",
+                    args[0]
+                );
+                return Ok(());
+            }
+
+            let repo = &Repository::discover()?;
+            let mut objects = Vec::new();
+            for line in io::stdin().lock().lines() {
+                let line = line?;
+                let (hash, path) = line.split_once(' ').unwrap_or((&line, ""));
+                objects.push((Hash::from_hex(hash)?, path.to_string()));
+            }
+            let pack = packfile::pack_objects(&objects, window, depth, true, |hash| {
+                read_raw_object(repo, hash)
+            })?;
+            match base_name {
+                Some(base_name) if !stdout => {
+                    let (index, checksum) =
+                        pack_index::index_pack(&pack, |hash| find_delta_base(repo, hash))?;
+                    let base_name = format!("{}-{}", base_name, checksum.to_hex());
+                    write_file_atomically(Path::new(&format!("{}.pack", base_name)), &pack)?;
+                    write_file_atomically(Path::new(&format!("{}.idx", base_name)), &index)?;
+                    println!("{}", checksum.to_hex());
+                }
+                _ => io::stdout().write_all(&pack)?,
+            }
+        }
+        "repack" => {
+            let all = args.iter().skip(2).any(|arg| arg == "-a" || arg == "-ad");
+            let delete = args.iter().skip(2).any(|arg| arg == "-d" || arg == "-ad");
+            gc::repack(&Repository::discover()?, all, delete)?;
+        }
+        "fsck" => fsck::fsck(&Repository::discover()?)?,
+        "gc" => {
+            let repo = &Repository::discover()?;
+            let config = repo.config()?;
+            let prune = args
+                .iter()
+                .skip(2)
+                .find_map(|arg| arg.strip_prefix("--prune="))
+                .or(config.get("gc.pruneExpire"))
+                .unwrap_or("2.weeks.ago");
+            gc::gc(repo, gc::parse_expiry(prune)?)?;
+        }
+        "unpack-objects" => {
+            // Read a pack from stdin and store every object as a loose object
+            let mut pack_data = Vec::new();
+            io::stdin().read_to_end(&mut pack_data)?;
+            unpack_objects(&Repository::discover()?, &pack_data)?;
         }
 
         _ => {
@@ -219,96 +824,511 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
-fn clone_repository(repository_url: &str, directory: &str) -> io::Result<()> {
-    // Step 1: Create the local directory if it doesn't exist
-    if !Path::new(directory).exists() {
-        fs::create_dir_all(directory)?;
+fn clone_repository(repository_url: &str, directory: &str, branch: Option<&str>) -> io::Result<()> {
+    // Step 1: Create the local directory and initialize it as git repository.
+    // Like git, only a new or empty directory will do.
+    let occupied = match fs::read_dir(directory) {
+        Ok(mut entries) => entries.next().is_some(),
+        Err(e) => e.kind() != io::ErrorKind::NotFound,
+    };
+    if occupied {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "destination path '{}' already exists and is not an empty directory.",
+                directory
+            ),
+        ));
+    }
+    let (repo, _) = Repository::init(Path::new(directory), &InitOptions::default())?;
+    let repo = &repo;
+    config::set(&repo.path("config"), "remote.origin.url", repository_url)?;
+    config::set(
+        &repo.path("config"),
+        "remote.origin.fetch",
+        "+refs/heads/*:refs/remotes/origin/*",
+    )?;
+
+    // Step 2: Discover the refs the remote repository advertises
+    let advertisement =
+        transport::discover_refs(repository_url, &["HEAD", "refs/heads/", "refs/tags/"])?;
+    let mut wants: Vec<Hash> = advertisement
+        .refs
+        .iter()
+        .filter(|remote_ref| remote_ref.name != "HEAD")
+        .map(|remote_ref| remote_ref.hash)
+        .collect();
+    wants.sort();
+    wants.dedup();
+    if wants.is_empty() {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+        return Ok(());
+    }
+
+    // Step 3: Fetch the objects from the remote repository
+    let pack_data = transport::fetch_pack(repository_url, &advertisement, &wants, &[])?;
+    store_pack(repo, &pack_data)?;
+
+    // Step 4: Record the remote branches and tags
+    for remote_ref in &advertisement.refs {
+        if let Some(branch) = remote_ref.name.strip_prefix("refs/heads/") {
+            write_ref(
+                repo,
+                &format!("refs/remotes/origin/{}", branch),
+                &remote_ref.hash,
+            )?;
+        } else if remote_ref.name.starts_with("refs/tags/") {
+            write_ref(repo, &remote_ref.name, &remote_ref.hash)?;
+        }
+    }
+
+    // Step 5: Find the remote default branch, the one its HEAD points to
+    let default_branch = advertisement
+        .refs
+        .iter()
+        .find(|remote_ref| remote_ref.name == "HEAD")
+        .and_then(|head| {
+            head.symref_target.clone().or_else(|| {
+                // Old servers do not report symrefs; guess from the hash like git does
+                let candidates = advertisement.refs.iter().filter(|remote_ref| {
+                    remote_ref.name.starts_with("refs/heads/") && remote_ref.hash == head.hash
+                });
+                candidates
+                    .clone()
+                    .find(|remote_ref| remote_ref.name == "refs/heads/master")
+                    .or(candidates.clone().next())
+                    .map(|remote_ref| remote_ref.name.clone())
+            })
+        });
+    if let Some(default_branch) = default_branch
+        .as_deref()
+        .and_then(|name| name.strip_prefix("refs/heads/"))
+    {
+        write_symbolic_ref(
+            repo,
+            "refs/remotes/origin/HEAD",
+            &format!("refs/remotes/origin/{}", default_branch),
+        )?;
+    }
+
+    // Step 6: Create the local branch and check it out
+    let branch_ref = match branch {
+        Some(branch) => format!("refs/heads/{}", branch),
+        None => default_branch.ok_or_else(|| {
+            io::Error::other("Remote HEAD does not point to a branch, use -b <branch>")
+        })?,
+    };
+    let head = advertisement
+        .refs
+        .iter()
+        .find(|remote_ref| remote_ref.name == branch_ref)
+        .map(|remote_ref| remote_ref.hash)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Remote branch {} not found in upstream origin", branch_ref),
+            )
+        })?;
+    let branch_name = branch_ref.trim_start_matches("refs/heads/");
+
+    let tree = match read_object(repo, &head)? {
+        Object::Commit(commit) => commit.tree,
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected a commit, found a {}", other.kind()),
+            ))
+        }
+    };
+    checkout::switch_trees(repo, None, &tree, false)?;
+    write_ref(repo, &branch_ref, &head)?;
+    write_symbolic_ref(repo, "HEAD", &branch_ref)?;
+    set_upstream(repo, branch_name, "origin", &branch_ref)?;
+
+    println!("Cloned repository from {} to {}", repository_url, directory);
+    Ok(())
+}
+
+/// `update-ref`: point `name` at `new`, or delete it when `new` is `None`,
+/// checking it holds `old` first if given. Values may be hashes or ref
+/// names; an empty or all-zero value stands for no ref at all.
+fn update_ref_command(
+    repo: &Repository,
+    name: &str,
+    new: Option<&str>,
+    old: Option<&str>,
+    deref: bool,
+) -> io::Result<()> {
+    let value = |value: &str| -> io::Result<Option<Hash>> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+        let hash = match Hash::from_hex(value) {
+            Ok(hash) => Some(hash),
+            Err(_) => match refs::expand_ref(repo, value)? {
+                Some(full_name) => read_ref(repo, &full_name)?,
+                None => None,
+            },
+        };
+        match hash {
+            Some(hash) if hash == Hash::zero() => Ok(None),
+            Some(hash) => Ok(Some(hash)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: not a valid SHA1", value),
+            )),
+        }
+    };
+    let new = new.map(value).transpose()?.flatten();
+    let old = old.map(value).transpose()?;
+
+    if let Some(hash) = &new {
+        if !has_object(repo, hash) {
+            return Err(io::Error::other(format!(
+                "update_ref failed for ref '{}': trying to write ref '{}' with nonexistent object {}",
+                name,
+                name,
+                hash.to_hex()
+            )));
+        }
+        if name.starts_with("refs/heads/") && read_object(repo, hash)?.kind() != ObjectKind::Commit
+        {
+            return Err(io::Error::other(format!(
+                "update_ref failed for ref '{}': trying to write non-commit object {} to branch '{}'",
+                name,
+                hash.to_hex(),
+                name
+            )));
+        }
     }
+    refs::update_ref(repo, name, new.as_ref(), old, deref)
+}
 
-    // Step 2: Initialize the directory as git repository
-    let output = Command::new("git").arg("init").arg(directory).output()?;
+/// Record `merge` on `remote` as the branch `branch` pulls from.
+fn set_upstream(repo: &Repository, branch: &str, remote: &str, merge: &str) -> io::Result<()> {
+    let path = repo.path("config");
+    config::set(&path, &format!("branch.{}.remote", branch), remote)?;
+    config::set(&path, &format!("branch.{}.merge", branch), merge)
+}
 
-    if !output.status.success() {
-        eprintln!(
-            "Failed to initialize the directory as git repository : {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to initialize the directory as git repository",
-        ));
+/// URL of a configured remote; a URL may also be given directly.
+fn remote_url(config: &config::Config, remote: &str) -> io::Result<String> {
+    match config.get(&format!("remote.{}.url", remote)) {
+        Some(url) => Ok(url.to_string()),
+        None if remote.contains("://") => Ok(remote.to_string()),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{}' does not appear to be a git repository", remote),
+        )),
     }
+}
 
-    // Step 3: Add the remote repository as origin
-    let output = Command::new("git")
-        .arg("-C")
-        .arg(directory)
-        .arg("remote")
-        .arg("add")
-        .arg("origin")
-        .arg(repository_url)
-        .output()?;
-
-    if !output.status.success() {
-        eprintln!(
-            "Failed to add the remote repository as origin : {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to add the remote repository as origin",
-        ));
+/// Objects reachable from `tips` but not from `known`, whose whole history
+/// is assumed present, with the paths trees and blobs were found at.
+fn list_objects(
+    repo: &Repository,
+    tips: &[Hash],
+    known: &[Hash],
+) -> io::Result<Vec<(Hash, String)>> {
+    // Everything in the history of the known tips is already there
+    let mut known_commits = HashSet::new();
+    let mut queue: VecDeque<Hash> = known.iter().copied().collect();
+    while let Some(hash) = queue.pop_front() {
+        if !known_commits.insert(hash) {
+            continue;
+        }
+        match read_object(repo, &hash)? {
+            Object::Commit(commit) => queue.extend(commit.parents),
+            Object::Tag(tag) => queue.push_back(tag.object),
+            _ => {}
+        }
     }
 
-    // Step 4: Fetch the objects from the remote repository
-    let output = Command::new("git")
-        .arg("-C")
-        .arg(directory)
-        .arg("fetch")
-        .arg("origin")
-        .output()?;
-    if !output.status.success() {
-        eprintln!(
-            "Failed to fetch the objects from the remote repository : {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to fetch the objects from the remote repository",
-        ));
+    // New commits and tags, collecting the trees (or blobs) they point at
+    let mut objects = Vec::new();
+    let mut roots = Vec::new();
+    let mut boundary = Vec::new();
+    let mut seen = HashSet::new();
+    let mut queue: VecDeque<Hash> = tips.iter().copied().collect();
+    while let Some(hash) = queue.pop_front() {
+        if known_commits.contains(&hash) || !seen.insert(hash) {
+            continue;
+        }
+        match read_object(repo, &hash)? {
+            Object::Commit(commit) => {
+                objects.push((hash, String::new()));
+                roots.push(commit.tree);
+                for parent in commit.parents {
+                    if known_commits.contains(&parent) {
+                        boundary.push(parent);
+                    } else {
+                        queue.push_back(parent);
+                    }
+                }
+            }
+            Object::Tag(tag) => {
+                objects.push((hash, String::new()));
+                queue.push_back(tag.object);
+            }
+            _ => roots.push(hash),
+        }
+    }
+
+    // Trees and blobs of the commits we build on are already there too
+    let mut known_objects = HashSet::new();
+    for commit in boundary {
+        if let Object::Commit(commit) = read_object(repo, &commit)? {
+            collect_tree(repo, &commit.tree, "", &mut known_objects, &mut Vec::new())?;
+        }
+    }
+    for root in roots {
+        collect_tree(repo, &root, "", &mut known_objects, &mut objects)?;
+    }
+    Ok(objects)
+}
+
+/// Add `hash`, found at `path`, and for a tree everything below it to
+/// `objects`, skipping anything already in `seen`.
+fn collect_tree(
+    repo: &Repository,
+    hash: &Hash,
+    path: &str,
+    seen: &mut HashSet<Hash>,
+    objects: &mut Vec<(Hash, String)>,
+) -> io::Result<()> {
+    if !seen.insert(*hash) {
+        return Ok(());
+    }
+    objects.push((*hash, path.to_string()));
+    if let Object::Tree(entries) = read_object(repo, hash)? {
+        for entry in entries {
+            let name = entry.name.to_string_lossy();
+            let child = if path.is_empty() {
+                name.into_owned()
+            } else {
+                format!("{}/{}", path, name)
+            };
+            match entry.mode.as_str() {
+                "40000" => collect_tree(repo, &entry.hash, &child, seen, objects)?,
+                // Submodule commits live in another repository
+                "160000" => {}
+                _ => {
+                    if seen.insert(entry.hash) {
+                        objects.push((entry.hash, child));
+                    }
+                }
+            }
+        }
     }
+    Ok(())
+}
 
-    // Step 5: Checkout the master branch
-    let output = Command::new("git")
-        .arg("-C")
-        .arg(directory)
-        .arg("checkout")
-        .arg("origin/master")
-        .output()?;
+fn short_hash(hash: &Hash) -> String {
+    hash.to_hex()[..7].to_string()
+}
+
+/// Whether `ancestor` is reachable from `descendant` through parent links.
+fn is_ancestor(repo: &Repository, ancestor: &Hash, descendant: &Hash) -> io::Result<bool> {
+    let mut queue = VecDeque::from([*descendant]);
+    let mut seen = HashSet::new();
+    while let Some(hash) = queue.pop_front() {
+        if hash == *ancestor {
+            return Ok(true);
+        }
+        if !seen.insert(hash) {
+            continue;
+        }
+        if let Object::Commit(commit) = read_object(repo, &hash)? {
+            queue.extend(commit.parents);
+        }
+    }
+    Ok(false)
+}
 
-    if !output.status.success() {
-        eprintln!(
-            "Failed to checkout the master branch : {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+/// Flatten a tree into `path -> (mode, hash)` for every non-tree entry.
+fn read_tree(
+    repo: &Repository,
+    hash: &Hash,
+    prefix: &str,
+    entries: &mut BTreeMap<String, (u32, Hash)>,
+) -> io::Result<()> {
+    let Object::Tree(tree) = read_object(repo, hash)? else {
         return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to checkout the master branch",
+            io::ErrorKind::InvalidData,
+            format!("{} is not a tree", hash.to_hex()),
         ));
+    };
+    for entry in tree {
+        // Names that could escape the work tree or reach into .git are
+        // refused, since trees may come from anyone
+        if objects::verify_path(entry.name.as_bytes()).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid path '{}{}'", prefix, entry.name.to_string_lossy()),
+            ));
+        }
+        // Index paths are UTF-8; a lossy name would check out or compare as
+        // a different file
+        let name = entry.name.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("path is not valid UTF-8: {}{:?}", prefix, entry.name),
+            )
+        })?;
+        let path = format!("{}{}", prefix, name);
+        if entry.mode == "40000" {
+            read_tree(repo, &entry.hash, &format!("{}/", path), entries)?;
+        } else {
+            let mode = u32::from_str_radix(&entry.mode, 8).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad mode {} for {}", entry.mode, path),
+                )
+            })?;
+            entries.insert(path, (mode, entry.hash));
+        }
     }
+    Ok(())
+}
 
-    println!("Cloned repository from {} to {}", repository_url, directory);
+fn unpack_objects(repo: &Repository, pack_data: &[u8]) -> io::Result<()> {
+    let pack = packfile::parse_pack(pack_data)?;
+    let objects = delta::resolve_pack(&pack.entries, |hash| find_delta_base(repo, hash))?;
+    for object in &objects {
+        write_object(repo, &object.hash, &with_header(object.kind, &object.data))?;
+    }
     Ok(())
 }
 
-fn write_commit_object(commit_data: &str) -> io::Result<Hash> {
-    let commit_bytes = commit_data.as_bytes();
-    let header = format!("commit {}\0", commit_bytes.len());
-    let full_commit_data = [header.as_bytes(), commit_bytes].concat();
+fn index_pack(
+    repo: Option<&Repository>,
+    pack_path: Option<&Path>,
+    index_path: Option<&Path>,
+    from_stdin: bool,
+    verify: bool,
+) -> io::Result<()> {
+    let find_base = |hash: &Hash| match repo {
+        Some(repo) => find_delta_base(repo, hash),
+        None => Ok(None),
+    };
+    if from_stdin {
+        let mut pack_data = Vec::new();
+        io::stdin().read_to_end(&mut pack_data)?;
+        let checksum = match pack_path {
+            Some(pack_path) => {
+                let (index, checksum) = pack_index::index_pack(&pack_data, find_base)?;
+                write_file_atomically(pack_path, &pack_data)?;
+                let index_path = index_path
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|| pack_path.with_extension("idx"));
+                write_file_atomically(&index_path, &index)?;
+                checksum
+            }
+            None => {
+                let repo = repo.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "not a git repository")
+                })?;
+                store_pack(repo, &pack_data)?
+            }
+        };
+        println!("pack\t{}", checksum.to_hex());
+        return Ok(());
+    }
 
-    let hash = compute_sha1(&full_commit_data);
-    write_object(&hash, &full_commit_data)?;
-    Ok(hash)
+    let pack_path = pack_path.expect("pack path is required without --stdin");
+    let pack_data = fs::read(pack_path)?;
+    let (index, checksum) = pack_index::index_pack(&pack_data, find_base)?;
+    let index_path = index_path
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| pack_path.with_extension("idx"));
+
+    if verify {
+        if fs::read(&index_path)? != index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not match its pack", index_path.display()),
+            ));
+        }
+    } else {
+        write_file_atomically(&index_path, &index)?;
+    }
+    println!("{}", checksum.to_hex());
+    Ok(())
+}
+
+/// Every loose object, with the path of its file.
+fn loose_objects(repo: &Repository) -> io::Result<Vec<(Hash, std::path::PathBuf)>> {
+    let mut objects = Vec::new();
+    for dir in fs::read_dir(repo.objects_dir())? {
+        let dir = dir?;
+        let prefix = dir.file_name().to_string_lossy().to_string();
+        if prefix.len() != 2 || !dir.file_type()?.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            let name = format!("{}{}", prefix, file.file_name().to_string_lossy());
+            // Skip temporary files and anything else that is not an object
+            if let Ok(hash) = Hash::from_hex(&name) {
+                objects.push((hash, file.path()));
+            }
+        }
+    }
+    Ok(objects)
+}
+
+/// Index a pack and store both files under `.git/objects/pack`.
+fn store_pack(repo: &Repository, pack_data: &[u8]) -> io::Result<Hash> {
+    let (index, checksum) = pack_index::index_pack(pack_data, |hash| find_delta_base(repo, hash))?;
+    let pack_dir = repo.pack_dir();
+    fs::create_dir_all(&pack_dir)?;
+    let base_name = format!("pack-{}", checksum.to_hex());
+    // Packs are found through their index, so the pack goes in first
+    write_file_atomically(&pack_dir.join(format!("{}.pack", base_name)), pack_data)?;
+    write_file_atomically(&pack_dir.join(format!("{}.idx", base_name)), &index)?;
+    repo.reload_packs()?;
+    Ok(checksum)
+}
+
+/// Whether `e` is ENOTDIR, from a path whose leading part is a file rather
+/// than a directory. `io::ErrorKind::NotADirectory` needs a newer Rust than
+/// we support, so the raw code is checked; ENOTDIR is 20 on Unix.
+fn is_not_a_directory(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(20)
+}
+
+/// Write `data` to a temporary file next to `path` and rename it into
+/// place, so nothing ever reads a partially written file.
+fn write_file_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_name = OsString::from(format!("tmp_{}_", std::process::id()));
+    temp_name.push(path.file_name().unwrap_or_default());
+    let temp_path = path.with_file_name(temp_name);
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Look up a REF_DELTA base that is not part of the pack being read.
+fn find_delta_base(repo: &Repository, hash: &Hash) -> io::Result<Option<(ObjectKind, Vec<u8>)>> {
+    match read_raw_object(repo, hash) {
+        Ok(object) => Ok(Some(object)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_commit_object(repo: &Repository, commit: &Commit) -> io::Result<Hash> {
+    store_object(repo, &Object::Commit(commit.clone()))
 }
 
 fn get_current_time() -> String {
@@ -317,76 +1337,291 @@ fn get_current_time() -> String {
 }
 
 fn create_commit_object(
-    tree_sha: &str,
-    parent_sha: Option<&String>,
+    tree_sha: Hash,
+    parent_sha: Option<Hash>,
     author: &str,
     committer: &str,
     message: &str,
-) -> String {
-    let mut commit_data = format!("tree {}\n", tree_sha);
+) -> Commit {
+    Commit {
+        tree: tree_sha,
+        parents: parent_sha.into_iter().collect(),
+        author: format!("{} {}", author, get_current_time()).into_bytes(),
+        committer: format!("{} {}", committer, get_current_time()).into_bytes(),
+        extra_headers: Vec::new(),
+        message: format!("{}\n", message).into_bytes(),
+    }
+}
 
-    if let Some(parent) = parent_sha {
-        commit_data.push_str(&format!("parent {}\n", parent));
+/// Resolve a command line path to its path in the index, relative to the
+/// top of the work tree and `/` separated.
+fn index_path(repo: &Repository, arg: &str) -> io::Result<String> {
+    // Resolve `.` and `..` by hand, as the path need not exist
+    fn normalize(path: &Path) -> PathBuf {
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                component => normalized.push(component),
+            }
+        }
+        normalized
     }
 
-    commit_data.push_str(&format!("author {} {}\n", author, get_current_time()));
-    commit_data.push_str(&format!("committer {} {}\n", committer, get_current_time()));
-    commit_data.push_str(&format!("\n{}\n", message));
+    let cwd = env::current_dir()?;
+    let work_tree = normalize(&cwd.join(repo.work_tree()?));
+    let path = normalize(&cwd.join(arg));
+    let relative = path.strip_prefix(&work_tree).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}: '{}' is outside repository at '{}'",
+                arg,
+                arg,
+                work_tree.display()
+            ),
+        )
+    })?;
+    let names = relative
+        .iter()
+        .map(|name| {
+            name.to_str().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "path is not valid UTF-8")
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(names.join("/"))
+}
 
-    commit_data
+#[derive(Debug, Default)]
+struct CheckIgnoreOptions {
+    /// Show the pattern that decided each path.
+    verbose: bool,
+    quiet: bool,
+    /// Also list paths no pattern matched.
+    non_matching: bool,
+    /// Check tracked files too.
+    no_index: bool,
 }
 
-fn write_tree(path: &Path) -> io::Result<Hash> {
+/// Print which of `paths` are ignored, returning whether any were. With
+/// `verbose`, paths re-included by a negated pattern are shown (and count)
+/// too, so the pattern can be seen.
+fn check_ignore(
+    repo: &Repository,
+    paths: &[String],
+    options: &CheckIgnoreOptions,
+) -> io::Result<bool> {
+    let work_tree = repo.work_tree()?;
+    let index = if options.no_index {
+        Index::default()
+    } else {
+        Index::load(&repo.path("index"))?
+    };
+    let mut ignore = Ignore::new(repo)?;
+
+    let mut any_ignored = false;
+    for arg in paths {
+        let path = index_path(repo, arg)?;
+        let pattern = if path.is_empty() || index.get(&path).is_some() {
+            None
+        } else {
+            let is_dir = work_tree.join(&path).is_dir();
+            ignore
+                .matching(&path, is_dir)?
+                .filter(|pattern| options.verbose || !pattern.negated)
+        };
+        any_ignored |= pattern.is_some();
+        if options.quiet {
+            continue;
+        }
+        match (&pattern, options.verbose) {
+            (Some(pattern), true) => println!(
+                "{}:{}:{}\t{}",
+                pattern.source, pattern.line, pattern.text, arg
+            ),
+            (Some(_), false) => println!("{}", arg),
+            (None, true) if options.non_matching => println!("::\t{}", arg),
+            (None, false) if options.non_matching => println!("{}", arg),
+            (None, _) => {}
+        }
+    }
+    Ok(any_ignored)
+}
+
+/// Write the trees for the staged entries, returning the tree for the
+/// directory `prefix` (the root when empty). Unless `missing_ok` is set,
+/// every blob the index refers to must already be stored.
+fn write_index_tree(
+    repo: &Repository,
+    index: &Index,
+    prefix: &str,
+    missing_ok: bool,
+) -> io::Result<Hash> {
+    let error_building_trees = || io::Error::other("git-write-tree: error building trees");
+
+    let mut problems = Vec::new();
+    for entry in index.entries() {
+        if entry.stage != 0 {
+            problems.push(format!(
+                "{}: unmerged ({})",
+                entry.path,
+                entry.hash.to_hex()
+            ));
+        } else if !missing_ok
+            && entry.mode != 0o160000
+            && !entry.is_intent_to_add()
+            && !has_object(repo, &entry.hash)
+        {
+            problems.push(format!(
+                "invalid object {:o} {} for '{}'",
+                entry.mode,
+                entry.hash.to_hex(),
+                entry.path
+            ));
+        }
+    }
+    if !problems.is_empty() {
+        for problem in problems {
+            eprintln!("error: {}", problem);
+        }
+        return Err(error_building_trees());
+    }
+
+    let prefix = prefix.trim_end_matches('/');
+    let dir = if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", prefix)
+    };
+    let entries: Vec<&IndexEntry> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.path.starts_with(&dir) && !entry.is_intent_to_add())
+        .collect();
+    if entries.is_empty() && !dir.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("git-write-tree: prefix {} not found", prefix),
+        ));
+    }
+    write_index_subtree(repo, &entries, &dir)
+}
+
+/// Write the tree for `entries`, which all lie below `dir` (empty or
+/// ending in `/`) and are sorted by path, so each subdirectory is a run.
+fn write_index_subtree(repo: &Repository, entries: &[&IndexEntry], dir: &str) -> io::Result<Hash> {
+    let mut tree = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let name = &entries[i].path[dir.len()..];
+        match name.split_once('/') {
+            Some((subdir, _)) => {
+                let subdir = format!("{}{}/", dir, subdir);
+                let end = i + entries[i..]
+                    .iter()
+                    .take_while(|entry| entry.path.starts_with(&subdir))
+                    .count();
+                let hash = write_index_subtree(repo, &entries[i..end], &subdir)?;
+                tree.push(GitTreeEntry {
+                    mode: "40000".to_string(),
+                    name: subdir[dir.len()..subdir.len() - 1].into(),
+                    hash,
+                });
+                i = end;
+            }
+            None => {
+                tree.push(GitTreeEntry {
+                    mode: format!("{:o}", entries[i].mode),
+                    name: name.into(),
+                    hash: entries[i].hash,
+                });
+                i += 1;
+            }
+        }
+    }
+
+    // Index order is by full path; trees order directories as if they
+    // ended in `/`
+    tree.sort_by(objects::tree_order);
+    store_object(repo, &Object::Tree(tree))
+}
+
+/// Write a tree of the files in the work tree, leaving out ignored ones.
+fn write_tree(repo: &Repository) -> io::Result<Hash> {
+    let mut ignore = Ignore::new(repo)?;
+    match write_work_tree_dir(repo, &mut ignore, repo.work_tree()?, "")? {
+        Some(hash) => Ok(hash),
+        None => store_object(repo, &Object::Tree(Vec::new())),
+    }
+}
+
+/// Write the tree for the work tree directory at `path`, known to the
+/// ignore rules as `dir` (empty or ending in `/`); `None` if nothing in it is
+/// kept, as git has no empty trees.
+fn write_work_tree_dir(
+    repo: &Repository,
+    ignore: &mut Ignore,
+    path: &Path,
+    dir: &str,
+) -> io::Result<Option<Hash>> {
     let mut entries = Vec::new();
 
     // Iterate over the files/directories in the working directory
     for entry in fs::read_dir(path)? {
         let entry = entry?;
         let path = entry.path();
-        let file_name = entry.file_name().into_string().unwrap();
-
-        if path.is_file() {
-            let hash = create_blob(&path)?;
-            entries.push(GitTreeEntry {
-                mode: "100644".to_string(),
-                name: file_name,
-                hash,
-            });
-        } else if path.is_dir() && file_name != ".git" {
-            let hash = write_tree(&path)?;
+        let file_name = entry.file_name();
+        // Names that are not UTF-8 can only match patterns approximately
+        let relative = format!("{}{}", dir, file_name.to_string_lossy());
+        let metadata = fs::symlink_metadata(&path)?;
+
+        if !metadata.is_dir() {
+            if ignore.is_ignored(&relative, false)? {
+                continue;
+            }
+            let hash = store_work_file(repo, &path, &metadata)?;
             entries.push(GitTreeEntry {
-                mode: "40000".to_string(),
+                mode: format!("{:o}", work_tree_mode(&metadata)),
                 name: file_name,
                 hash,
             });
+        } else if file_name != *".git" {
+            if ignore.is_ignored(&relative, true)? {
+                continue;
+            }
+            if let Some(nested) = Repository::at_work_tree(&path) {
+                // Nested repositories without commits are left out, as git does
+                if let Some(commit) = read_ref(&nested, "HEAD")? {
+                    entries.push(GitTreeEntry {
+                        mode: "160000".to_string(),
+                        name: file_name,
+                        hash: commit,
+                    });
+                }
+                continue;
+            }
+            if let Some(hash) = write_work_tree_dir(repo, ignore, &path, &format!("{}/", relative))?
+            {
+                entries.push(GitTreeEntry {
+                    mode: "40000".to_string(),
+                    name: file_name,
+                    hash,
+                });
+            }
         }
     }
-
-    // Sort the entries by name
-    entries.sort_by(|a, b| a.name.cmp(&b.name));
-
-    // Create the tree object
-    let mut tree_data = Vec::new();
-    for entry in entries {
-        let mode = entry.mode;
-        let name = entry.name;
-        let hash = entry.hash.to_hex();
-        tree_data.extend_from_slice(mode.as_bytes());
-        tree_data.push(b' ');
-        tree_data.extend_from_slice(name.as_bytes());
-        tree_data.push(0);
-        tree_data.extend_from_slice(&hex::decode(hash).unwrap());
+    if entries.is_empty() {
+        return Ok(None);
     }
 
-    // Add the tree header
-    let header = format!("tree {}\0", tree_data.len());
-    let mut result = Vec::from(header.as_bytes());
-    result.extend_from_slice(&tree_data);
+    // Sort the entries the way git does, directories as if ending in `/`
+    entries.sort_by(objects::tree_order);
 
-    // Compute the SHA-1 hash of the tree_data
-    let hash = compute_sha1(&result);
-    write_object(&hash, &result)?;
-    Ok(hash)
+    store_object(repo, &Object::Tree(entries)).map(Some)
 }
 
 fn compute_sha1(data: &[u8]) -> Hash {
@@ -395,117 +1630,228 @@ fn compute_sha1(data: &[u8]) -> Hash {
     Hash::from_bytes(&hasher.finalize()).unwrap()
 }
 
-fn create_blob(path: &Path) -> io::Result<Hash> {
+/// The mode a work tree file is recorded with.
+fn work_tree_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.file_type().is_symlink() {
+        0o120000
+    } else if metadata.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+/// The blob contents of a work tree file; for a symlink that is the path it
+/// points to, not the file there.
+fn read_work_file(path: &Path, metadata: &fs::Metadata) -> io::Result<Vec<u8>> {
+    if metadata.file_type().is_symlink() {
+        Ok(fs::read_link(path)?.into_os_string().into_encoded_bytes())
+    } else {
+        fs::read(path)
+    }
+}
+
+fn store_work_file(repo: &Repository, path: &Path, metadata: &fs::Metadata) -> io::Result<Hash> {
+    store_object(repo, &Object::Blob(read_work_file(path, metadata)?))
+}
+
+/// Check out a blob as a file of the given mode, replacing any file or
+/// symlink already at `path`.
+fn write_work_file(path: &Path, mode: u32, contents: &[u8]) -> io::Result<()> {
+    if fs::symlink_metadata(path).is_ok_and(|metadata| !metadata.is_dir()) {
+        fs::remove_file(path)?;
+    }
+    if mode == 0o120000 {
+        let target = OsStr::from_bytes(contents);
+        return symlink(target, path);
+    }
+
+    fs::write(path, contents)?;
+    let mut permissions = fs::metadata(path)?.permissions();
+    let bits = permissions.mode();
+    // Executable by whoever may read it, like a new file under the umask
+    permissions.set_mode(if mode == 0o100755 {
+        bits | (bits & 0o444) >> 2
+    } else {
+        bits & !0o111
+    });
+    fs::set_permissions(path, permissions)
+}
+
+fn create_blob(repo: &Repository, path: &Path) -> io::Result<Hash> {
     // Read the file content
-    let mut file = fs::File::open(path)?;
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents)?;
-
-    // Create the blob data
-    let header = format!("blob {}", contents.len());
-    let mut result = Vec::from(header.as_bytes());
-    result.push(0); // null byte
-    result.extend_from_slice(&contents);
-
-    // Compute the SHA-1 hash of the blob_data
-    let hash = compute_sha1(&result);
-    write_object(&hash, &result)?;
+    let contents = fs::read(path)?;
+    store_object(repo, &Object::Blob(contents))
+}
+
+/// Serialize, hash and write an object, returning its hash.
+fn store_object(repo: &Repository, object: &Object) -> io::Result<Hash> {
+    let data = object.serialize();
+    let hash = compute_sha1(&data);
+    write_object(repo, &hash, &data)?;
     Ok(hash)
 }
 
-fn write_object(hash: &Hash, data: &[u8]) -> io::Result<()> {
+/// Store `data` as a loose object, returning the path of its file.
+fn write_object(repo: &Repository, hash: &Hash, data: &[u8]) -> io::Result<PathBuf> {
     let hash_hex = hash.to_hex();
     let (dir, file) = hash_hex.split_at(2);
-    let object_dir = format!(".git/objects/{}", dir);
+    let object_dir = repo.objects_dir().join(dir);
     fs::create_dir_all(&object_dir)?;
 
     let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
     encoder.write_all(data)?;
     let compressed_data = encoder.finish()?;
-    let object_path = format!("{}/{}", object_dir, file);
-    fs::write(object_path, compressed_data)?;
-    Ok(())
+    let object_path = object_dir.join(file);
+    fs::write(&object_path, compressed_data)?;
+    Ok(object_path)
 }
 
-fn extract_names_from_tree_entries(tree_object: &[u8]) -> Vec<String> {
-    let mut names = Vec::new();
-    let mut i = 0;
-
-    //tree <size>\0<mode> <name>\0<20_byte_sha><mode> <name>\0<20_byte_sha>
-
-    // Skip the tree header
-    while i < tree_object.len() {
-        if let Some(null_pos) = tree_object[i..].iter().position(|&b| b == b'\0') {
-            i = null_pos + 1;
-            break;
+/// Read and decompress a stored object, header included.
+///
+/// Loose objects are tried first, then every pack under `.git/objects/pack`.
+fn read_object_data(repo: &Repository, hash: &Hash) -> io::Result<Vec<u8>> {
+    //object directory is in form of .git/objects/[first 2 hash digits]/[remaining hash digits after that]
+    //ex - .git/objects/e8/8f7a929cd70b0274c4ea33b209c97fa845fdbc
+    let hash_hex = hash.to_hex();
+    let (dir, file) = hash_hex.split_at(2);
+    let path = repo.objects_dir().join(dir).join(file);
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return match read_packed_object(repo, hash)? {
+                Some((kind, body)) => Ok(with_header(kind, &body)),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Object {} not found", hash_hex),
+                )),
+            };
         }
-        i += 1;
-    }
+        Err(e) => return Err(e),
+    };
 
-    // <mode> <name>\0<20_byte_sha><mode> <name>\0<20_byte_sha>
+    let mut decoder = ZlibDecoder::new(&content[..]);
+    let mut data = Vec::new();
+    decoder.read_to_end(&mut data)?;
+    Ok(data)
+}
 
-    while i < tree_object.len() {
-        // Find the null byte that separates the mode and the name
-        if let Some(null_pos) = tree_object[i..].iter().position(|&b| b == b'\0') {
-            // Extract the mode and the name parts
-            // <mode> <name>
-            let entry = &tree_object[i..i + null_pos];
-            if let Some(space_pos) = entry.iter().position(|&b| b == b' ') {
-                // Extract the name part
-                // <name>
-                let name = &entry[space_pos + 1..];
-                if let Ok(name) = std::str::from_utf8(name) {
-                    // check if the entry is a tree or a blob
-                    names.push(name.to_string());
-                }
-            }
-            // Move to the next entry in the tree object
-            i += null_pos + 20 + 1; // 20 bytes for the SHA-1 hash + 1 for the null byte
-        } else {
-            break;
-        }
+fn read_packed_object(repo: &Repository, hash: &Hash) -> io::Result<Option<(ObjectKind, Vec<u8>)>> {
+    let find = |packs: &[Rc<Pack>]| {
+        packs
+            .iter()
+            .find_map(|pack| Some((pack.clone(), pack.index.find(hash)?)))
+    };
+    // A pack written by someone else since the packs were loaded may hold it
+    let found = match find(&repo.packs()?) {
+        Some(found) => Some(found),
+        None => find(&repo.reload_packs()?),
+    };
+    match found {
+        Some((pack, offset)) => pack
+            .read(offset, |base| read_raw_object(repo, base))
+            .map(Some),
+        None => Ok(None),
     }
-    names
 }
 
-fn cat_file(blob_sha: &str) -> io::Result<()> {
-    // Step 1: identify the file & read from it
-    //object directory is in form of .git/objects/[first 2 hash digits]/[remaining hash digits after that]
-    //ex - .git/objects/e8/8f7a929cd70b0274c4ea33b209c97fa845fdbc
-
-    let (dir, file) = blob_sha.split_at(2);
-    let path = format!(".git/objects/{}/{}", dir, file);
-    let content = fs::read(path)?;
+/// The type and body of a stored object.
+fn read_raw_object(repo: &Repository, hash: &Hash) -> io::Result<(ObjectKind, Vec<u8>)> {
+    let data = read_object_data(repo, hash)?;
+    let (header, body) = GitObjectHeader::from_bytes(&data)?;
+    Ok((ObjectKind::from_name(&header.object_type)?, body.to_vec()))
+}
 
-    // Step 2: Decompress the data
-    let mut decompressed_data = ZlibDecoder::new(&content[..]);
-    // Data would be something like this: "x\x9CK\xCA\xC9OR04c(\xCFH,Q\xC8,V(-\xD0QH\xC9O\xB6\a\x00_\x1C\a\x9D"
+fn has_loose_object(repo: &Repository, hash: &Hash) -> bool {
+    let hash_hex = hash.to_hex();
+    let (dir, file) = hash_hex.split_at(2);
+    repo.objects_dir().join(dir).join(file).exists()
+}
 
-    //Step3: EXTRACT CONTENT from the DECOMPRESSED DATA
-    let mut blob_file_contents_vec = Vec::new();
+fn has_object(repo: &Repository, hash: &Hash) -> bool {
+    has_loose_object(repo, hash)
+        || repo
+            .packs()
+            .is_ok_and(|packs| packs.iter().any(|pack| pack.index.find(hash).is_some()))
+}
 
-    //Step4: Filling the buffer with contents of blob file
-    decompressed_data.read_to_end(&mut blob_file_contents_vec)?;
+fn read_object(repo: &Repository, hash: &Hash) -> io::Result<Object> {
+    Object::parse(&read_object_data(repo, hash)?)
+}
 
-    //Step5: Convert the blob file contents to a readable string
-    let readable_blob = String::from_utf8_lossy(&blob_file_contents_vec);
+fn extract_names_from_tree_entries(tree_object: &[u8]) -> io::Result<Vec<String>> {
+    match Object::parse(tree_object)? {
+        Object::Tree(entries) => Ok(entries
+            .into_iter()
+            .map(|entry| entry.name.to_string_lossy().into_owned())
+            .collect()),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected a tree object, found a {}", other.kind()),
+        )),
+    }
+}
 
-    //Step6: Now extract <content> from blob <size>\0<content>
-    match extract_content(&readable_blob) {
-        Some(content) => print!("{}", content.to_string().trim_end()),
-        None => println!("Invalid blob object file"),
+fn cat_file(repo: &Repository, blob_sha: &str) -> io::Result<()> {
+    let data = read_object_data(repo, &Hash::from_hex(blob_sha)?)?;
+    let object = Object::parse(&data)?;
+
+    let mut stdout = io::stdout().lock();
+    match &object {
+        Object::Tree(entries) => {
+            for entry in entries {
+                let kind = match entry.mode.as_str() {
+                    "40000" => "tree",
+                    "160000" => "commit",
+                    _ => "blob",
+                };
+                write!(
+                    stdout,
+                    "{:0>6} {} {}\t",
+                    entry.mode,
+                    kind,
+                    entry.hash.to_hex()
+                )?;
+                stdout.write_all(entry.name.as_bytes())?;
+                writeln!(stdout)?;
+            }
+        }
+        // Print what is stored rather than a re-serialization of it
+        _ => stdout.write_all(GitObjectHeader::from_bytes(&data)?.1)?,
     }
 
     Ok(())
 }
 
-fn extract_content(blob_file_contents: &str) -> Option<&str> {
-    // Find the position of the first null byte
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, git_available, ScratchDir};
 
-    if let Some(pos) = blob_file_contents.find('\0') {
-        // Extract the content after the null byte
-        Some(&blob_file_contents[pos + 1..])
-    } else {
-        None
+    #[test]
+    fn index_trees_match_git_write_tree() {
+        if !git_available() {
+            return;
+        }
+        let dir = ScratchDir::new("main-write-tree");
+        let work_tree = dir.path();
+        git(work_tree, &["init", "-q"]);
+        for path in ["a.txt", "a/b/c", "a/z", "a-b", "a0", "B"] {
+            let path = work_tree.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, path.to_str().unwrap()).unwrap();
+        }
+        fs::set_permissions(work_tree.join("a0"), fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("a.txt", work_tree.join("link")).unwrap();
+        git(work_tree, &["add", "."]);
+        let expected = git(work_tree, &["write-tree"]);
+        let expected_a = git(work_tree, &["write-tree", "--prefix=a"]);
+
+        let repo = Repository::at_work_tree(work_tree).unwrap();
+        let index = Index::load(&repo.git_dir().join("index")).unwrap();
+        let tree = write_index_tree(&repo, &index, "", false).unwrap();
+        assert_eq!(format!("{}\n", tree.to_hex()).into_bytes(), expected);
+        let tree = write_index_tree(&repo, &index, "a/", false).unwrap();
+        assert_eq!(format!("{}\n", tree.to_hex()).into_bytes(), expected_a);
     }
 }
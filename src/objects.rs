@@ -0,0 +1,492 @@
+use std::cmp::Ordering;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+
+use crate::object_headers::GitObjectHeader;
+use crate::{GitTreeEntry, Hash};
+
+/// The four kinds of object git stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
+        }
+    }
+
+    pub fn from_name(name: &str) -> io::Result<ObjectKind> {
+        match name {
+            "blob" => Ok(ObjectKind::Blob),
+            "tree" => Ok(ObjectKind::Tree),
+            "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown object type: {}", name),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Header values and messages are kept as raw bytes: they are in whatever
+/// `encoding` the object names (UTF-8 when it names none), and must hash
+/// back to the same object when serialized again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: Hash,
+    pub parents: Vec<Hash>,
+    pub author: Vec<u8>,
+    pub committer: Vec<u8>,
+    /// Any other headers (`encoding`, `gpgsig`, `mergetag`, ...) in their
+    /// original order. Continuation lines are joined with `\n`.
+    pub extra_headers: Headers,
+    pub message: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub object: Hash,
+    pub kind: ObjectKind,
+    pub tag: Vec<u8>,
+    pub tagger: Option<Vec<u8>>,
+    pub extra_headers: Headers,
+    pub message: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    Blob(Vec<u8>),
+    Tree(Vec<GitTreeEntry>),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+impl Object {
+    /// Parse a full object (`<type> <size>\0<body>`) as stored on disk.
+    pub fn parse(data: &[u8]) -> io::Result<Object> {
+        let (header, body) = GitObjectHeader::from_bytes(data)?;
+        if header.size != body.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Object size mismatch: header says {}, found {}",
+                    header.size,
+                    body.len()
+                ),
+            ));
+        }
+        let kind = ObjectKind::from_name(&header.object_type)?;
+        Object::parse_body(kind, body)
+    }
+
+    /// Parse an object body whose type is already known.
+    pub fn parse_body(kind: ObjectKind, body: &[u8]) -> io::Result<Object> {
+        match kind {
+            ObjectKind::Blob => Ok(Object::Blob(body.to_vec())),
+            ObjectKind::Tree => parse_tree(body).map(Object::Tree),
+            ObjectKind::Commit => parse_commit(body).map(Object::Commit),
+            ObjectKind::Tag => parse_tag(body).map(Object::Tag),
+        }
+    }
+
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            Object::Blob(_) => ObjectKind::Blob,
+            Object::Tree(_) => ObjectKind::Tree,
+            Object::Commit(_) => ObjectKind::Commit,
+            Object::Tag(_) => ObjectKind::Tag,
+        }
+    }
+
+    /// Serialize the object body, without the `<type> <size>\0` header.
+    pub fn body(&self) -> Vec<u8> {
+        match self {
+            Object::Blob(data) => data.clone(),
+            Object::Tree(entries) => serialize_tree(entries),
+            Object::Commit(commit) => serialize_commit(commit),
+            Object::Tag(tag) => serialize_tag(tag),
+        }
+    }
+
+    /// Serialize the object with its header, ready to be hashed and stored.
+    pub fn serialize(&self) -> Vec<u8> {
+        with_header(self.kind(), &self.body())
+    }
+}
+
+/// Prefix an object body with its `<type> <size>\0` header.
+pub fn with_header(kind: ObjectKind, body: &[u8]) -> Vec<u8> {
+    let header = format!("{} {}\0", kind, body.len());
+    let mut result = Vec::with_capacity(header.len() + body.len());
+    result.extend_from_slice(header.as_bytes());
+    result.extend_from_slice(body);
+    result
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn parse_tree(body: &[u8]) -> io::Result<Vec<GitTreeEntry>> {
+    // <mode> <name>\0<20_byte_sha><mode> <name>\0<20_byte_sha>
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < body.len() {
+        let space = body[i..]
+            .iter()
+            .position(|&b| b == b' ')
+            .ok_or_else(|| invalid("Missing mode in tree entry"))?;
+        let mode = std::str::from_utf8(&body[i..i + space])
+            .map_err(|_| invalid("Invalid mode in tree entry"))?
+            .to_string();
+        i += space + 1;
+
+        let null = body[i..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("Missing name in tree entry"))?;
+        let name = OsStr::from_bytes(&body[i..i + null]).to_os_string();
+        i += null + 1;
+
+        if i + 20 > body.len() {
+            return Err(invalid("Truncated hash in tree entry"));
+        }
+        let hash = Hash::from_bytes(&body[i..i + 20])?;
+        i += 20;
+
+        entries.push(GitTreeEntry { mode, name, hash });
+    }
+    Ok(entries)
+}
+
+/// The order git keeps tree entries in: by name, with directories compared
+/// as if their name ended in `/`.
+pub fn tree_order(a: &GitTreeEntry, b: &GitTreeEntry) -> Ordering {
+    let key = |entry: &GitTreeEntry| {
+        let mut key = entry.name.as_bytes().to_vec();
+        if entry.mode == "40000" {
+            key.push(b'/');
+        }
+        key
+    };
+    key(a).cmp(&key(b))
+}
+
+/// Why a tree entry name may not be checked out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BadPath {
+    EmptyName,
+    HasDot,
+    HasDotgit,
+    FullPathname,
+}
+
+/// Check a tree entry name as git does before it reaches the work tree: it
+/// must be a single path component other than `.`, `..` and `.git` in any
+/// case, so no tree can write outside the work tree or into the repository.
+pub fn verify_path(name: &[u8]) -> Result<(), BadPath> {
+    match name {
+        b"" => Err(BadPath::EmptyName),
+        b"." | b".." => Err(BadPath::HasDot),
+        name if name.eq_ignore_ascii_case(b".git") => Err(BadPath::HasDotgit),
+        name if name.contains(&b'/') => Err(BadPath::FullPathname),
+        _ => Ok(()),
+    }
+}
+
+fn serialize_tree(entries: &[GitTreeEntry]) -> Vec<u8> {
+    let mut tree_data = Vec::new();
+    for entry in entries {
+        tree_data.extend_from_slice(entry.mode.as_bytes());
+        tree_data.push(b' ');
+        tree_data.extend_from_slice(entry.name.as_bytes());
+        tree_data.push(0);
+        tree_data.extend_from_slice(entry.hash.as_bytes());
+    }
+    tree_data
+}
+
+/// Headers of a commit or tag, as `(key, value)` pairs in order.
+pub type Headers = Vec<(String, Vec<u8>)>;
+
+/// Split a commit or tag body into its headers and message.
+fn parse_headers(body: &[u8]) -> io::Result<(Headers, Vec<u8>)> {
+    let (head, message) = match body.windows(2).position(|pair| pair == b"\n\n") {
+        Some(pos) => (&body[..pos], &body[pos + 2..]),
+        None => (body.strip_suffix(b"\n").unwrap_or(body), &[][..]),
+    };
+
+    let mut headers: Headers = Vec::new();
+    for line in head.split(|&b| b == b'\n') {
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            // Multi-line headers such as `gpgsig` continue with a leading space
+            let last = headers
+                .last_mut()
+                .ok_or_else(|| invalid("Continuation line without a header"))?;
+            last.1.push(b'\n');
+            last.1.extend_from_slice(continuation);
+        } else {
+            let space = line
+                .iter()
+                .position(|&b| b == b' ')
+                .ok_or_else(|| invalid("Malformed header line"))?;
+            let key = std::str::from_utf8(&line[..space])
+                .map_err(|_| invalid("Malformed header line"))?;
+            headers.push((key.to_string(), line[space + 1..].to_vec()));
+        }
+    }
+    Ok((headers, message.to_vec()))
+}
+
+fn write_header(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+    out.extend_from_slice(key.as_bytes());
+    out.push(b' ');
+    for &byte in value {
+        out.push(byte);
+        if byte == b'\n' {
+            out.push(b' ');
+        }
+    }
+    out.push(b'\n');
+}
+
+/// Parse a hash written out as a header value.
+fn header_hash(value: &[u8]) -> io::Result<Hash> {
+    Hash::from_hex(std::str::from_utf8(value).map_err(|_| invalid("Invalid hash in header"))?)
+}
+
+fn parse_commit(body: &[u8]) -> io::Result<Commit> {
+    let (headers, message) = parse_headers(body)?;
+    let mut tree = None;
+    let mut parents = Vec::new();
+    let mut author = None;
+    let mut committer = None;
+    let mut extra_headers = Vec::new();
+
+    for (key, value) in headers {
+        match key.as_str() {
+            "tree" if tree.is_none() => tree = Some(header_hash(&value)?),
+            "parent" => parents.push(header_hash(&value)?),
+            "author" if author.is_none() => author = Some(value),
+            "committer" if committer.is_none() => committer = Some(value),
+            _ => extra_headers.push((key, value)),
+        }
+    }
+
+    Ok(Commit {
+        tree: tree.ok_or_else(|| invalid("Commit is missing a tree"))?,
+        parents,
+        author: author.ok_or_else(|| invalid("Commit is missing an author"))?,
+        committer: committer.ok_or_else(|| invalid("Commit is missing a committer"))?,
+        extra_headers,
+        message,
+    })
+}
+
+fn serialize_commit(commit: &Commit) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_header(&mut out, "tree", commit.tree.to_hex().as_bytes());
+    for parent in &commit.parents {
+        write_header(&mut out, "parent", parent.to_hex().as_bytes());
+    }
+    write_header(&mut out, "author", &commit.author);
+    write_header(&mut out, "committer", &commit.committer);
+    for (key, value) in &commit.extra_headers {
+        write_header(&mut out, key, value);
+    }
+    out.push(b'\n');
+    out.extend_from_slice(&commit.message);
+    out
+}
+
+fn parse_tag(body: &[u8]) -> io::Result<Tag> {
+    let (headers, message) = parse_headers(body)?;
+    let mut object = None;
+    let mut kind = None;
+    let mut tag = None;
+    let mut tagger = None;
+    let mut extra_headers = Vec::new();
+
+    for (key, value) in headers {
+        match key.as_str() {
+            "object" if object.is_none() => object = Some(header_hash(&value)?),
+            "type" if kind.is_none() => {
+                kind = Some(ObjectKind::from_name(&String::from_utf8_lossy(&value))?)
+            }
+            "tag" if tag.is_none() => tag = Some(value),
+            "tagger" if tagger.is_none() => tagger = Some(value),
+            _ => extra_headers.push((key, value)),
+        }
+    }
+
+    Ok(Tag {
+        object: object.ok_or_else(|| invalid("Tag is missing an object"))?,
+        kind: kind.ok_or_else(|| invalid("Tag is missing a type"))?,
+        tag: tag.ok_or_else(|| invalid("Tag is missing a name"))?,
+        tagger,
+        extra_headers,
+        message,
+    })
+}
+
+fn serialize_tag(tag: &Tag) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_header(&mut out, "object", tag.object.to_hex().as_bytes());
+    write_header(&mut out, "type", tag.kind.as_str().as_bytes());
+    write_header(&mut out, "tag", &tag.tag);
+    if let Some(tagger) = &tag.tagger {
+        write_header(&mut out, "tagger", tagger);
+    }
+    for (key, value) in &tag.extra_headers {
+        write_header(&mut out, key, value);
+    }
+    out.push(b'\n');
+    out.extend_from_slice(&tag.message);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute_sha1;
+    use crate::test_support::{git, git_available, git_with_stdin, ScratchDir};
+    use std::ffi::OsString;
+    use std::fs;
+    use std::os::unix::ffi::OsStringExt;
+
+    fn tree_entry(mode: &str, name: &str) -> GitTreeEntry {
+        GitTreeEntry {
+            mode: mode.to_string(),
+            name: name.into(),
+            hash: Hash::from_hex("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391").unwrap(),
+        }
+    }
+
+    #[test]
+    fn directories_sort_as_if_ending_in_a_slash() {
+        let mut entries = [
+            tree_entry("40000", "a"),
+            tree_entry("100644", "a.txt"),
+            tree_entry("100644", "a0"),
+            tree_entry("100644", "a-b"),
+            tree_entry("40000", "b"),
+            tree_entry("100644", "B"),
+        ];
+        entries.sort_by(tree_order);
+        let names: Vec<&OsStr> = entries.iter().map(|entry| entry.name.as_os_str()).collect();
+        // '-' < '.' < '/' < '0', and uppercase before lowercase
+        assert_eq!(names, ["B", "a-b", "a.txt", "a", "a0", "b"]);
+
+        // A file named like the directory sorts before it
+        let file = tree_entry("100644", "a");
+        assert_eq!(tree_order(&file, &tree_entry("40000", "a")), Ordering::Less);
+    }
+
+    #[test]
+    fn verify_path_refuses_names_that_leave_the_tree() {
+        let cases: [(&[u8], Result<(), BadPath>); 9] = [
+            (b"file.txt", Ok(())),
+            (b".gitignore", Ok(())),
+            (b"...", Ok(())),
+            (b"", Err(BadPath::EmptyName)),
+            (b".", Err(BadPath::HasDot)),
+            (b"..", Err(BadPath::HasDot)),
+            (b".git", Err(BadPath::HasDotgit)),
+            (b".GiT", Err(BadPath::HasDotgit)),
+            (b"a/b", Err(BadPath::FullPathname)),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(verify_path(name), expected, "{:?}", OsStr::from_bytes(name));
+        }
+    }
+
+    #[test]
+    fn tree_hashes_match_git_mktree() {
+        if !git_available() {
+            return;
+        }
+        let dir = ScratchDir::new("objects-mktree");
+        git(dir.path(), &["init", "-q"]);
+        let subtree = git_with_stdin(dir.path(), &["mktree"], "");
+        let subtree = String::from_utf8(subtree).unwrap().trim().to_string();
+
+        let mut entries = vec![
+            tree_entry("100644", "a.txt"),
+            tree_entry("100755", "a0"),
+            tree_entry("120000", "a-b"),
+            tree_entry("160000", "module"),
+            GitTreeEntry {
+                hash: Hash::from_hex(&subtree).unwrap(),
+                ..tree_entry("40000", "a")
+            },
+        ];
+        entries.sort_by(tree_order);
+        let listing: String = entries
+            .iter()
+            .map(|entry| {
+                let kind = match entry.mode.as_str() {
+                    "40000" => "tree",
+                    "160000" => "commit",
+                    _ => "blob",
+                };
+                format!(
+                    "{:0>6} {} {}\t{}\n",
+                    entry.mode,
+                    kind,
+                    entry.hash.to_hex(),
+                    entry.name.to_str().unwrap()
+                )
+            })
+            .collect();
+        let expected = git_with_stdin(dir.path(), &["mktree", "--missing"], &listing);
+
+        let tree = Object::Tree(entries);
+        assert_eq!(
+            compute_sha1(&tree.serialize()).to_hex(),
+            String::from_utf8(expected).unwrap().trim()
+        );
+    }
+
+    #[test]
+    fn trees_with_non_utf8_names_round_trip() {
+        if !git_available() {
+            return;
+        }
+        let dir = ScratchDir::new("objects-raw-names");
+        git(dir.path(), &["init", "-q"]);
+        let name = OsString::from_vec(b"caf\xe9.txt".to_vec());
+        fs::write(dir.path().join(&name), "latin-1\n").unwrap();
+        fs::write(dir.path().join("plain.txt"), "utf-8\n").unwrap();
+        git(dir.path(), &["add", "."]);
+        let hash = git(dir.path(), &["write-tree"]);
+        let hash = String::from_utf8(hash).unwrap().trim().to_string();
+        let body = git(dir.path(), &["cat-file", "tree", &hash]);
+
+        let Object::Tree(entries) = Object::parse_body(ObjectKind::Tree, &body).unwrap() else {
+            panic!("not a tree");
+        };
+        assert_eq!(entries[0].name, name);
+        assert_eq!(entries[1].name, "plain.txt");
+        let tree = Object::Tree(entries);
+        assert_eq!(tree.body(), body);
+        assert_eq!(compute_sha1(&tree.serialize()).to_hex(), hash);
+    }
+}
@@ -0,0 +1,291 @@
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::delta::{read_packed_object, resolve_pack, DeltaBaseCache};
+use crate::objects::ObjectKind;
+use crate::packfile::parse_pack;
+use crate::{compute_sha1, Hash};
+
+/*
+A version 2 pack index (.idx) is laid out as:
+
+\377tOc <version: u32 = 2>
+<fanout: 256 x u32>        number of objects whose first byte is <= i
+<hashes: N x 20 bytes>     sorted
+<crc32: N x u32>           of each packed entry
+<offsets: N x u32>         MSB set means an index into the 64-bit table
+<large offsets: M x u64>
+<pack checksum: 20 bytes>
+<index checksum: 20 bytes>
+*/
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+
+#[derive(Debug, Clone)]
+pub struct PackIndex {
+    fanout: [u32; 256],
+    hashes: Vec<Hash>,
+    crc32s: Vec<u32>,
+    offsets: Vec<u64>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u32(data: &[u8], pos: usize) -> io::Result<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or_else(|| invalid("Truncated pack index"))?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+impl PackIndex {
+    pub fn parse(data: &[u8]) -> io::Result<PackIndex> {
+        if data.len() < 8 + 256 * 4 + 40 || data[..4] != IDX_MAGIC {
+            return Err(invalid("Not a version 2 pack index"));
+        }
+        let version = read_u32(data, 4)?;
+        if version != 2 {
+            return Err(invalid(&format!(
+                "Unsupported pack index version {}",
+                version
+            )));
+        }
+
+        let (body, trailer) = data.split_at(data.len() - 20);
+        if compute_sha1(body) != Hash::from_bytes(trailer)? {
+            return Err(invalid("Pack index checksum mismatch"));
+        }
+
+        let mut fanout = [0u32; 256];
+        for (i, slot) in fanout.iter_mut().enumerate() {
+            *slot = read_u32(data, 8 + i * 4)?;
+        }
+        if fanout.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(invalid("Pack index fanout is not monotonic"));
+        }
+        let count = fanout[255] as usize;
+
+        let hashes_start = 8 + 256 * 4;
+        let crc_start = hashes_start + count * 20;
+        let offsets_start = crc_start + count * 4;
+        let large_start = offsets_start + count * 4;
+        if large_start + 40 > data.len() {
+            return Err(invalid("Truncated pack index"));
+        }
+
+        let mut hashes = Vec::with_capacity(count);
+        let mut crc32s = Vec::with_capacity(count);
+        let mut offsets = Vec::with_capacity(count);
+        for i in 0..count {
+            let start = hashes_start + i * 20;
+            hashes.push(Hash::from_bytes(&data[start..start + 20])?);
+            crc32s.push(read_u32(data, crc_start + i * 4)?);
+
+            let offset = read_u32(data, offsets_start + i * 4)?;
+            if offset & 0x8000_0000 == 0 {
+                offsets.push(offset as u64);
+            } else {
+                // Offsets past 2GiB live in the 64-bit table
+                let pos = large_start + (offset & 0x7fff_ffff) as usize * 8;
+                let bytes = data
+                    .get(pos..pos + 8)
+                    .filter(|_| pos + 8 <= data.len() - 40)
+                    .ok_or_else(|| invalid("Large offset out of range"))?;
+                offsets.push(u64::from_be_bytes(bytes.try_into().unwrap()));
+            }
+        }
+        if hashes.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(invalid("Pack index hashes are not sorted"));
+        }
+
+        Ok(PackIndex {
+            fanout,
+            hashes,
+            crc32s,
+            offsets,
+        })
+    }
+
+    pub fn open(path: &Path) -> io::Result<PackIndex> {
+        PackIndex::parse(&fs::read(path)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Offset of `hash` in the matching pack, if the index contains it.
+    pub fn find(&self, hash: &Hash) -> Option<u64> {
+        let first = hash.as_bytes()[0] as usize;
+        let start = if first == 0 {
+            0
+        } else {
+            self.fanout[first - 1] as usize
+        };
+        let end = self.fanout[first] as usize;
+        self.hashes[start..end]
+            .binary_search(hash)
+            .ok()
+            .map(|i| self.offsets[start + i])
+    }
+
+    /// `(hash, offset, crc32)` of every object, in hash order.
+    pub fn entries(&self) -> impl Iterator<Item = (Hash, u64, u32)> + '_ {
+        (0..self.len()).map(|i| (self.hashes[i], self.offsets[i], self.crc32s[i]))
+    }
+}
+
+/// Serialize a version 2 index for the given `(hash, offset, crc32)` entries.
+pub fn write_index(entries: &[(Hash, u64, u32)], pack_checksum: &Hash) -> io::Result<Vec<u8>> {
+    let mut sorted = entries.to_vec();
+    sorted.sort_by_key(|entry| entry.0);
+    if let Some(pair) = sorted.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(invalid(&format!(
+            "Duplicate object {} in pack",
+            pair[0].0.to_hex()
+        )));
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&IDX_MAGIC);
+    out.extend_from_slice(&2u32.to_be_bytes());
+
+    let mut fanout = [0u32; 256];
+    for (hash, _, _) in &sorted {
+        fanout[hash.as_bytes()[0] as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout.iter_mut() {
+        total += *count;
+        *count = total;
+        out.extend_from_slice(&count.to_be_bytes());
+    }
+
+    for (hash, _, _) in &sorted {
+        out.extend_from_slice(hash.as_bytes());
+    }
+    for (_, _, crc32) in &sorted {
+        out.extend_from_slice(&crc32.to_be_bytes());
+    }
+    let mut large_offsets = Vec::new();
+    for (_, offset, _) in &sorted {
+        if *offset < 0x8000_0000 {
+            out.extend_from_slice(&(*offset as u32).to_be_bytes());
+        } else {
+            let slot = large_offsets.len() as u32 | 0x8000_0000;
+            out.extend_from_slice(&slot.to_be_bytes());
+            large_offsets.push(*offset);
+        }
+    }
+    for offset in large_offsets {
+        out.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    out.extend_from_slice(pack_checksum.as_bytes());
+    let checksum = compute_sha1(&out);
+    out.extend_from_slice(checksum.as_bytes());
+    Ok(out)
+}
+
+/// Walk a pack, resolve all of its deltas and build its index.
+///
+/// Returns the serialized `.idx` and the pack checksum. `external` provides
+/// delta bases missing from the pack, as in `resolve_pack`.
+pub fn index_pack<F>(pack_data: &[u8], external: F) -> io::Result<(Vec<u8>, Hash)>
+where
+    F: FnMut(&Hash) -> io::Result<Option<(ObjectKind, Vec<u8>)>>,
+{
+    let pack = parse_pack(pack_data)?;
+    let objects = resolve_pack(&pack.entries, external)?;
+
+    let entries: Vec<(Hash, u64, u32)> = pack
+        .entries
+        .iter()
+        .zip(&objects)
+        .map(|(entry, object)| {
+            let start = entry.offset as usize;
+            let crc32 = crc32fast::hash(&pack_data[start..start + entry.packed_size]);
+            (object.hash, entry.offset, crc32)
+        })
+        .collect();
+
+    let index = write_index(&entries, &pack.checksum)?;
+    Ok((index, pack.checksum))
+}
+
+/// The reading state of a pack: the open file and the bases rebuilt from it.
+#[derive(Debug, Default)]
+struct PackReader {
+    file: Option<BufReader<File>>,
+    bases: DeltaBaseCache,
+}
+
+/// A pack with its index. The pack file is opened on the first read and
+/// kept open for the ones after it.
+#[derive(Debug)]
+pub struct Pack {
+    pub index: PackIndex,
+    /// The `.pack` file.
+    pub path: PathBuf,
+    reader: RefCell<PackReader>,
+}
+
+impl Pack {
+    pub fn open(index_path: &Path) -> io::Result<Pack> {
+        Ok(Pack {
+            index: PackIndex::open(index_path)?,
+            path: index_path.with_extension("pack"),
+            reader: RefCell::new(PackReader::default()),
+        })
+    }
+
+    /// Rebuild the object at `offset`. `external` provides REF_DELTA bases
+    /// stored elsewhere.
+    pub fn read<F>(&self, offset: u64, external: F) -> io::Result<(ObjectKind, Vec<u8>)>
+    where
+        F: FnMut(&Hash) -> io::Result<(ObjectKind, Vec<u8>)>,
+    {
+        // Only a base chain that comes back to this pack through another
+        // one can find it already being read
+        let mut reader = self
+            .reader
+            .try_borrow_mut()
+            .map_err(|_| invalid("Delta chain loops back on itself"))?;
+        let PackReader { file, bases } = &mut *reader;
+        let file = match file {
+            Some(file) => file,
+            None => file.insert(BufReader::new(File::open(&self.path)?)),
+        };
+        read_packed_object(file, offset, bases, |base| self.index.find(base), external)
+    }
+}
+
+/// Every pack in `pack_dir`, by the name of its `.idx`. Packs already in
+/// `loaded` are reused rather than read again.
+pub fn load_packs(pack_dir: &Path, loaded: &[Rc<Pack>]) -> io::Result<Vec<Rc<Pack>>> {
+    let read_dir = match fs::read_dir(pack_dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut packs = Vec::new();
+    for entry in read_dir {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "idx") {
+            continue;
+        }
+        let pack_path = path.with_extension("pack");
+        match loaded.iter().find(|pack| pack.path == pack_path) {
+            Some(pack) => packs.push(pack.clone()),
+            None => packs.push(Rc::new(Pack::open(&path)?)),
+        }
+    }
+    packs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(packs)
+}
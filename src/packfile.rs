@@ -0,0 +1,438 @@
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::io::{self, BufRead, Read, Write};
+
+use crate::delta::create_delta;
+use crate::objects::ObjectKind;
+use crate::{compute_sha1, Hash};
+
+/*
+A pack is laid out as:
+
+PACK <version: u32> <object count: u32>
+<entry>*
+<20 byte SHA-1 of everything above>
+
+Each entry starts with a variable length header carrying the type and the
+inflated size, followed (for deltas) by a reference to the base object, and
+then the zlib compressed data.
+*/
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// Sizes read from a pack are untrusted, so buffers sized from them start
+/// no larger than this and grow as data actually arrives.
+pub const MAX_PREALLOC: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackEntryKind {
+    /// A whole object stored as-is.
+    Base(ObjectKind),
+    /// A delta against the entry found at `base_offset` in the same pack.
+    OfsDelta { base_offset: u64 },
+    /// A delta against the object named `base`, which may live outside the pack.
+    RefDelta { base: Hash },
+}
+
+#[derive(Debug, Clone)]
+pub struct PackEntry {
+    /// Offset of the entry header from the start of the pack.
+    pub offset: u64,
+    /// Number of bytes the entry occupies in the pack, header included.
+    pub packed_size: usize,
+    pub kind: PackEntryKind,
+    /// Inflated entry data: the object body, or the delta instructions.
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PackFile {
+    pub entries: Vec<PackEntry>,
+    /// SHA-1 of the whole pack, as found in its trailer.
+    pub checksum: Hash,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u32(data: &[u8], pos: usize) -> io::Result<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or_else(|| invalid("Truncated pack header"))?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Parse a complete pack stream, verifying its trailing checksum.
+pub fn parse_pack(data: &[u8]) -> io::Result<PackFile> {
+    if data.len() < 12 + 20 || &data[..4] != b"PACK" {
+        return Err(invalid("Not a pack file"));
+    }
+    let version = read_u32(data, 4)?;
+    if version != 2 && version != 3 {
+        return Err(invalid(&format!("Unsupported pack version {}", version)));
+    }
+    let object_count = read_u32(data, 8)?;
+
+    let mut entries = Vec::with_capacity((object_count as usize).min(MAX_PREALLOC));
+    let mut pos = 12;
+    for _ in 0..object_count {
+        let entry = parse_entry(data, pos)?;
+        pos += entry.packed_size;
+        entries.push(entry);
+    }
+
+    let trailer = data
+        .get(pos..pos + 20)
+        .ok_or_else(|| invalid("Missing pack checksum"))?;
+    let checksum = Hash::from_bytes(trailer)?;
+    if compute_sha1(&data[..pos]) != checksum {
+        return Err(invalid("Pack checksum mismatch"));
+    }
+
+    Ok(PackFile { entries, checksum })
+}
+
+fn read_byte<R: BufRead>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0u8; 1];
+    reader
+        .read_exact(&mut byte)
+        .map_err(|_| invalid("Truncated pack entry"))?;
+    Ok(byte[0])
+}
+
+/// Parse the entry whose header starts at `offset`.
+pub fn parse_entry(data: &[u8], offset: usize) -> io::Result<PackEntry> {
+    let mut reader = data
+        .get(offset..)
+        .ok_or_else(|| invalid("Truncated pack entry"))?;
+    read_entry(&mut reader, offset as u64)
+}
+
+/// Read the entry at the current position of `reader`, which sits at
+/// `offset` in the pack. The reader is left right after the entry.
+pub fn read_entry<R: BufRead>(reader: &mut R, offset: u64) -> io::Result<PackEntry> {
+    // Type and size header: 1TTTSSSS then 1SSSSSSS... little endian groups
+    let mut byte = read_byte(reader)?;
+    let mut header_size = 1;
+    let type_id = (byte >> 4) & 0x7;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        if shift > 57 {
+            return Err(invalid("Pack entry size is too large"));
+        }
+        byte = read_byte(reader)?;
+        header_size += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    let kind = match type_id {
+        OBJ_COMMIT => PackEntryKind::Base(ObjectKind::Commit),
+        OBJ_TREE => PackEntryKind::Base(ObjectKind::Tree),
+        OBJ_BLOB => PackEntryKind::Base(ObjectKind::Blob),
+        OBJ_TAG => PackEntryKind::Base(ObjectKind::Tag),
+        OBJ_OFS_DELTA => {
+            // Big endian groups where each continuation adds one before shifting
+            let mut byte = read_byte(reader)?;
+            header_size += 1;
+            let mut distance = (byte & 0x7f) as u64;
+            while byte & 0x80 != 0 {
+                if distance >= 1 << 56 {
+                    return Err(invalid("Delta base offset is too large"));
+                }
+                byte = read_byte(reader)?;
+                header_size += 1;
+                distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+            }
+            if distance == 0 {
+                return Err(invalid("Delta base offset points at the delta itself"));
+            }
+            let base_offset = offset
+                .checked_sub(distance)
+                .ok_or_else(|| invalid("Delta base offset is before the pack start"))?;
+            PackEntryKind::OfsDelta { base_offset }
+        }
+        OBJ_REF_DELTA => {
+            let mut base = [0u8; 20];
+            reader
+                .read_exact(&mut base)
+                .map_err(|_| invalid("Truncated pack entry"))?;
+            header_size += 20;
+            PackEntryKind::RefDelta {
+                base: Hash::from_bytes(&base)?,
+            }
+        }
+        _ => return Err(invalid(&format!("Unknown pack object type {}", type_id))),
+    };
+
+    // The bufread decoder only consumes the compressed stream itself
+    let mut decoder = ZlibDecoder::new(reader);
+    let mut inflated = Vec::with_capacity(size.min(MAX_PREALLOC));
+    decoder.read_to_end(&mut inflated)?;
+    if inflated.len() != size {
+        return Err(invalid("Pack entry size mismatch"));
+    }
+
+    Ok(PackEntry {
+        offset,
+        packed_size: header_size + decoder.total_in() as usize,
+        kind,
+        data: inflated,
+    })
+}
+
+fn type_id(kind: ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Commit => OBJ_COMMIT,
+        ObjectKind::Tree => OBJ_TREE,
+        ObjectKind::Blob => OBJ_BLOB,
+        ObjectKind::Tag => OBJ_TAG,
+    }
+}
+
+/// Append an entry header for `type_id` and an inflated `size`.
+fn write_entry_header(out: &mut Vec<u8>, type_id: u8, size: usize) {
+    let mut byte = (type_id << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+    while size != 0 {
+        out.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    out.push(byte);
+}
+
+/// Objects tried as delta bases for each object, unless told otherwise.
+pub const DEFAULT_WINDOW: usize = 10;
+/// Longest delta chain an object may end, unless told otherwise.
+pub const DEFAULT_DEPTH: usize = 50;
+/// Objects smaller than this are always stored whole.
+const MIN_DELTA_SIZE: usize = 50;
+
+struct ObjectToPack {
+    hash: Hash,
+    kind: ObjectKind,
+    data: Vec<u8>,
+    name_hash: u32,
+    base: Option<usize>,
+    delta: Option<Vec<u8>>,
+    depth: usize,
+}
+
+/// git's path hash: the last characters of a path weigh the most, so files
+/// with the same name (in any directory) sort next to each other.
+fn name_hash(name: &str) -> u32 {
+    name.bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .fold(0u32, |hash, byte| {
+            (hash >> 2).wrapping_add((byte as u32) << 24)
+        })
+}
+
+/// Append an OFS_DELTA base distance: big endian 7 bit groups, each
+/// continuation adding one before the shift.
+fn write_base_distance(out: &mut Vec<u8>, mut distance: u64) {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance != 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    out.extend_from_slice(&bytes);
+}
+
+/// Build a version 2 pack holding `objects`, each given with the path it
+/// was reached through (empty for commits and tags).
+///
+/// Objects are sorted by type, name and size and every object is tried as a
+/// delta against the `window` objects before it; no chain grows longer than
+/// `max_depth`. Deltas are stored as OFS_DELTA when `ofs_delta` is set, and
+/// as REF_DELTA for readers that do not understand offsets. `read` loads an
+/// object's type and body.
+pub fn pack_objects<F>(
+    objects: &[(Hash, String)],
+    window: usize,
+    max_depth: usize,
+    ofs_delta: bool,
+    mut read: F,
+) -> io::Result<Vec<u8>>
+where
+    F: FnMut(&Hash) -> io::Result<(ObjectKind, Vec<u8>)>,
+{
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for (hash, name) in objects {
+        if !seen.insert(*hash) {
+            continue;
+        }
+        let (kind, data) = read(hash)?;
+        entries.push(ObjectToPack {
+            hash: *hash,
+            kind,
+            data,
+            name_hash: name_hash(name),
+            base: None,
+            delta: None,
+            depth: 0,
+        });
+    }
+
+    // Bigger objects first, so deltas mostly remove data
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|&i| {
+        let entry = &entries[i];
+        (
+            type_id(entry.kind),
+            entry.name_hash,
+            Reverse(entry.data.len()),
+        )
+    });
+
+    for (position, &target) in order.iter().enumerate() {
+        let target_size = entries[target].data.len();
+        if target_size < MIN_DELTA_SIZE {
+            continue;
+        }
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for &base in order[position.saturating_sub(window)..position]
+            .iter()
+            .rev()
+        {
+            let candidate = &entries[base];
+            if candidate.kind != entries[target].kind
+                || candidate.depth >= max_depth
+                || target_size < candidate.data.len() / 32
+            {
+                continue;
+            }
+            let max_size = match &best {
+                Some((_, delta)) => delta.len() - 1,
+                None => (target_size / 2).saturating_sub(20),
+            };
+            if let Some(delta) = create_delta(&candidate.data, &entries[target].data, max_size) {
+                best = Some((base, delta));
+            }
+        }
+        if let Some((base, delta)) = best {
+            entries[target].depth = entries[base].depth + 1;
+            entries[target].base = Some(base);
+            entries[target].delta = Some(delta);
+        }
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"PACK");
+    out.extend_from_slice(&2u32.to_be_bytes());
+    out.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    // Keep the given order, but write each delta base before its deltas
+    let mut offsets: Vec<Option<u64>> = vec![None; entries.len()];
+    for first in 0..entries.len() {
+        let mut chain = Vec::new();
+        let mut next = Some(first);
+        while let Some(i) = next.filter(|&i| offsets[i].is_none()) {
+            chain.push(i);
+            next = entries[i].base;
+        }
+
+        for &i in chain.iter().rev() {
+            let offset = out.len() as u64;
+            let entry = &entries[i];
+            let body = match (&entry.delta, entry.base) {
+                (Some(delta), Some(base)) if ofs_delta => {
+                    write_entry_header(&mut out, OBJ_OFS_DELTA, delta.len());
+                    write_base_distance(&mut out, offset - offsets[base].unwrap());
+                    delta
+                }
+                (Some(delta), Some(base)) => {
+                    write_entry_header(&mut out, OBJ_REF_DELTA, delta.len());
+                    out.extend_from_slice(entries[base].hash.as_bytes());
+                    delta
+                }
+                _ => {
+                    write_entry_header(&mut out, type_id(entry.kind), entry.data.len());
+                    &entry.data
+                }
+            };
+            let mut encoder = ZlibEncoder::new(out, Compression::default());
+            encoder.write_all(body)?;
+            out = encoder.finish()?;
+            offsets[i] = Some(offset);
+        }
+    }
+
+    let checksum = compute_sha1(&out);
+    out.extend_from_slice(checksum.as_bytes());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::resolve_pack;
+    use crate::objects::with_header;
+
+    fn blobs() -> Vec<(Hash, Vec<u8>)> {
+        let text: Vec<u8> = (0..200)
+            .flat_map(|i| format!("line {}\n", i).into_bytes())
+            .collect();
+        let mut edited = text.clone();
+        edited.extend_from_slice(b"one more line\n");
+        [text, edited]
+            .into_iter()
+            .map(|data| (compute_sha1(&with_header(ObjectKind::Blob, &data)), data))
+            .collect()
+    }
+
+    fn round_trip(ofs_delta: bool) -> Vec<PackEntryKind> {
+        let blobs = blobs();
+        let objects: Vec<(Hash, String)> = blobs
+            .iter()
+            .map(|(hash, _)| (*hash, "file.txt".to_string()))
+            .collect();
+        let pack = pack_objects(&objects, DEFAULT_WINDOW, DEFAULT_DEPTH, ofs_delta, |hash| {
+            let (_, data) = blobs.iter().find(|(h, _)| h == hash).unwrap();
+            Ok((ObjectKind::Blob, data.clone()))
+        })
+        .unwrap();
+
+        let parsed = parse_pack(&pack).unwrap();
+        let resolved = resolve_pack(&parsed.entries, |_| Ok(None)).unwrap();
+        for (hash, data) in &blobs {
+            let object = resolved.iter().find(|object| object.hash == *hash).unwrap();
+            assert_eq!(&object.data, data);
+        }
+        parsed.entries.into_iter().map(|entry| entry.kind).collect()
+    }
+
+    #[test]
+    fn deltas_use_offsets_when_allowed() {
+        let kinds = round_trip(true);
+        assert!(kinds
+            .iter()
+            .any(|kind| matches!(kind, PackEntryKind::OfsDelta { .. })));
+    }
+
+    #[test]
+    fn deltas_name_their_base_without_ofs_delta() {
+        let kinds = round_trip(false);
+        assert!(kinds
+            .iter()
+            .any(|kind| matches!(kind, PackEntryKind::RefDelta { .. })));
+        assert!(!kinds
+            .iter()
+            .any(|kind| matches!(kind, PackEntryKind::OfsDelta { .. })));
+    }
+}
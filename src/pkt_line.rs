@@ -0,0 +1,305 @@
+use std::io::{self, Read, Write};
+
+/*
+pkt-line framing, shared by every git wire protocol:
+
+  <4 hex digits: length of the line, the 4 digits included><payload>
+
+Lengths below 4 are reserved for special packets:
+  0000  flush-pkt         end of a message section
+  0001  delim-pkt         separates sections (protocol v2)
+  0002  response-end-pkt  end of a response (protocol v2, stateless)
+
+With side-band, each data payload starts with a band number:
+  1  pack data
+  2  progress messages
+  3  fatal error message
+*/
+
+/// Largest payload a single pkt-line may carry.
+pub const MAX_PAYLOAD: usize = 65516;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    Flush,
+    Delim,
+    ResponseEnd,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Strip the trailing `\n` text lines usually carry.
+pub fn trim_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+pub struct PktLineReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> PktLineReader<R> {
+    pub fn new(inner: R) -> Self {
+        PktLineReader { inner }
+    }
+
+    /// Read the next packet, or `None` at a clean end of stream.
+    pub fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        let mut length = [0u8; 4];
+        let mut filled = 0;
+        while filled < 4 {
+            match self.inner.read(&mut length[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(invalid("Truncated pkt-line length")),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let length = std::str::from_utf8(&length)
+            .ok()
+            .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| invalid("Invalid pkt-line length"))?;
+        match length {
+            0 => Ok(Some(Packet::Flush)),
+            1 => Ok(Some(Packet::Delim)),
+            2 => Ok(Some(Packet::ResponseEnd)),
+            3 => Err(invalid("Invalid pkt-line length")),
+            _ => {
+                let mut payload = vec![0u8; length - 4];
+                self.inner
+                    .read_exact(&mut payload)
+                    .map_err(|_| invalid("Truncated pkt-line"))?;
+                Ok(Some(Packet::Data(payload)))
+            }
+        }
+    }
+
+    /// Read the next packet, treating the end of the stream as an error.
+    pub fn expect_packet(&mut self) -> io::Result<Packet> {
+        self.read_packet()?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Unexpected end of pkt-line stream",
+            )
+        })
+    }
+
+    /// Collect data packets up to the next flush, delim or response-end.
+    pub fn read_section(&mut self) -> io::Result<(Vec<Vec<u8>>, Packet)> {
+        let mut lines = Vec::new();
+        loop {
+            match self.expect_packet()? {
+                Packet::Data(line) => lines.push(line),
+                terminator => return Ok((lines, terminator)),
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+pub struct PktLineWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> PktLineWriter<W> {
+    pub fn new(inner: W) -> Self {
+        PktLineWriter { inner }
+    }
+
+    pub fn write_data(&mut self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pkt-line payload is too long",
+            ));
+        }
+        write!(self.inner, "{:04x}", payload.len() + 4)?;
+        self.inner.write_all(payload)
+    }
+
+    /// Write a text line, adding the trailing `\n`.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let mut payload = Vec::with_capacity(line.len() + 1);
+        payload.extend_from_slice(line.as_bytes());
+        payload.push(b'\n');
+        self.write_data(&payload)
+    }
+
+    pub fn write_flush(&mut self) -> io::Result<()> {
+        self.inner.write_all(b"0000")
+    }
+
+    pub fn write_delim(&mut self) -> io::Result<()> {
+        self.inner.write_all(b"0001")
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Demultiplexes a side-band stream: band 1 is exposed through `Read`,
+/// band 2 is forwarded to `progress` with a `remote: ` prefix on each line,
+/// and band 3 ends the stream with an error. A flush-pkt ends the data.
+pub struct SidebandReader<'a, R: Read, P: Write> {
+    packets: &'a mut PktLineReader<R>,
+    progress: P,
+    buffer: Vec<u8>,
+    position: usize,
+    at_line_start: bool,
+    done: bool,
+}
+
+impl<'a, R: Read, P: Write> SidebandReader<'a, R, P> {
+    pub fn new(packets: &'a mut PktLineReader<R>, progress: P) -> Self {
+        SidebandReader {
+            packets,
+            progress,
+            buffer: Vec::new(),
+            position: 0,
+            at_line_start: true,
+            done: false,
+        }
+    }
+
+    fn write_progress(&mut self, message: &[u8]) -> io::Result<()> {
+        let mut output = Vec::with_capacity(message.len() + 8);
+        for &byte in message {
+            if self.at_line_start {
+                output.extend_from_slice(b"remote: ");
+            }
+            output.push(byte);
+            self.at_line_start = byte == b'\n' || byte == b'\r';
+        }
+        self.progress.write_all(&output)?;
+        self.progress.flush()
+    }
+}
+
+impl<R: Read, P: Write> Read for SidebandReader<'_, R, P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() && !self.done {
+            match self.packets.read_packet()? {
+                None | Some(Packet::Flush) | Some(Packet::ResponseEnd) => self.done = true,
+                Some(Packet::Delim) => return Err(invalid("Unexpected delim-pkt in side-band")),
+                Some(Packet::Data(payload)) => match payload.split_first() {
+                    Some((1, data)) => {
+                        self.buffer = data.to_vec();
+                        self.position = 0;
+                    }
+                    Some((2, message)) => self.write_progress(message)?,
+                    Some((3, message)) => {
+                        return Err(io::Error::other(format!(
+                            "remote error: {}",
+                            String::from_utf8_lossy(trim_newline(message))
+                        )))
+                    }
+                    _ => return Err(invalid("Invalid side-band packet")),
+                },
+            }
+        }
+
+        let available = &self.buffer[self.position..];
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(bytes: &[u8]) -> io::Result<Vec<Packet>> {
+        let mut reader = PktLineReader::new(bytes);
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet()? {
+            packets.push(packet);
+        }
+        Ok(packets)
+    }
+
+    #[test]
+    fn writes_and_reads_back_every_packet_kind() {
+        let mut writer = PktLineWriter::new(Vec::new());
+        writer.write_line("want abc").unwrap();
+        writer.write_delim().unwrap();
+        writer.write_data(b"").unwrap();
+        writer.write_flush().unwrap();
+        let bytes = writer.into_inner();
+        assert_eq!(bytes, b"000dwant abc\n000100040000");
+
+        let mut stream = bytes.clone();
+        stream.extend_from_slice(b"0002");
+        assert_eq!(
+            read_all(&stream).unwrap(),
+            vec![
+                Packet::Data(b"want abc\n".to_vec()),
+                Packet::Delim,
+                Packet::Data(Vec::new()),
+                Packet::Flush,
+                Packet::ResponseEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lengths() {
+        for stream in [&b"0003"[..], b"zzzz", b"00", b"0009abc"] {
+            let error = read_all(stream).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{stream:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_oversized_payloads() {
+        let mut writer = PktLineWriter::new(Vec::new());
+        assert!(writer.write_data(&vec![b'x'; MAX_PAYLOAD]).is_ok());
+        let error = writer.write_data(&vec![b'x'; MAX_PAYLOAD + 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn sideband_splits_pack_data_from_progress() {
+        let mut writer = PktLineWriter::new(Vec::new());
+        writer.write_data(b"\x02Counting\rCounting done\n").unwrap();
+        writer.write_data(b"\x01PACK").unwrap();
+        writer.write_data(b"\x02Total 3\n").unwrap();
+        writer.write_data(b"\x01data").unwrap();
+        writer.write_flush().unwrap();
+        let bytes = writer.into_inner();
+
+        let mut packets = PktLineReader::new(&bytes[..]);
+        let mut progress = Vec::new();
+        let mut data = Vec::new();
+        SidebandReader::new(&mut packets, &mut progress)
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"PACKdata");
+        assert_eq!(
+            progress,
+            b"remote: Counting\rremote: Counting done\nremote: Total 3\n"
+        );
+    }
+
+    #[test]
+    fn sideband_surfaces_remote_errors() {
+        let mut writer = PktLineWriter::new(Vec::new());
+        writer.write_data(b"\x03access denied\n").unwrap();
+        let bytes = writer.into_inner();
+
+        let mut packets = PktLineReader::new(&bytes[..]);
+        let error = SidebandReader::new(&mut packets, io::sink())
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(error.to_string(), "remote error: access denied");
+    }
+}
@@ -0,0 +1,266 @@
+use std::io;
+
+use crate::packfile;
+use crate::refs::{
+    current_branch, delete_ref, list_refs, qualify_ref, read_ref, resolve_local_ref, shorten_ref,
+    write_ref,
+};
+use crate::refspec::Refspec;
+use crate::repository::Repository;
+use crate::transport;
+use crate::{has_object, is_ancestor, list_objects, read_raw_object, remote_url, short_hash, Hash};
+
+/*
+push matches its refspecs against local refs and compares each with what
+the remote advertises. An update is rejected here, before anything is sent,
+when it would not fast-forward the remote ref (or would move a tag) and is
+not forced; the rest go to the remote in one request with a pack of the
+objects it lacks. The remote may still refuse any of them.
+
+Remote-tracking refs configured by `remote.<name>.fetch` follow each update
+the remote accepts, as if it had been fetched back.
+*/
+
+/// A local ref to push, or `None` as the source to delete `dst`.
+struct PushUpdate {
+    src: Option<String>,
+    dst: String,
+    force: bool,
+}
+
+pub fn push(repo: &Repository, remote: &str, cli_refspecs: &[&str]) -> io::Result<()> {
+    let config = repo.config()?;
+    let url = remote_url(&config, remote)?;
+    let refspecs = if cli_refspecs.is_empty() {
+        // Push the current branch to the branch of the same name
+        vec![Refspec::parse(&current_branch(repo)?)?]
+    } else {
+        cli_refspecs
+            .iter()
+            .map(|spec| Refspec::parse(spec))
+            .collect::<io::Result<Vec<_>>>()?
+    };
+
+    // Match the refspecs against local refs
+    let mut updates = Vec::new();
+    for spec in &refspecs {
+        if spec.is_glob() {
+            let (prefix, _) = spec.src.split_once('*').unwrap();
+            for (name, _) in list_refs(repo, prefix)? {
+                if let Some(dst) = spec
+                    .map_to_dst(&name)
+                    .or_else(|| spec.dst.is_none().then(|| name.clone()))
+                {
+                    updates.push(PushUpdate {
+                        src: Some(name),
+                        dst,
+                        force: spec.force,
+                    });
+                }
+            }
+            continue;
+        }
+
+        // An empty source deletes the destination
+        if spec.src.is_empty() {
+            let dst = spec
+                .dst
+                .clone()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Nothing to push"))?;
+            updates.push(PushUpdate {
+                src: None,
+                dst: qualify_ref(&dst, "refs/heads/"),
+                force: spec.force,
+            });
+            continue;
+        }
+
+        let src = resolve_local_ref(repo, &spec.src)?;
+        let dst = match &spec.dst {
+            Some(dst) if dst.starts_with("refs/") => dst.clone(),
+            Some(dst) if src.starts_with("refs/tags/") => format!("refs/tags/{}", dst),
+            Some(dst) => format!("refs/heads/{}", dst),
+            None => src.clone(),
+        };
+        updates.push(PushUpdate {
+            src: Some(src),
+            dst,
+            force: spec.force,
+        });
+    }
+
+    let advertisement = transport::discover_push_refs(&url)?;
+    let remote_hash = |name: &str| {
+        advertisement
+            .refs
+            .iter()
+            .find(|remote_ref| remote_ref.name == name)
+            .map(|remote_ref| remote_ref.hash)
+    };
+
+    // Decide which updates to send; the rest are rejected locally
+    let mut commands = Vec::new();
+    let mut report = Vec::new();
+    let mut failed = false;
+    for update in &updates {
+        let old = remote_hash(&update.dst).unwrap_or_else(Hash::zero);
+        let new = match &update.src {
+            Some(src) => read_ref(repo, src)?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("src refspec {} does not match any", src),
+                )
+            })?,
+            None => Hash::zero(),
+        };
+        let short_src = update.src.as_deref().map_or("", shorten_ref);
+        let short_dst = shorten_ref(&update.dst);
+        if old == new {
+            continue;
+        }
+
+        let rejection = if old == Hash::zero() || new == Hash::zero() || update.force {
+            None
+        } else if update.dst.starts_with("refs/tags/") {
+            Some("already exists")
+        } else if !has_object(repo, &old) {
+            Some("fetch first")
+        } else if !is_ancestor(repo, &old, &new)? {
+            Some("non-fast-forward")
+        } else {
+            None
+        };
+        if let Some(reason) = rejection {
+            failed = true;
+            report.push(format!(
+                " ! {:<17} {} -> {} ({})",
+                "[rejected]", short_src, short_dst, reason
+            ));
+            continue;
+        }
+        commands.push(transport::PushCommand {
+            old,
+            new,
+            name: update.dst.clone(),
+        });
+    }
+
+    if !commands.is_empty() {
+        // Send everything reachable from the new tips that the remote lacks
+        let tips: Vec<Hash> = commands
+            .iter()
+            .map(|command| command.new)
+            .filter(|hash| *hash != Hash::zero())
+            .collect();
+        let known: Vec<Hash> = advertisement
+            .refs
+            .iter()
+            .map(|remote_ref| remote_ref.hash)
+            .filter(|hash| has_object(repo, hash))
+            .collect();
+        let pack = packfile::pack_objects(
+            &list_objects(repo, &tips, &known)?,
+            packfile::DEFAULT_WINDOW,
+            packfile::DEFAULT_DEPTH,
+            advertisement.has_capability("ofs-delta"),
+            |hash| read_raw_object(repo, hash),
+        )?;
+
+        let status = transport::send_pack(&url, &advertisement, &commands, &pack)?;
+        if let Some(error) = &status.unpack_error {
+            failed = true;
+            eprintln!("error: remote unpack failed: {}", error);
+        }
+
+        let tracking: Vec<Refspec> = config
+            .get_all(&format!("remote.{}.fetch", remote))
+            .into_iter()
+            .map(Refspec::parse)
+            .collect::<io::Result<_>>()?;
+        for command in &commands {
+            let short_dst = shorten_ref(&command.name);
+            let short_src = updates
+                .iter()
+                .find(|update| update.dst == command.name)
+                .and_then(|update| update.src.as_deref())
+                .map_or("", shorten_ref);
+            if let Some((_, reason)) = status
+                .rejected
+                .iter()
+                .find(|(name, _)| *name == command.name)
+            {
+                failed = true;
+                let refs = if command.new == Hash::zero() {
+                    short_dst.to_string()
+                } else {
+                    format!("{} -> {}", short_src, short_dst)
+                };
+                report.push(format!(
+                    " ! {:<17} {} ({})",
+                    "[remote rejected]", refs, reason
+                ));
+                continue;
+            }
+            if status.unpack_error.is_some() {
+                continue;
+            }
+
+            report.push(if command.new == Hash::zero() {
+                format!(" - {:<17} {}", "[deleted]", short_dst)
+            } else if command.old == Hash::zero() {
+                let kind = if command.name.starts_with("refs/tags/") {
+                    "[new tag]"
+                } else {
+                    "[new branch]"
+                };
+                format!(" * {:<17} {} -> {}", kind, short_src, short_dst)
+            } else if is_ancestor(repo, &command.old, &command.new)? {
+                format!(
+                    "   {:<17} {} -> {}",
+                    format!("{}..{}", short_hash(&command.old), short_hash(&command.new)),
+                    short_src,
+                    short_dst
+                )
+            } else {
+                format!(
+                    " + {:<17} {} -> {} (forced update)",
+                    format!(
+                        "{}...{}",
+                        short_hash(&command.old),
+                        short_hash(&command.new)
+                    ),
+                    short_src,
+                    short_dst
+                )
+            });
+
+            // Keep the remote-tracking ref in step with what we pushed
+            if let Some(tracking_ref) = tracking
+                .iter()
+                .find_map(|spec| spec.map_to_dst(&command.name))
+            {
+                if command.new == Hash::zero() {
+                    delete_ref(repo, &tracking_ref)?;
+                } else {
+                    write_ref(repo, &tracking_ref, &command.new)?;
+                }
+            }
+        }
+    }
+
+    if report.is_empty() {
+        eprintln!("Everything up-to-date");
+    } else {
+        eprintln!("To {}", url);
+        for line in report {
+            eprintln!("{}", line);
+        }
+    }
+    if failed {
+        return Err(io::Error::other(format!(
+            "failed to push some refs to '{}'",
+            url
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,724 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::objects::Object;
+use crate::repository::Repository;
+use crate::{is_not_a_directory, read_object, Hash};
+
+/*
+Refs are names for objects, kept as files in the git directory:
+
+- `HEAD`, normally `ref: refs/heads/<branch>`: a symbolic ref, naming another
+  ref rather than an object; a detached HEAD holds a hash instead
+- under `refs/heads/` are branches, under `refs/tags/` tags and under
+  `refs/remotes/<remote>/` the branches of a remote as last fetched
+- `packed-refs` holds `<hash> <name>` lines for refs without a file of their
+  own; a loose file wins over a packed line of the same name
+
+Symbolic refs are followed at most five levels deep, and a chain that comes
+back to a ref already visited is an error rather than a hang. Refs are
+rewritten through `<name>.lock`, so readers never see half a ref and two
+writers cannot both win.
+*/
+
+/// How many symbolic refs may be followed in a row, as in git.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// What a ref holds, before any symbolic ref is followed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefValue {
+    Direct(Hash),
+    Symbolic(String),
+}
+
+/// Extra forms `check_ref_format` accepts.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RefFormat {
+    /// Names without a `/`, such as `main`.
+    pub allow_onelevel: bool,
+    /// A single `*` standing for one component, as in refspecs.
+    pub refspec_pattern: bool,
+}
+
+/// Whether `name` is a valid ref name by git's rules:
+///
+/// - no component is empty, starts with `.` or ends with `.lock`
+/// - no `..`, `@{`, control characters, space or any of ``~^:?*[\``
+/// - it does not end with `.` or `/`, and is not `@`
+/// - it has at least two components, unless `allow_onelevel`
+pub fn check_ref_format(name: &str, format: RefFormat) -> bool {
+    let mut stars = 0;
+    for component in name.split('/') {
+        if component.is_empty() || component.starts_with('.') || component.ends_with(".lock") {
+            return false;
+        }
+        for c in component.chars() {
+            match c {
+                '*' if format.refspec_pattern => stars += 1,
+                '\0'..=' ' | '\x7f' | '~' | '^' | ':' | '?' | '*' | '[' | '\\' => return false,
+                _ => {}
+            }
+        }
+    }
+    let onelevel = !name.contains('/');
+    !(name.contains("..")
+        || name.contains("@{")
+        || name.ends_with('.')
+        || name == "@"
+        || stars > 1
+        || (onelevel && !format.allow_onelevel))
+}
+
+/// Whether `name` can be a branch name, `refs/heads/` left out.
+pub fn is_valid_branch_name(name: &str) -> bool {
+    !name.starts_with('-')
+        && name != "HEAD"
+        && check_ref_format(&format!("refs/heads/{}", name), RefFormat::default())
+}
+
+/// Whether a ref of this name may be read or written: a valid name under
+/// `refs/`, or a top-level one in capitals such as `HEAD` or `FETCH_HEAD`.
+fn is_safe_name(name: &str) -> bool {
+    if name.starts_with("refs/") {
+        check_ref_format(name, RefFormat::default())
+    } else {
+        !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+    }
+}
+
+/// The value of a ref without following it, `None` if there is no such ref.
+pub fn read_raw_ref(repo: &Repository, name: &str) -> io::Result<Option<RefValue>> {
+    if !is_safe_name(name) {
+        return Ok(None);
+    }
+    let path = repo.path(name);
+    // A directory of refs is not a ref, though a packed one may share its name
+    let loose = match fs::read_to_string(&path) {
+        Ok(contents) => Some(contents),
+        Err(e)
+            if e.kind() == io::ErrorKind::NotFound || is_not_a_directory(&e) || path.is_dir() =>
+        {
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    match loose {
+        Some(contents) => {
+            let contents = contents.trim_end();
+            match contents.strip_prefix("ref: ") {
+                Some(target) => Ok(Some(RefValue::Symbolic(target.trim().to_string()))),
+                None => Hash::from_hex(contents)
+                    .map(|hash| Some(RefValue::Direct(hash)))
+                    .map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("bad ref {}: '{}'", name, contents),
+                        )
+                    }),
+            }
+        }
+        None => Ok(read_packed_refs(repo)?
+            .into_iter()
+            .find(|(packed, _)| packed == name)
+            .map(|(_, hash)| RefValue::Direct(hash))),
+    }
+}
+
+/// Follow `name` through symbolic refs to the ref holding a hash, returning
+/// that ref's name and hash. The hash is `None` when the ref does not exist
+/// yet, as with the branch HEAD names in a new repository.
+pub fn resolve_ref(repo: &Repository, name: &str) -> io::Result<(String, Option<Hash>)> {
+    let mut chain = vec![name.to_string()];
+    loop {
+        let current = &chain[chain.len() - 1];
+        let target = match read_raw_ref(repo, current)? {
+            None => return Ok((current.clone(), None)),
+            Some(RefValue::Direct(hash)) => return Ok((current.clone(), Some(hash))),
+            Some(RefValue::Symbolic(target)) => target,
+        };
+        if chain.contains(&target) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("symbolic ref loop: {} -> {}", chain.join(" -> "), target),
+            ));
+        }
+        if chain.len() > MAX_SYMREF_DEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Too many levels of symbolic refs at {}", name),
+            ));
+        }
+        chain.push(target);
+    }
+}
+
+/// Resolve a ref to a hash, following symbolic refs and `packed-refs`.
+pub fn read_ref(repo: &Repository, name: &str) -> io::Result<Option<Hash>> {
+    Ok(resolve_ref(repo, name)?.1)
+}
+
+/// The ref a symbolic ref points to, `None` if `name` is not symbolic.
+pub fn read_symbolic_ref(repo: &Repository, name: &str) -> io::Result<Option<String>> {
+    Ok(match read_raw_ref(repo, name)? {
+        Some(RefValue::Symbolic(target)) => Some(target),
+        _ => None,
+    })
+}
+
+/// The ref `HEAD` points to, e.g. `refs/heads/main`.
+pub fn current_branch(repo: &Repository) -> io::Result<String> {
+    read_symbolic_ref(repo, "HEAD")?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "HEAD is detached"))
+}
+
+pub fn read_packed_refs(repo: &Repository) -> io::Result<Vec<(String, Hash)>> {
+    let contents = match fs::read_to_string(repo.path("packed-refs")) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut refs = Vec::new();
+    for line in contents.lines() {
+        // Skip the header and the peeled values of tags
+        if line.starts_with('#') || line.starts_with('^') {
+            continue;
+        }
+        if let Some((hash, name)) = line.split_once(' ') {
+            refs.push((name.to_string(), Hash::from_hex(hash)?));
+        }
+    }
+    Ok(refs)
+}
+
+/// Every ref under `prefix` (e.g. `refs/heads/`), loose refs taking
+/// precedence over packed ones. Broken refs are skipped with a warning.
+pub fn list_refs(repo: &Repository, prefix: &str) -> io::Result<Vec<(String, Hash)>> {
+    fn walk(
+        repo: &Repository,
+        dir: &std::path::Path,
+        name: &str,
+        refs: &mut Vec<(String, Hash)>,
+    ) -> io::Result<()> {
+        let read_dir = match fs::read_dir(dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in read_dir {
+            let entry = entry?;
+            let child = format!("{}{}", name, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                walk(repo, &entry.path(), &format!("{}/", child), refs)?;
+            } else if child.ends_with(".lock") {
+                // A ref being written by someone else
+            } else {
+                match read_ref(repo, &child) {
+                    Ok(Some(hash)) => refs.push((child, hash)),
+                    Ok(None) => {}
+                    Err(_) => eprintln!("warning: ignoring broken ref {}", child),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut refs = Vec::new();
+    walk(repo, &repo.path(prefix), prefix, &mut refs)?;
+    for (name, hash) in read_packed_refs(repo)? {
+        if name.starts_with(prefix) && !refs.iter().any(|(loose, _)| *loose == name) {
+            refs.push((name, hash));
+        }
+    }
+    refs.sort();
+    Ok(refs)
+}
+
+/// `<ref>.lock`, held while a ref is rewritten. Committing renames it over
+/// the ref; dropping it leaves the ref untouched.
+struct RefLock {
+    path: PathBuf,
+    lock_path: PathBuf,
+    committed: bool,
+}
+
+impl RefLock {
+    fn acquire(repo: &Repository, name: &str) -> io::Result<RefLock> {
+        let path = repo.path(name);
+        let mut lock_name = path.as_os_str().to_owned();
+        lock_name.push(".lock");
+        let lock_path = PathBuf::from(lock_name);
+        let cannot_lock =
+            |reason: String| io::Error::other(format!("cannot lock ref '{}': {}", name, reason));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| cannot_lock(e.to_string()))?;
+        }
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => cannot_lock(format!(
+                    "Unable to create '{}': File exists.",
+                    lock_path.display()
+                )),
+                _ => cannot_lock(e.to_string()),
+            })?;
+        Ok(RefLock {
+            path,
+            lock_path,
+            committed: false,
+        })
+    }
+
+    /// Write `contents` to the lock file and move it into place.
+    fn commit(mut self, contents: &str) -> io::Result<()> {
+        fs::write(&self.lock_path, contents)?;
+        fs::rename(&self.lock_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Point `name` at `new`, or delete it when `new` is `None`.
+///
+/// With `expected`, the ref must hold that value when it is locked, `None`
+/// meaning it must not exist. With `deref`, a symbolic ref is followed and
+/// the ref it ends at is updated instead.
+pub fn update_ref(
+    repo: &Repository,
+    name: &str,
+    new: Option<&Hash>,
+    expected: Option<Option<Hash>>,
+    deref: bool,
+) -> io::Result<()> {
+    if !is_safe_name(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("refusing to update ref with bad name '{}'", name),
+        ));
+    }
+    let name = match deref {
+        true => resolve_ref(repo, name)?.0,
+        false => name.to_string(),
+    };
+    let lock = RefLock::acquire(repo, &name)?;
+
+    if let Some(expected) = expected {
+        let current = read_ref(repo, &name)?;
+        let problem = match (current, expected) {
+            (None, None) => None,
+            (Some(current), Some(expected)) if current == expected => None,
+            (Some(_), None) => Some("reference already exists".to_string()),
+            (None, Some(_)) => Some(format!("unable to resolve reference '{}'", name)),
+            (Some(current), Some(expected)) => Some(format!(
+                "is at {} but expected {}",
+                current.to_hex(),
+                expected.to_hex()
+            )),
+        };
+        if let Some(problem) = problem {
+            return Err(io::Error::other(format!(
+                "cannot lock ref '{}': {}",
+                name, problem
+            )));
+        }
+    }
+
+    match new {
+        Some(hash) => lock.commit(&format!("{}\n", hash.to_hex())),
+        None => {
+            match fs::remove_file(repo.path(&name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            remove_packed_ref(repo, &name)?;
+            drop(lock);
+            // Leave no empty directories behind under refs/
+            let refs_dir = repo.path("refs");
+            for dir in repo.path(&name).ancestors().skip(1) {
+                if dir == refs_dir || !dir.starts_with(&refs_dir) || fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Drop `name` from `packed-refs`, along with the peeled value after it.
+fn remove_packed_ref(repo: &Repository, name: &str) -> io::Result<()> {
+    let contents = match fs::read_to_string(repo.path("packed-refs")) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let mut kept = String::new();
+    let mut removed = false;
+    let mut skipping = false;
+    for line in contents.lines() {
+        if line.starts_with('^') && skipping {
+            continue;
+        }
+        skipping = line
+            .split_once(' ')
+            .is_some_and(|(_, packed)| packed == name);
+        if skipping {
+            removed = true;
+            continue;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    if removed {
+        RefLock::acquire(repo, "packed-refs")?.commit(&kept)?;
+    }
+    Ok(())
+}
+
+/// Move every loose ref into `packed-refs`, with the peeled value of each
+/// annotated tag. Symbolic refs stay loose.
+pub fn pack_refs(repo: &Repository) -> io::Result<()> {
+    // Only one writer of packed-refs at a time; loose refs may still move
+    // while they are being listed, which is settled below
+    let lock = RefLock::acquire(repo, "packed-refs")?;
+    let mut contents = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    let mut packed = Vec::new();
+    for (name, hash) in list_refs(repo, "refs/")? {
+        if let Some(RefValue::Symbolic(_)) = read_raw_ref(repo, &name)? {
+            continue;
+        }
+        contents.push_str(&format!("{} {}\n", hash.to_hex(), name));
+
+        let mut peeled = hash;
+        while let Object::Tag(tag) = read_object(repo, &peeled)? {
+            peeled = tag.object;
+        }
+        if peeled != hash {
+            contents.push_str(&format!("^{}\n", peeled.to_hex()));
+        }
+        packed.push((name, hash));
+    }
+    lock.commit(&contents)?;
+
+    // A loose ref only goes if it still holds the value just packed, checked
+    // under its own lock; one updated in the meantime stays and wins over
+    // the stale packed line
+    for (name, hash) in packed {
+        let Ok(ref_lock) = RefLock::acquire(repo, &name) else {
+            continue;
+        };
+        if read_raw_ref(repo, &name)? == Some(RefValue::Direct(hash)) {
+            match fs::remove_file(repo.path(&name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        drop(ref_lock);
+    }
+    Ok(())
+}
+
+pub fn write_ref(repo: &Repository, name: &str, hash: &Hash) -> io::Result<()> {
+    update_ref(repo, name, Some(hash), None, false)
+}
+
+pub fn delete_ref(repo: &Repository, name: &str) -> io::Result<()> {
+    update_ref(repo, name, None, None, false)
+}
+
+pub fn write_symbolic_ref(repo: &Repository, name: &str, target: &str) -> io::Result<()> {
+    if !is_safe_name(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("refusing to update ref with bad name '{}'", name),
+        ));
+    }
+    RefLock::acquire(repo, name)?.commit(&format!("ref: {}\n", target))
+}
+
+/// Prefix a short ref name with `prefix` unless it is already qualified.
+pub fn qualify_ref(name: &str, prefix: &str) -> String {
+    if name.starts_with("refs/") {
+        name.to_string()
+    } else {
+        format!("{}{}", prefix, name)
+    }
+}
+
+pub fn shorten_ref(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Expand a short ref name the way git does, returning the first existing
+/// ref of: the name itself, then under `refs/`, `refs/tags/`, `refs/heads/`,
+/// `refs/remotes/`, and finally `refs/remotes/<name>/HEAD`.
+pub fn expand_ref(repo: &Repository, name: &str) -> io::Result<Option<String>> {
+    for candidate in [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ] {
+        if read_ref(repo, &candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Expand a short local ref name the way git does: as-is, then as a
+/// branch, then as a tag. `HEAD` stands for the current branch.
+pub fn resolve_local_ref(repo: &Repository, name: &str) -> io::Result<String> {
+    if name == "HEAD" {
+        return current_branch(repo);
+    }
+    for candidate in [
+        name.to_string(),
+        format!("refs/heads/{}", name),
+        format!("refs/tags/{}", name),
+    ] {
+        if candidate.starts_with("refs/") && read_ref(repo, &candidate)?.is_some() {
+            return Ok(candidate);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("src refspec {} does not match any", name),
+    ))
+}
+
+#[derive(Debug, Default)]
+pub struct ShowRefOptions {
+    /// Only branches, or only tags; both or neither mean any ref.
+    pub heads: bool,
+    pub tags: bool,
+    /// Show HEAD too, whatever the patterns.
+    pub head: bool,
+    /// Follow each tag to the object it finally points at, shown as `<tag>^{}`.
+    pub dereference: bool,
+    /// Print only the hash, cut to this many digits.
+    pub hash_only: Option<usize>,
+    /// Patterns are full ref names that must all exist.
+    pub verify: bool,
+    pub quiet: bool,
+}
+
+/// List the refs matching `patterns` (all of them if there are none), a
+/// pattern matching a whole ref name or its last components. Returns
+/// whether anything matched.
+pub fn show_ref(
+    repo: &Repository,
+    patterns: &[&str],
+    options: &ShowRefOptions,
+) -> io::Result<bool> {
+    let mut refs = Vec::new();
+    if options.verify {
+        for pattern in patterns {
+            let full = *pattern == "HEAD" || pattern.starts_with("refs/");
+            match read_ref(repo, pattern)?.filter(|_| full) {
+                Some(hash) => refs.push((pattern.to_string(), hash)),
+                None if options.quiet => return Ok(false),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("'{}' - not a valid ref", pattern),
+                    ))
+                }
+            }
+        }
+    } else {
+        if options.head {
+            refs.extend(read_ref(repo, "HEAD")?.map(|hash| ("HEAD".to_string(), hash)));
+        }
+        for (name, hash) in list_refs(repo, "refs/")? {
+            let kind = (!options.heads && !options.tags)
+                || (options.heads && name.starts_with("refs/heads/"))
+                || (options.tags && name.starts_with("refs/tags/"));
+            let matched = patterns.is_empty()
+                || patterns
+                    .iter()
+                    .any(|pattern| name == *pattern || name.ends_with(&format!("/{}", pattern)));
+            if kind && matched {
+                refs.push((name, hash));
+            }
+        }
+    }
+
+    if !options.quiet {
+        let show = |name: &str, hash: &Hash| match options.hash_only {
+            Some(digits) => println!("{}", &hash.to_hex()[..digits]),
+            None => println!("{} {}", hash.to_hex(), name),
+        };
+        for (name, hash) in &refs {
+            show(name, hash);
+            if !options.dereference {
+                continue;
+            }
+            let mut peeled = None;
+            while let Object::Tag(tag) = read_object(repo, peeled.as_ref().unwrap_or(hash))? {
+                peeled = Some(tag.object);
+            }
+            if let Some(peeled) = peeled {
+                show(&format!("{}^{{}}", name), &peeled);
+            }
+        }
+    }
+    Ok(!refs.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InitOptions;
+    use crate::test_support::{git, git_available, ScratchDir};
+
+    #[test]
+    fn ref_names_follow_git_rules() {
+        let plain = RefFormat::default();
+        let onelevel = RefFormat {
+            allow_onelevel: true,
+            ..RefFormat::default()
+        };
+        let pattern = RefFormat {
+            refspec_pattern: true,
+            ..RefFormat::default()
+        };
+        let cases = [
+            ("refs/heads/main", plain, true),
+            ("refs/heads/feature/x-1", plain, true),
+            ("refs/heads/a.b", plain, true),
+            ("main", plain, false),
+            ("main", onelevel, true),
+            ("refs/heads/a..b", plain, false),
+            ("refs/heads/a@{1}", plain, false),
+            ("refs/heads/a@b", plain, true),
+            ("@", onelevel, false),
+            ("refs/heads/main.lock", plain, false),
+            ("refs/heads.lock/main", plain, false),
+            ("refs/heads/.hidden", plain, false),
+            ("refs/heads/main.", plain, false),
+            ("refs/heads/main/", plain, false),
+            ("refs//heads", plain, false),
+            ("/refs/heads", plain, false),
+            ("refs/heads/a b", plain, false),
+            ("refs/heads/tab\there", plain, false),
+            ("refs/heads/bell\x07", plain, false),
+            ("refs/heads/del\x7f", plain, false),
+            ("refs/heads/a~1", plain, false),
+            ("refs/heads/a^", plain, false),
+            ("refs/heads/a:b", plain, false),
+            ("refs/heads/a?", plain, false),
+            ("refs/heads/a[b", plain, false),
+            ("refs/heads/a\\b", plain, false),
+            ("refs/heads/*", plain, false),
+            ("refs/heads/*", pattern, true),
+            ("refs/heads/foo*", pattern, true),
+            ("refs/*/*", pattern, false),
+        ];
+        for (name, format, expected) in cases {
+            assert_eq!(check_ref_format(name, format), expected, "{:?}", name);
+        }
+
+        assert!(is_valid_branch_name("feature/x"));
+        assert!(!is_valid_branch_name("-n"));
+        assert!(!is_valid_branch_name("HEAD"));
+        assert!(!is_valid_branch_name("a..b"));
+    }
+
+    #[test]
+    fn symbolic_refs_resolve_within_limits() {
+        let dir = ScratchDir::new("refs-symrefs");
+        let repo = Repository::init(
+            dir.path(),
+            &InitOptions {
+                bare: true,
+                initial_branch: "main",
+                ..InitOptions::default()
+            },
+        )
+        .unwrap()
+        .0;
+        let hash = Hash::from_hex("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391").unwrap();
+
+        // An unborn branch resolves to its name with no hash
+        assert_eq!(
+            resolve_ref(&repo, "HEAD").unwrap(),
+            ("refs/heads/main".to_string(), None)
+        );
+        write_ref(&repo, "refs/heads/main", &hash).unwrap();
+        assert_eq!(read_ref(&repo, "HEAD").unwrap(), Some(hash));
+
+        // HEAD -> s1 -> ... -> s5 -> main is the longest chain followed
+        let mut target = "refs/heads/main".to_string();
+        for i in 1..=5 {
+            let name = format!("refs/s{}", i);
+            write_symbolic_ref(&repo, &name, &target).unwrap();
+            target = name;
+        }
+        assert_eq!(read_ref(&repo, "refs/s5").unwrap(), Some(hash));
+        write_symbolic_ref(&repo, "refs/s6", "refs/s5").unwrap();
+        let error = read_ref(&repo, "refs/s6").unwrap_err();
+        assert!(
+            error.to_string().starts_with("Too many levels"),
+            "{}",
+            error
+        );
+
+        write_symbolic_ref(&repo, "refs/loop/a", "refs/loop/b").unwrap();
+        write_symbolic_ref(&repo, "refs/loop/b", "refs/loop/a").unwrap();
+        let error = read_ref(&repo, "refs/loop/a").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "symbolic ref loop: refs/loop/a -> refs/loop/b -> refs/loop/a"
+        );
+
+        // Names that are not refs are neither read nor written
+        assert_eq!(read_raw_ref(&repo, "refs/../config").unwrap(), None);
+        assert!(write_ref(&repo, "refs/heads/a..b", &hash).is_err());
+        assert!(write_symbolic_ref(&repo, "../HEAD", "refs/heads/main").is_err());
+    }
+
+    #[test]
+    fn pack_refs_keeps_loose_refs_it_cannot_lock() {
+        if !git_available() {
+            return;
+        }
+        let dir = ScratchDir::new("refs-pack");
+        git(dir.path(), &["init", "-q"]);
+        git(
+            dir.path(),
+            &["commit", "-q", "--allow-empty", "-m", "first"],
+        );
+        git(dir.path(), &["branch", "busy"]);
+        git(dir.path(), &["tag", "-a", "-m", "tag", "v1"]);
+        let repo = Repository::at_work_tree(dir.path()).unwrap();
+        let head = read_ref(&repo, "HEAD").unwrap().unwrap();
+
+        let lock = RefLock::acquire(&repo, "refs/heads/busy").unwrap();
+        pack_refs(&repo).unwrap();
+        drop(lock);
+
+        assert!(!repo.path("refs/heads/main").exists());
+        assert!(!repo.path("refs/tags/v1").exists());
+        assert!(repo.path("refs/heads/busy").exists());
+        assert_eq!(read_ref(&repo, "refs/heads/main").unwrap(), Some(head));
+        assert_eq!(read_ref(&repo, "refs/heads/busy").unwrap(), Some(head));
+        let listed = git(dir.path(), &["show-ref", "--dereference"]);
+        assert_eq!(String::from_utf8(listed).unwrap().lines().count(), 4);
+        git(dir.path(), &["fsck", "--no-progress"]);
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+
+/// A refspec such as `+refs/heads/*:refs/remotes/origin/*`.
+///
+/// `src` and `dst` may each contain a single `*`, which matches the same
+/// text on both sides. A leading `+` allows non-fast-forward updates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    pub force: bool,
+    pub src: String,
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> io::Result<Refspec> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src, (!dst.is_empty()).then(|| dst.to_string())),
+            None => (spec, None),
+        };
+
+        let globs = |side: &str| side.matches('*').count();
+        let balanced = match &dst {
+            Some(dst) => globs(src) == globs(dst),
+            None => true,
+        };
+        if globs(src) > 1 || !balanced {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid refspec '{}'", spec),
+            ));
+        }
+
+        Ok(Refspec {
+            force,
+            src: src.to_string(),
+            dst,
+        })
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    /// Whether `name` matches the source side.
+    pub fn matches_src(&self, name: &str) -> bool {
+        match_glob(&self.src, name).is_some()
+    }
+
+    /// Map `name` from the source side to the destination side.
+    pub fn map_to_dst(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_ref()?;
+        let matched = match_glob(&self.src, name)?;
+        Some(dst.replacen('*', matched, 1))
+    }
+}
+
+/// Match `name` against a pattern with at most one `*`, returning the text
+/// the `*` stands for (empty for an exact match).
+fn match_glob<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => name
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(suffix)),
+        None => (pattern == name).then_some(""),
+    }
+}
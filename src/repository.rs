@@ -0,0 +1,344 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::config::Config;
+use crate::pack_index::{self, Pack};
+use crate::refs::is_valid_branch_name;
+
+/*
+Finding the repository for the current directory works like git:
+
+- GIT_DIR names the git directory outright; the work tree is then
+  GIT_WORK_TREE, or the current directory
+- otherwise each directory from the current one upwards is tried: a `.git`
+  directory, a `.git` file (`gitdir: <path>`) or the directory itself being a
+  bare repository
+- the walk never moves up into a directory listed in GIT_CEILING_DIRECTORIES
+
+A repository whose config sets `core.bare = true` has no work tree unless
+GIT_WORK_TREE gives one.
+*/
+
+/// How `Repository::init` lays out a new repository.
+#[derive(Debug, Clone)]
+pub struct InitOptions<'a> {
+    pub bare: bool,
+    /// Branch the unborn `HEAD` points to.
+    pub initial_branch: &'a str,
+    /// Files copied into the new git directory, e.g. `hooks/` and `info/`.
+    pub template: Option<&'a Path>,
+    /// `sha1` or `sha256`; `None` keeps an existing repository's format and
+    /// gives a new one SHA-1.
+    pub object_format: Option<&'a str>,
+}
+
+impl Default for InitOptions<'_> {
+    fn default() -> Self {
+        InitOptions {
+            bare: false,
+            initial_branch: "master",
+            template: None,
+            object_format: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Repository {
+    git_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    /// The packs in `objects/pack`, loaded on first use and shared by every
+    /// object lookup after it.
+    packs: RefCell<Option<Rc<[Rc<Pack>]>>>,
+}
+
+fn not_a_repository(path: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("not a git repository: {}", path),
+    )
+}
+
+/// The hash algorithm a repository's objects are named with.
+fn object_format(config: &Config) -> &str {
+    config.get("extensions.objectformat").unwrap_or("sha1")
+}
+
+/// Whether `path` has the layout of a git directory.
+fn is_git_dir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+/// Follow a `.git` file to the git directory it names.
+fn read_gitfile(path: &Path) -> io::Result<PathBuf> {
+    let contents = fs::read_to_string(path)?;
+    let target = contents
+        .trim_end()
+        .strip_prefix("gitdir: ")
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid gitfile format: {}", path.display()),
+            )
+        })?;
+    // A relative path is relative to the directory holding the file
+    Ok(path.parent().unwrap_or(Path::new(".")).join(target))
+}
+
+/// Copy the files under `template` into `dest`, keeping any that exist.
+fn copy_template(template: &Path, dest: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(template)? {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&target)?;
+            copy_template(&entry.path(), &target)?;
+        } else if !target.exists() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Ceiling directories that lie above `cwd`.
+fn ceiling_directories(cwd: &Path) -> Vec<PathBuf> {
+    let Some(ceilings) = env::var_os("GIT_CEILING_DIRECTORIES") else {
+        return Vec::new();
+    };
+    env::split_paths(&ceilings)
+        .filter(|ceiling| ceiling.is_absolute())
+        .map(|ceiling| fs::canonicalize(&ceiling).unwrap_or(ceiling))
+        .filter(|ceiling| cwd.starts_with(ceiling) && cwd != ceiling)
+        .collect()
+}
+
+impl Repository {
+    /// Create a repository at `path`, or fill in what is missing from an
+    /// existing one. Returns the repository and whether it already existed;
+    /// an existing `HEAD` and `config` are never overwritten.
+    pub fn init(path: &Path, options: &InitOptions) -> io::Result<(Repository, bool)> {
+        fs::create_dir_all(path)?;
+        let path = fs::canonicalize(path)?;
+        let (git_dir, work_tree) = if options.bare {
+            (path.clone(), None)
+        } else {
+            (path.join(".git"), Some(path))
+        };
+        let existed = is_git_dir(&git_dir);
+
+        let format = options.object_format.unwrap_or("sha1");
+        if format != "sha1" && format != "sha256" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown hash algorithm '{}'", format),
+            ));
+        }
+        if !is_valid_branch_name(options.initial_branch) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid initial branch name: '{}'", options.initial_branch),
+            ));
+        }
+        if existed {
+            let current = Config::load(&git_dir.join("config"))?;
+            if options
+                .object_format
+                .is_some_and(|format| format != object_format(&current))
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "attempt to reinitialize repository with different hash",
+                ));
+            }
+        }
+
+        for dir in [
+            "",
+            "objects",
+            "objects/info",
+            "objects/pack",
+            "refs",
+            "refs/heads",
+            "refs/tags",
+        ] {
+            fs::create_dir_all(git_dir.join(dir))?;
+        }
+        if let Some(template) = options.template {
+            copy_template(template, &git_dir)?;
+        }
+
+        let head = git_dir.join("HEAD");
+        if !head.exists() {
+            fs::write(
+                &head,
+                format!("ref: refs/heads/{}\n", options.initial_branch),
+            )?;
+        }
+        let config = git_dir.join("config");
+        if !config.exists() {
+            // Extensions such as the object format need format version 1
+            let version = if format == "sha1" { 0 } else { 1 };
+            let mut contents = format!(
+                "[core]\n\trepositoryformatversion = {}\n\tfilemode = {}\n\tbare = {}\n",
+                version,
+                cfg!(unix),
+                options.bare
+            );
+            if !options.bare {
+                contents.push_str("\tlogallrefupdates = true\n");
+            }
+            if format != "sha1" {
+                contents.push_str(&format!("[extensions]\n\tobjectformat = {}\n", format));
+            }
+            fs::write(&config, contents)?;
+        }
+
+        Ok((Repository::new(git_dir, work_tree), existed))
+    }
+
+    /// Find the repository the current directory belongs to.
+    pub fn discover() -> io::Result<Repository> {
+        let cwd = env::current_dir()?;
+        let work_tree_override = env::var_os("GIT_WORK_TREE").map(|path| cwd.join(path));
+
+        if let Some(git_dir) = env::var_os("GIT_DIR") {
+            let git_dir = cwd.join(&git_dir);
+            if !is_git_dir(&git_dir) {
+                return Err(not_a_repository(&git_dir.display().to_string()));
+            }
+            return Repository::open(git_dir, Some(cwd), work_tree_override);
+        }
+
+        let ceilings = ceiling_directories(&cwd);
+        let mut dir = cwd.as_path();
+        loop {
+            let dot_git = dir.join(".git");
+            if dot_git.is_file() {
+                let git_dir = read_gitfile(&dot_git)?;
+                if !is_git_dir(&git_dir) {
+                    return Err(not_a_repository(&git_dir.display().to_string()));
+                }
+                return Repository::open(git_dir, Some(dir.to_path_buf()), work_tree_override);
+            }
+            if is_git_dir(&dot_git) {
+                return Repository::open(dot_git, Some(dir.to_path_buf()), work_tree_override);
+            }
+            if is_git_dir(dir) {
+                return Repository::open(dir.to_path_buf(), None, work_tree_override);
+            }
+
+            match dir.parent() {
+                Some(parent) if !ceilings.iter().any(|ceiling| ceiling.starts_with(parent)) => {
+                    dir = parent
+                }
+                _ => break,
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "not a git repository (or any of the parent directories): .git",
+        ))
+    }
+
+    /// The repository whose work tree is `dir`, if `dir` holds a `.git`
+    /// directory or file. Used for repositories nested in another's work
+    /// tree, which are recorded as gitlinks.
+    pub fn at_work_tree(dir: &Path) -> Option<Repository> {
+        let dot_git = dir.join(".git");
+        let git_dir = if dot_git.is_file() {
+            read_gitfile(&dot_git).ok()?
+        } else {
+            dot_git
+        };
+        is_git_dir(&git_dir).then(|| Repository::new(git_dir, Some(dir.to_path_buf())))
+    }
+
+    /// Settle the work tree of a found git directory: an explicit one wins,
+    /// then `core.bare` decides whether the default applies.
+    fn open(
+        git_dir: PathBuf,
+        default_work_tree: Option<PathBuf>,
+        work_tree_override: Option<PathBuf>,
+    ) -> io::Result<Repository> {
+        let config = Config::load(&git_dir.join("config"))?;
+        // Objects are named by SHA-1 hashes throughout; init can lay out a
+        // SHA-256 repository, but nothing else can work in one
+        if object_format(&config) != "sha1" {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{}: the {} object format is not supported",
+                    git_dir.display(),
+                    object_format(&config)
+                ),
+            ));
+        }
+        let work_tree = match (work_tree_override, config.get_bool("core.bare")?) {
+            (Some(work_tree), _) => Some(work_tree),
+            (None, Some(true)) => None,
+            (None, _) => default_work_tree,
+        };
+        Ok(Repository::new(git_dir, work_tree))
+    }
+
+    fn new(git_dir: PathBuf, work_tree: Option<PathBuf>) -> Repository {
+        Repository {
+            git_dir,
+            work_tree,
+            packs: RefCell::new(None),
+        }
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// A path inside the git directory, e.g. `path("objects/pack")`.
+    pub fn path(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.git_dir.join(relative)
+    }
+
+    pub fn objects_dir(&self) -> PathBuf {
+        self.path("objects")
+    }
+
+    pub fn pack_dir(&self) -> PathBuf {
+        self.path("objects/pack")
+    }
+
+    /// The checked out files, failing for a bare repository.
+    pub fn work_tree(&self) -> io::Result<&Path> {
+        self.work_tree.as_deref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "this operation must be run in a work tree",
+            )
+        })
+    }
+
+    /// The packs in `objects/pack`, read once and then reused.
+    pub fn packs(&self) -> io::Result<Rc<[Rc<Pack>]>> {
+        if let Some(packs) = &*self.packs.borrow() {
+            return Ok(packs.clone());
+        }
+        self.reload_packs()
+    }
+
+    /// Look at `objects/pack` again, for packs added or removed since the
+    /// last look. Packs seen before keep their open files and caches.
+    pub fn reload_packs(&self) -> io::Result<Rc<[Rc<Pack>]>> {
+        let loaded = self.packs.borrow_mut().take();
+        let packs: Rc<[Rc<Pack>]> =
+            pack_index::load_packs(&self.pack_dir(), loaded.as_deref().unwrap_or_default())?.into();
+        *self.packs.borrow_mut() = Some(packs.clone());
+        Ok(packs)
+    }
+
+    pub fn config(&self) -> io::Result<Config> {
+        Config::load(&self.path("config"))
+    }
+}
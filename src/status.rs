@@ -0,0 +1,882 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::env;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::Path;
+
+use crate::ignore::Ignore;
+use crate::index::Index;
+use crate::objects::Object;
+use crate::refs::{read_ref, read_symbolic_ref, shorten_ref};
+use crate::refspec::Refspec;
+use crate::repository::Repository;
+use crate::{
+    compute_sha1, is_not_a_directory, read_object, read_tree, read_work_file, short_hash,
+    work_tree_mode, Hash,
+};
+
+/*
+status compares three snapshots of the tree:
+
+HEAD's tree  <->  the index  <->  the work tree
+   staged changes      unstaged changes
+
+Each changed path gets a two letter code, staged then unstaged: ' ' for
+unchanged, M(odified), T(ype changed), A(dded), D(eleted), R(enamed). Paths
+with merge conflicts carry a code for which sides of the merge touched them
+instead (UU, AA, DU, ...). Files in neither HEAD nor the index are untracked;
+a directory holding nothing but untracked files is shown as `dir/`. Files
+matching the ignore rules are left out, or listed apart with `--ignored`.
+
+Renames are only detected between HEAD and the index, and only when the
+contents are identical.
+*/
+
+/// The blob with no contents, which is never paired up as a rename.
+const EMPTY_BLOB: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Long,
+    Short,
+    PorcelainV1,
+    PorcelainV2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Untracked {
+    No,
+    /// Untracked directories are shown as a whole.
+    Normal,
+    All,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusOptions {
+    pub format: Format,
+    /// Show the branch and its upstream in the short formats.
+    pub branch: bool,
+    pub untracked: Untracked,
+    /// Also list ignored files.
+    pub ignored: bool,
+}
+
+/// A mode and object, as found in one of the snapshots.
+type Version = Option<(u32, Hash)>;
+
+#[derive(Debug, Clone)]
+struct Change {
+    path: String,
+    /// Where a staged rename came from.
+    orig_path: Option<String>,
+    staged: char,
+    unstaged: char,
+    head: Version,
+    index: Version,
+    /// Mode of the file on disk, 0 when it is missing.
+    worktree_mode: u32,
+    /// The base, ours and theirs stages of a conflicted path.
+    conflict: Option<[Version; 3]>,
+}
+
+#[derive(Debug, Clone)]
+struct BranchInfo {
+    head: Option<Hash>,
+    /// Short name of the checked out branch; `None` when detached.
+    branch: Option<String>,
+    /// Full ref name of the upstream branch.
+    upstream: Option<String>,
+    /// Commits ahead and behind the upstream; `None` if it no longer exists.
+    ahead_behind: Option<(usize, usize)>,
+    /// A merge is in progress (`MERGE_HEAD` exists).
+    merging: bool,
+}
+
+pub fn status(repo: &Repository, options: &StatusOptions) -> io::Result<()> {
+    let work_tree = repo.work_tree()?;
+    let index = Index::load(&repo.path("index"))?;
+    let branch = branch_info(repo)?;
+
+    let mut head_tree = BTreeMap::new();
+    if let Some(head) = branch.head {
+        match read_object(repo, &head)? {
+            Object::Commit(commit) => read_tree(repo, &commit.tree, "", &mut head_tree)?,
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("HEAD points to a {}, not a commit", other.kind()),
+                ))
+            }
+        }
+    }
+
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+    let mut changes = compare(work_tree, &index, &head_tree, filemode)?;
+    detect_renames(&mut changes);
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut others = Others::default();
+    if options.untracked != Untracked::No || options.ignored {
+        let tracked: HashSet<&str> = index.entries().iter().map(|e| e.path.as_str()).collect();
+        let tracked_dirs: HashSet<&str> = index
+            .entries()
+            .iter()
+            .flat_map(|entry| entry.path.match_indices('/').map(|(i, _)| &entry.path[..i]))
+            .collect();
+        let mut walk = OthersWalk {
+            work_tree,
+            tracked: &tracked,
+            ignore: Ignore::new(repo)?,
+            all: options.untracked == Untracked::All,
+            others: Others::default(),
+        };
+        walk.visit("", false)?;
+        others = walk.others;
+        if options.untracked != Untracked::All {
+            others.collapse(&tracked_dirs);
+        }
+        if options.untracked == Untracked::No {
+            others.untracked.clear();
+        }
+        if !options.ignored {
+            others.ignored.clear();
+        }
+    }
+
+    let prefix = env::current_dir()
+        .ok()
+        .and_then(|cwd| {
+            cwd.strip_prefix(work_tree)
+                .ok()
+                .and_then(|p| p.to_str().map(str::to_string))
+        })
+        .unwrap_or_default();
+    match options.format {
+        Format::Long => print_long(&branch, &changes, &others, options, &prefix),
+        Format::Short => print_short(&branch, &changes, &others, options, &prefix),
+        Format::PorcelainV1 => print_short(&branch, &changes, &others, options, ""),
+        Format::PorcelainV2 => print_porcelain_v2(&branch, &changes, &others, options),
+    }
+    Ok(())
+}
+
+fn branch_info(repo: &Repository) -> io::Result<BranchInfo> {
+    let head = read_ref(repo, "HEAD")?;
+    let merging = repo.path("MERGE_HEAD").exists();
+    let Some(branch) = read_symbolic_ref(repo, "HEAD")?.map(|name| shorten_ref(&name).to_string())
+    else {
+        return Ok(BranchInfo {
+            head,
+            branch: None,
+            upstream: None,
+            ahead_behind: None,
+            merging,
+        });
+    };
+
+    let config = repo.config()?;
+    let upstream = match (
+        config.get(&format!("branch.{}.remote", branch)),
+        config.get(&format!("branch.{}.merge", branch)),
+    ) {
+        (Some("."), Some(merge)) => Some(merge.to_string()),
+        (Some(remote), Some(merge)) => config
+            .get_all(&format!("remote.{}.fetch", remote))
+            .into_iter()
+            .filter_map(|spec| Refspec::parse(spec).ok())
+            .find_map(|spec| spec.map_to_dst(merge)),
+        _ => None,
+    };
+    let ahead_behind = match (&upstream, head) {
+        (Some(upstream), Some(head)) => match read_ref(repo, upstream)? {
+            Some(upstream) => {
+                let ours = ancestors(repo, head)?;
+                let theirs = ancestors(repo, upstream)?;
+                Some((
+                    ours.difference(&theirs).count(),
+                    theirs.difference(&ours).count(),
+                ))
+            }
+            None => None,
+        },
+        // An unborn branch is behind by everything upstream
+        (Some(upstream), None) => match read_ref(repo, upstream)? {
+            Some(upstream) => Some((0, ancestors(repo, upstream)?.len())),
+            None => None,
+        },
+        _ => None,
+    };
+
+    Ok(BranchInfo {
+        head,
+        branch: Some(branch),
+        upstream,
+        ahead_behind,
+        merging,
+    })
+}
+
+/// Every commit reachable from `tip`, itself included.
+fn ancestors(repo: &Repository, tip: Hash) -> io::Result<HashSet<Hash>> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([tip]);
+    while let Some(hash) = queue.pop_front() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        if let Object::Commit(commit) = read_object(repo, &hash)? {
+            queue.extend(commit.parents);
+        }
+    }
+    Ok(seen)
+}
+
+/// The staged and unstaged changes of every path in HEAD or the index.
+fn compare(
+    work_tree: &Path,
+    index: &Index,
+    head_tree: &BTreeMap<String, (u32, Hash)>,
+    filemode: bool,
+) -> io::Result<Vec<Change>> {
+    let mut conflicts: BTreeMap<&str, [Version; 3]> = BTreeMap::new();
+    let mut changes = Vec::new();
+    for entry in index.entries() {
+        if entry.stage != 0 {
+            let stages = conflicts.entry(&entry.path).or_default();
+            stages[entry.stage as usize - 1] = Some((entry.mode, entry.hash));
+            continue;
+        }
+
+        let full_path = work_tree.join(&entry.path);
+        let head = head_tree.get(&entry.path).copied();
+        let index_version = Some((entry.mode, entry.hash));
+
+        // Only the commit a submodule has checked out is compared, not its
+        // files; one that is not checked out is an empty directory
+        if entry.mode == 0o160000 {
+            let unstaged = match Repository::at_work_tree(&full_path) {
+                Some(nested) if read_ref(&nested, "HEAD")?.is_some_and(|c| c != entry.hash) => 'M',
+                Some(_) => ' ',
+                None if full_path.is_dir() => ' ',
+                None => 'D',
+            };
+            let staged = diff_code(head, index_version);
+            if staged != ' ' || unstaged != ' ' {
+                changes.push(Change {
+                    path: entry.path.clone(),
+                    orig_path: None,
+                    staged,
+                    unstaged,
+                    head,
+                    index: index_version,
+                    worktree_mode: if unstaged == 'D' { 0 } else { entry.mode },
+                    conflict: None,
+                });
+            }
+            continue;
+        }
+
+        let metadata = worktree_metadata(&full_path)?;
+        let worktree_mode = match &metadata {
+            Some(metadata) => file_mode(metadata, entry.mode, filemode),
+            None => 0,
+        };
+        let unstaged = match &metadata {
+            None => 'D',
+            Some(_) if entry.is_intent_to_add() => 'A',
+            Some(_) if worktree_mode >> 12 != entry.mode >> 12 => 'T',
+            Some(_) if worktree_mode != entry.mode => 'M',
+            Some(metadata) => {
+                if index.stat_matches(entry, metadata) {
+                    ' '
+                } else if hash_file(&full_path, metadata)? != entry.hash {
+                    'M'
+                } else {
+                    ' '
+                }
+            }
+        };
+        let staged = if entry.is_intent_to_add() {
+            ' '
+        } else {
+            diff_code(head, index_version)
+        };
+        if staged != ' ' || unstaged != ' ' {
+            changes.push(Change {
+                path: entry.path.clone(),
+                orig_path: None,
+                staged,
+                unstaged,
+                head,
+                index: (!entry.is_intent_to_add()).then_some((entry.mode, entry.hash)),
+                worktree_mode,
+                conflict: None,
+            });
+        }
+    }
+
+    for (path, stages) in &conflicts {
+        let metadata = worktree_metadata(&work_tree.join(path))?;
+        let (staged, unstaged) = match stages.each_ref().map(Option::is_some) {
+            [true, true, true] => ('U', 'U'),
+            [false, true, true] => ('A', 'A'),
+            [true, false, false] => ('D', 'D'),
+            [true, true, false] => ('U', 'D'),
+            [true, false, true] => ('D', 'U'),
+            [false, true, false] => ('A', 'U'),
+            _ => ('U', 'A'),
+        };
+        changes.push(Change {
+            path: path.to_string(),
+            orig_path: None,
+            staged,
+            unstaged,
+            head: head_tree.get(*path).copied(),
+            index: None,
+            worktree_mode: match metadata {
+                Some(_) => stages[1].or(stages[2]).map_or(0o100644, |(mode, _)| mode),
+                None => 0,
+            },
+            conflict: Some(*stages),
+        });
+    }
+
+    // Paths deleted from the index but still in HEAD
+    let in_index: HashSet<&str> = index.entries().iter().map(|e| e.path.as_str()).collect();
+    for (path, version) in head_tree {
+        if !in_index.contains(path.as_str()) {
+            changes.push(Change {
+                path: path.clone(),
+                orig_path: None,
+                staged: 'D',
+                unstaged: ' ',
+                head: Some(*version),
+                index: None,
+                worktree_mode: 0,
+                conflict: None,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// The status letter for a path going from `old` to `new`.
+fn diff_code(old: Version, new: Version) -> char {
+    match (old, new) {
+        (None, None) => ' ',
+        (None, Some(_)) => 'A',
+        (Some(_), None) => 'D',
+        (Some((old_mode, old_hash)), Some((new_mode, new_hash))) => {
+            // The top bits of the mode give the type: file, link or gitlink
+            if old_mode >> 12 != new_mode >> 12 {
+                'T'
+            } else if old_mode != new_mode || old_hash != new_hash {
+                'M'
+            } else {
+                ' '
+            }
+        }
+    }
+}
+
+/// Pair each staged deletion with a staged addition of the same contents.
+fn detect_renames(changes: &mut Vec<Change>) {
+    let mut renamed = HashSet::new();
+    for i in 0..changes.len() {
+        let Some((_, hash)) = changes[i].index else {
+            continue;
+        };
+        if changes[i].staged != 'A' || hash.to_hex() == EMPTY_BLOB {
+            continue;
+        }
+        let source = changes.iter().position(|change| {
+            change.staged == 'D'
+                && change.conflict.is_none()
+                && change.head.is_some_and(|(_, head_hash)| head_hash == hash)
+                && !renamed.contains(&change.path)
+        });
+        if let Some(source) = source {
+            let orig_path = changes[source].path.clone();
+            renamed.insert(orig_path.clone());
+            changes[i].staged = 'R';
+            changes[i].head = changes[source].head;
+            changes[i].orig_path = Some(orig_path);
+        }
+    }
+    changes.retain(|change| {
+        !(change.staged == 'D' && change.unstaged == ' ' && renamed.contains(&change.path))
+    });
+}
+
+/// Stat data of a path in the work tree, `None` if there is no file there.
+pub fn worktree_metadata(path: &Path) -> io::Result<Option<Metadata>> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Ok(None),
+        Ok(metadata) => Ok(Some(metadata)),
+        // A file where a leading directory should be gives ENOTDIR
+        Err(e) if e.kind() == io::ErrorKind::NotFound || is_not_a_directory(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The mode a file on disk would be staged with. Without `filemode` the
+/// executable bit is not trusted and is taken from `index_mode` instead.
+pub fn file_mode(metadata: &Metadata, index_mode: u32, filemode: bool) -> u32 {
+    match work_tree_mode(metadata) {
+        0o120000 => 0o120000,
+        _ if !filemode && index_mode >> 12 == 0o100000 >> 12 => index_mode,
+        mode => mode,
+    }
+}
+
+/// Hash a work tree file as a blob, without storing it.
+pub fn hash_file(path: &Path, metadata: &Metadata) -> io::Result<Hash> {
+    let contents = read_work_file(path, metadata)?;
+    Ok(compute_sha1(&Object::Blob(contents).serialize()))
+}
+
+/// Files in the work tree that are not in the index, sorted by path.
+/// Directories end in `/`.
+#[derive(Debug, Default)]
+struct Others {
+    untracked: Vec<String>,
+    ignored: Vec<String>,
+}
+
+impl Others {
+    /// Show a directory without tracked files as `dir/` rather than listing
+    /// what is in it. Ignored files are only folded into a directory that
+    /// holds nothing untracked.
+    fn collapse(&mut self, tracked_dirs: &HashSet<&str>) {
+        // The outermost directory of `path` without tracked files, if any
+        let untracked_dir = |path: &str| {
+            path.match_indices('/')
+                .map(|(i, _)| &path[..=i])
+                .find(|dir| !tracked_dirs.contains(&dir[..dir.len() - 1]))
+                .map(str::to_string)
+        };
+
+        let untracked: BTreeSet<String> = self
+            .untracked
+            .iter()
+            .map(|path| untracked_dir(path).unwrap_or_else(|| path.clone()))
+            .collect();
+        let ignored: BTreeSet<String> = self
+            .ignored
+            .iter()
+            .map(|path| match untracked_dir(path) {
+                Some(dir) if !untracked.contains(&dir) => dir,
+                _ => path.clone(),
+            })
+            .collect();
+        self.untracked = untracked.into_iter().collect();
+        self.ignored = ignored.into_iter().collect();
+    }
+}
+
+/// A walk of the work tree sorting what is not tracked into untracked and
+/// ignored files.
+struct OthersWalk<'a> {
+    work_tree: &'a Path,
+    tracked: &'a HashSet<&'a str>,
+    ignore: Ignore,
+    /// List every file rather than stopping at ignored directories.
+    all: bool,
+    others: Others,
+}
+
+impl OthersWalk<'_> {
+    /// Visit the directory `dir` (empty or ending in `/`); `ignored` says
+    /// it lies in an ignored directory.
+    fn visit(&mut self, dir: &str, ignored: bool) -> io::Result<()> {
+        let mut entries =
+            fs::read_dir(self.work_tree.join(dir))?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if name == ".git" {
+                continue;
+            }
+            let path = format!("{}{}", dir, name);
+            if self.tracked.contains(path.as_str()) {
+                continue;
+            }
+            let is_dir = entry.file_type()?.is_dir();
+            let path_ignored = ignored || self.ignore.is_ignored(&path, is_dir)?;
+            // Nested repositories are shown whole, never looked into
+            if is_dir && !path_ignored && Repository::at_work_tree(&entry.path()).is_some() {
+                self.others.untracked.push(format!("{}/", path));
+                continue;
+            }
+            match (is_dir, path_ignored) {
+                (true, true) if !self.all => self.others.ignored.push(format!("{}/", path)),
+                (true, _) => self.visit(&format!("{}/", path), path_ignored)?,
+                (false, true) => self.others.ignored.push(path),
+                (false, false) => self.others.untracked.push(path),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `path` (relative to the work tree) as seen from the directory `prefix`.
+fn relative_path(prefix: &str, path: &str) -> String {
+    let mut prefix: Vec<&str> = prefix.split('/').filter(|s| !s.is_empty()).collect();
+    let mut rest = path;
+    let mut common = 0;
+    for component in &prefix {
+        match rest
+            .strip_prefix(component)
+            .and_then(|r| r.strip_prefix('/'))
+        {
+            Some(remainder) => {
+                rest = remainder;
+                common += 1;
+            }
+            None => break,
+        }
+    }
+    prefix.drain(..common);
+    let mut relative = "../".repeat(prefix.len());
+    relative.push_str(rest);
+    if relative.is_empty() {
+        relative.push_str("./");
+    }
+    relative
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        "commit"
+    } else {
+        "commits"
+    }
+}
+
+fn print_long(
+    branch: &BranchInfo,
+    changes: &[Change],
+    others: &Others,
+    options: &StatusOptions,
+    prefix: &str,
+) {
+    match (&branch.branch, branch.head) {
+        (Some(name), _) => println!("On branch {}", name),
+        (None, Some(head)) => println!("HEAD detached at {}", short_hash(&head)),
+        (None, None) => println!("Not currently on any branch."),
+    }
+    if let Some(upstream) = &branch.upstream {
+        let upstream = shorten_ref(upstream);
+        match branch.ahead_behind {
+            None => {
+                println!(
+                    "Your branch is based on '{}', but the upstream is gone.",
+                    upstream
+                );
+                println!("  (use \"git branch --unset-upstream\" to fixup)");
+            }
+            Some((0, 0)) => println!("Your branch is up to date with '{}'.", upstream),
+            Some((ahead, 0)) => {
+                println!(
+                    "Your branch is ahead of '{}' by {} {}.",
+                    upstream,
+                    ahead,
+                    plural(ahead)
+                );
+                println!("  (use \"git push\" to publish your local commits)");
+            }
+            Some((0, behind)) => {
+                println!(
+                    "Your branch is behind '{}' by {} {}, and can be fast-forwarded.",
+                    upstream,
+                    behind,
+                    plural(behind)
+                );
+                println!("  (use \"git pull\" to update your local branch)");
+            }
+            Some((ahead, behind)) => {
+                println!("Your branch and '{}' have diverged,", upstream);
+                println!(
+                    "and have {} and {} different commits each, respectively.",
+                    ahead, behind
+                );
+                println!("  (use \"git pull\" to merge the remote branch into yours)");
+            }
+        }
+        println!();
+    }
+    if branch.head.is_none() {
+        if branch.upstream.is_none() {
+            println!();
+        }
+        println!("No commits yet");
+        println!();
+    }
+
+    let show = |path: &str| relative_path(prefix, path);
+    let has_unmerged = changes.iter().any(|c| c.conflict.is_some());
+    if branch.merging {
+        if has_unmerged {
+            println!("You have unmerged paths.");
+            println!("  (fix conflicts and run \"git commit\")");
+            println!("  (use \"git merge --abort\" to abort the merge)");
+        } else {
+            println!("All conflicts fixed but you are still merging.");
+            println!("  (use \"git commit\" to conclude merge)");
+        }
+        println!();
+    }
+
+    let staged: Vec<&Change> = changes
+        .iter()
+        .filter(|c| c.conflict.is_none() && c.staged != ' ')
+        .collect();
+    let unmerged: Vec<&Change> = changes.iter().filter(|c| c.conflict.is_some()).collect();
+    let unstaged: Vec<&Change> = changes
+        .iter()
+        .filter(|c| c.conflict.is_none() && c.unstaged != ' ')
+        .collect();
+
+    if !staged.is_empty() {
+        println!("Changes to be committed:");
+        if branch.merging {
+            // Unstaging would lose part of the merge result
+        } else if branch.head.is_some() {
+            println!("  (use \"git restore --staged <file>...\" to unstage)");
+        } else {
+            println!("  (use \"git rm --cached <file>...\" to unstage)");
+        }
+        for change in &staged {
+            let label = match change.staged {
+                'A' => "new file:",
+                'D' => "deleted:",
+                'R' => "renamed:",
+                'T' => "typechange:",
+                _ => "modified:",
+            };
+            match &change.orig_path {
+                Some(orig_path) => println!(
+                    "\t{:<12}{} -> {}",
+                    label,
+                    show(orig_path),
+                    show(&change.path)
+                ),
+                None => println!("\t{:<12}{}", label, show(&change.path)),
+            }
+        }
+        println!();
+    }
+
+    if !unmerged.is_empty() {
+        println!("Unmerged paths:");
+        if unmerged
+            .iter()
+            .any(|c| c.staged == 'D' || c.unstaged == 'D')
+        {
+            println!("  (use \"git add/rm <file>...\" as appropriate to mark resolution)");
+        } else {
+            println!("  (use \"git add <file>...\" to mark resolution)");
+        }
+        for change in &unmerged {
+            let label = match (change.staged, change.unstaged) {
+                ('D', 'D') => "both deleted:",
+                ('A', 'U') => "added by us:",
+                ('U', 'D') => "deleted by them:",
+                ('U', 'A') => "added by them:",
+                ('D', 'U') => "deleted by us:",
+                ('A', 'A') => "both added:",
+                _ => "both modified:",
+            };
+            println!("\t{:<17}{}", label, show(&change.path));
+        }
+        println!();
+    }
+
+    if !unstaged.is_empty() {
+        println!("Changes not staged for commit:");
+        if unstaged.iter().any(|c| c.unstaged == 'D') {
+            println!("  (use \"git add/rm <file>...\" to update what will be committed)");
+        } else {
+            println!("  (use \"git add <file>...\" to update what will be committed)");
+        }
+        println!("  (use \"git restore <file>...\" to discard changes in working directory)");
+        for change in &unstaged {
+            let label = match change.unstaged {
+                'A' => "new file:",
+                'D' => "deleted:",
+                'T' => "typechange:",
+                _ => "modified:",
+            };
+            let detail = if change.index.is_some_and(|(mode, _)| mode == 0o160000) {
+                " (new commits)"
+            } else {
+                ""
+            };
+            println!("\t{:<12}{}{}", label, show(&change.path), detail);
+        }
+        println!();
+    }
+
+    if !others.untracked.is_empty() {
+        println!("Untracked files:");
+        println!("  (use \"git add <file>...\" to include in what will be committed)");
+        for path in &others.untracked {
+            println!("\t{}", show(path));
+        }
+        println!();
+    }
+
+    if !others.ignored.is_empty() {
+        println!("Ignored files:");
+        println!("  (use \"git add -f <file>...\" to include in what will be committed)");
+        for path in &others.ignored {
+            println!("\t{}", show(path));
+        }
+        println!();
+    }
+
+    if !staged.is_empty() {
+        if options.untracked == Untracked::No {
+            println!("Untracked files not listed (use -u option to show untracked files)");
+        }
+        return;
+    }
+    if !unstaged.is_empty() || !unmerged.is_empty() {
+        println!("no changes added to commit (use \"git add\" and/or \"git commit -a\")");
+    } else if !others.untracked.is_empty() {
+        println!("nothing added to commit but untracked files present (use \"git add\" to track)");
+    } else if branch.head.is_none() {
+        println!("nothing to commit (create/copy files and use \"git add\" to track)");
+    } else if options.untracked == Untracked::No {
+        println!("nothing to commit (use -u to show untracked files)");
+    } else {
+        println!("nothing to commit, working tree clean");
+    }
+}
+
+/// The short format, which is also porcelain v1 when `prefix` is empty.
+fn print_short(
+    branch: &BranchInfo,
+    changes: &[Change],
+    others: &Others,
+    options: &StatusOptions,
+    prefix: &str,
+) {
+    if options.branch {
+        let mut header = match (&branch.branch, branch.head) {
+            (Some(name), Some(_)) => format!("## {}", name),
+            (Some(name), None) => format!("## No commits yet on {}", name),
+            (None, _) => "## HEAD (no branch)".to_string(),
+        };
+        if let Some(upstream) = &branch.upstream {
+            header.push_str(&format!("...{}", shorten_ref(upstream)));
+            match branch.ahead_behind {
+                None => header.push_str(" [gone]"),
+                Some((0, 0)) => {}
+                Some((ahead, 0)) => header.push_str(&format!(" [ahead {}]", ahead)),
+                Some((0, behind)) => header.push_str(&format!(" [behind {}]", behind)),
+                Some((ahead, behind)) => {
+                    header.push_str(&format!(" [ahead {}, behind {}]", ahead, behind))
+                }
+            }
+        }
+        println!("{}", header);
+    }
+
+    for change in changes {
+        let path = relative_path(prefix, &change.path);
+        match &change.orig_path {
+            Some(orig_path) => println!(
+                "{}{} {} -> {}",
+                change.staged,
+                change.unstaged,
+                relative_path(prefix, orig_path),
+                path
+            ),
+            None => println!("{}{} {}", change.staged, change.unstaged, path),
+        }
+    }
+    for path in &others.untracked {
+        println!("?? {}", relative_path(prefix, path));
+    }
+    for path in &others.ignored {
+        println!("!! {}", relative_path(prefix, path));
+    }
+}
+
+fn print_porcelain_v2(
+    branch: &BranchInfo,
+    changes: &[Change],
+    others: &Others,
+    options: &StatusOptions,
+) {
+    if options.branch {
+        match branch.head {
+            Some(head) => println!("# branch.oid {}", head.to_hex()),
+            None => println!("# branch.oid (initial)"),
+        }
+        println!(
+            "# branch.head {}",
+            branch.branch.as_deref().unwrap_or("(detached)")
+        );
+        if let Some(upstream) = &branch.upstream {
+            println!("# branch.upstream {}", shorten_ref(upstream));
+            if let Some((ahead, behind)) = branch.ahead_behind {
+                println!("# branch.ab +{} -{}", ahead, behind);
+            }
+        }
+    }
+
+    let mode = |version: &Version| format!("{:06o}", version.map_or(0, |(mode, _)| mode));
+    let hash = |version: &Version| version.map_or(Hash::zero(), |(_, hash)| hash).to_hex();
+    let code = |c: char| if c == ' ' { '.' } else { c };
+    // Conflicted paths come after all other changes
+    let (unmerged, changed): (Vec<&Change>, Vec<&Change>) =
+        changes.iter().partition(|change| change.conflict.is_some());
+    for change in changed.into_iter().chain(unmerged) {
+        let xy = format!("{}{}", code(change.staged), code(change.unstaged));
+        if let Some(stages) = &change.conflict {
+            println!(
+                "u {} N... {} {} {} {:06o} {} {} {} {}",
+                xy,
+                mode(&stages[0]),
+                mode(&stages[1]),
+                mode(&stages[2]),
+                change.worktree_mode,
+                hash(&stages[0]),
+                hash(&stages[1]),
+                hash(&stages[2]),
+                change.path
+            );
+            continue;
+        }
+        // Submodules say whether their checked out commit changed
+        let submodule = match (change.head, change.index) {
+            (Some((0o160000, _)), _) | (_, Some((0o160000, _))) if change.unstaged == 'M' => "SC..",
+            (Some((0o160000, _)), _) | (_, Some((0o160000, _))) => "S...",
+            _ => "N...",
+        };
+        let fields = format!(
+            "{} {} {} {} {:06o} {} {}",
+            xy,
+            submodule,
+            mode(&change.head),
+            mode(&change.index),
+            change.worktree_mode,
+            hash(&change.head),
+            hash(&change.index)
+        );
+        match &change.orig_path {
+            Some(orig_path) => println!("2 {} R100 {}\t{}", fields, change.path, orig_path),
+            None => println!("1 {} {}", fields, change.path),
+        }
+    }
+    for path in &others.untracked {
+        println!("? {}", path);
+    }
+    for path in &others.ignored {
+        println!("! {}", path);
+    }
+}
@@ -0,0 +1,560 @@
+use std::io::{self, Read};
+
+use crate::pkt_line::{trim_newline, Packet, PktLineReader, PktLineWriter, SidebandReader};
+use crate::Hash;
+
+/*
+Smart HTTP, protocol v0:
+
+GET  <url>/info/refs?service=git-upload-pack
+     -> "# service=git-upload-pack" flush
+        <hash> <ref>\0<capabilities> ... flush
+POST <url>/git-upload-pack
+     <- want <hash> <capabilities> ... flush have <hash> ... done
+     -> NAK (or ACK <hash>), then the pack (multiplexed when side-band-64k is on)
+
+Protocol v2 (requested with the `Git-Protocol: version=2` header):
+
+GET  <url>/info/refs?service=git-upload-pack
+     -> version 2, capabilities ... flush
+POST <url>/git-upload-pack
+     <- command=ls-refs ... delim peel symrefs ref-prefix <prefix> ... flush
+     -> <hash> <ref> [symref-target:<ref>] [peeled:<hash>] ... flush
+POST <url>/git-upload-pack
+     <- command=fetch ... delim want <hash> ... have <hash> ... done flush
+     -> packfile, then the side-band multiplexed pack
+
+Pushing always uses v0:
+
+GET  <url>/info/refs?service=git-receive-pack
+     -> "# service=git-receive-pack" flush
+        <hash> <ref>\0<capabilities> ... flush
+POST <url>/git-receive-pack
+     <- <old> <new> <ref>\0<capabilities> ... flush, then the pack
+     -> unpack ok, ok <ref> / ng <ref> <reason> ... flush
+        (multiplexed when side-band-64k is on)
+*/
+
+const AGENT: &str = "agent=codecrafters-git/0.1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    V0,
+    V2,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRef {
+    pub name: String,
+    pub hash: Hash,
+    /// Target of a symbolic ref such as `HEAD`, when the server reports it.
+    pub symref_target: Option<String>,
+    /// What an annotated tag points to.
+    pub peeled: Option<Hash>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RefAdvertisement {
+    pub version: ProtocolVersion,
+    /// Advertised refs in server order, `HEAD` included when present.
+    pub refs: Vec<RemoteRef>,
+    pub capabilities: Vec<String>,
+}
+
+impl RefAdvertisement {
+    /// Whether the server advertised `name`, either bare or as `name=<value>`.
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|cap| cap == name || cap.split_once('=').is_some_and(|(key, _)| key == name))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn http_error(e: reqwest::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn service_url(url: &str, path: &str) -> String {
+    format!("{}/{}", url.trim_end_matches('/'), path)
+}
+
+fn remote_error(message: &[u8]) -> io::Error {
+    io::Error::other(String::from_utf8_lossy(trim_newline(message)).to_string())
+}
+
+/// POST a request to `service`, returning the response stream.
+fn service_request(
+    url: &str,
+    service: &str,
+    version: ProtocolVersion,
+    body: Vec<u8>,
+) -> io::Result<reqwest::blocking::Response> {
+    let client = reqwest::blocking::Client::new();
+    let mut request = client
+        .post(service_url(url, service))
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            format!("application/x-{}-request", service),
+        )
+        .header(
+            reqwest::header::ACCEPT,
+            format!("application/x-{}-result", service),
+        );
+    if version == ProtocolVersion::V2 {
+        request = request.header("Git-Protocol", "version=2");
+    }
+    let response = request.body(body).send().map_err(http_error)?;
+    if !response.status().is_success() {
+        return Err(io::Error::other(format!(
+            "Failed to talk to {}: HTTP {}",
+            url,
+            response.status()
+        )));
+    }
+    Ok(response)
+}
+
+/// GET `info/refs` for `service`, returning the advertisement lines that
+/// follow the service announcement.
+fn advertisement_lines(url: &str, service: &str, v2: bool) -> io::Result<Vec<Vec<u8>>> {
+    let client = reqwest::blocking::Client::new();
+    let mut request = client.get(service_url(url, &format!("info/refs?service={}", service)));
+    if v2 {
+        request = request.header("Git-Protocol", "version=2");
+    }
+    let response = request.send().map_err(http_error)?;
+    if !response.status().is_success() {
+        return Err(io::Error::other(format!(
+            "Failed to fetch refs from {}: HTTP {}",
+            url,
+            response.status()
+        )));
+    }
+    let content_type = format!("application/x-{}-advertisement", service);
+    let smart = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .is_some_and(|value| value == content_type.as_str());
+    if !smart {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Remote does not speak the smart HTTP protocol",
+        ));
+    }
+    let mut reader = PktLineReader::new(response);
+
+    let announcement = format!("# service={}", service);
+    let mut lines = Vec::new();
+    match reader.expect_packet()? {
+        // Skip the service announcement and the flush that ends it
+        Packet::Data(line) if trim_newline(&line) == announcement.as_bytes() => {
+            reader.expect_packet()?;
+        }
+        Packet::Data(line) => lines.push(line),
+        Packet::Flush => {}
+        _ => return Err(invalid("Unexpected packet in ref advertisement")),
+    }
+    let (rest, _) = reader.read_section()?;
+    lines.extend(rest);
+    Ok(lines)
+}
+
+/// Discover the refs of a smart HTTP remote.
+///
+/// Protocol v2 is requested first; only refs matching one of
+/// `ref_prefixes` are then listed. Servers that do not speak v2 answer with
+/// a full v0 advertisement instead.
+pub fn discover_refs(url: &str, ref_prefixes: &[&str]) -> io::Result<RefAdvertisement> {
+    let lines = advertisement_lines(url, "git-upload-pack", true)?;
+
+    if lines.first().map(|line| trim_newline(line)) == Some(b"version 2".as_slice()) {
+        let capabilities = lines[1..]
+            .iter()
+            .map(|line| String::from_utf8_lossy(trim_newline(line)).to_string())
+            .collect();
+        let mut advertisement = RefAdvertisement {
+            version: ProtocolVersion::V2,
+            refs: Vec::new(),
+            capabilities,
+        };
+        advertisement.refs = ls_refs(url, &advertisement, ref_prefixes)?;
+        return Ok(advertisement);
+    }
+
+    parse_v0_advertisement(&lines)
+}
+
+/// Discover the refs of a remote we are about to push to.
+///
+/// `git-receive-pack` only speaks v0, so every ref is advertised.
+pub fn discover_push_refs(url: &str) -> io::Result<RefAdvertisement> {
+    let lines = advertisement_lines(url, "git-receive-pack", false)?;
+    parse_v0_advertisement(&lines)
+}
+
+fn parse_v0_advertisement(lines: &[Vec<u8>]) -> io::Result<RefAdvertisement> {
+    let mut refs: Vec<RemoteRef> = Vec::new();
+    let mut capabilities = Vec::new();
+    for line in lines {
+        let line = trim_newline(line);
+        if let Some(message) = line.strip_prefix(b"ERR ") {
+            return Err(remote_error(message));
+        }
+        let (line, caps) = match line.iter().position(|&b| b == 0) {
+            Some(null) => (&line[..null], Some(&line[null + 1..])),
+            None => (line, None),
+        };
+        if let Some(caps) = caps {
+            capabilities = String::from_utf8_lossy(caps)
+                .split(' ')
+                .filter(|cap| !cap.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        let line = std::str::from_utf8(line).map_err(|_| invalid("Invalid ref line"))?;
+        let (hash, name) = line
+            .split_once(' ')
+            .ok_or_else(|| invalid("Invalid ref line"))?;
+        let hash = Hash::from_hex(hash)?;
+
+        // Peeled tags follow the tag they belong to; empty repositories
+        // advertise a lone capabilities^{} placeholder
+        if let Some(tag) = name.strip_suffix("^{}") {
+            if let Some(last) = refs.last_mut().filter(|last| last.name == tag) {
+                last.peeled = Some(hash);
+            }
+            continue;
+        }
+        refs.push(RemoteRef {
+            name: name.to_string(),
+            hash,
+            symref_target: None,
+            peeled: None,
+        });
+    }
+
+    // v0 reports symbolic refs as `symref=HEAD:refs/heads/main` capabilities
+    for cap in &capabilities {
+        if let Some((name, target)) = cap
+            .strip_prefix("symref=")
+            .and_then(|symref| symref.split_once(':'))
+        {
+            if let Some(remote_ref) = refs.iter_mut().find(|remote_ref| remote_ref.name == name) {
+                remote_ref.symref_target = Some(target.to_string());
+            }
+        }
+    }
+
+    Ok(RefAdvertisement {
+        version: ProtocolVersion::V0,
+        refs,
+        capabilities,
+    })
+}
+
+/// Run the v2 `ls-refs` command.
+fn ls_refs(
+    url: &str,
+    advertisement: &RefAdvertisement,
+    ref_prefixes: &[&str],
+) -> io::Result<Vec<RemoteRef>> {
+    if !advertisement.has_capability("ls-refs") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Remote does not support ls-refs",
+        ));
+    }
+
+    let mut request = PktLineWriter::new(Vec::new());
+    request.write_line("command=ls-refs")?;
+    request.write_line(AGENT)?;
+    request.write_delim()?;
+    request.write_line("peel")?;
+    request.write_line("symrefs")?;
+    for prefix in ref_prefixes {
+        request.write_line(&format!("ref-prefix {}", prefix))?;
+    }
+    request.write_flush()?;
+
+    let response = service_request(
+        url,
+        "git-upload-pack",
+        ProtocolVersion::V2,
+        request.into_inner(),
+    )?;
+    let mut reader = PktLineReader::new(response);
+    let (lines, _) = reader.read_section()?;
+
+    let mut refs = Vec::new();
+    for line in &lines {
+        let line = trim_newline(line);
+        if let Some(message) = line.strip_prefix(b"ERR ") {
+            return Err(remote_error(message));
+        }
+        let line = std::str::from_utf8(line).map_err(|_| invalid("Invalid ref line"))?;
+        let mut parts = line.split(' ');
+        let hash = parts.next().ok_or_else(|| invalid("Invalid ref line"))?;
+        let name = parts.next().ok_or_else(|| invalid("Invalid ref line"))?;
+        // An unborn HEAD is reported as "unborn HEAD symref-target:..."
+        if hash == "unborn" {
+            continue;
+        }
+
+        let mut remote_ref = RemoteRef {
+            name: name.to_string(),
+            hash: Hash::from_hex(hash)?,
+            symref_target: None,
+            peeled: None,
+        };
+        for attribute in parts {
+            if let Some(target) = attribute.strip_prefix("symref-target:") {
+                remote_ref.symref_target = Some(target.to_string());
+            } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                remote_ref.peeled = Some(Hash::from_hex(peeled)?);
+            }
+        }
+        refs.push(remote_ref);
+    }
+    Ok(refs)
+}
+
+/// Ask the remote for `wants`, telling it which objects we already have,
+/// and return the raw pack it sends back.
+pub fn fetch_pack(
+    url: &str,
+    advertisement: &RefAdvertisement,
+    wants: &[Hash],
+    haves: &[Hash],
+) -> io::Result<Vec<u8>> {
+    match advertisement.version {
+        ProtocolVersion::V0 => fetch_pack_v0(url, advertisement, wants, haves),
+        ProtocolVersion::V2 => fetch_pack_v2(url, advertisement, wants, haves),
+    }
+}
+
+fn fetch_pack_v0(
+    url: &str,
+    advertisement: &RefAdvertisement,
+    wants: &[Hash],
+    haves: &[Hash],
+) -> io::Result<Vec<u8>> {
+    let mut capabilities = Vec::new();
+    for cap in ["side-band-64k", "ofs-delta"] {
+        if advertisement.has_capability(cap) {
+            capabilities.push(cap);
+        }
+    }
+    capabilities.push(AGENT);
+
+    let mut request = PktLineWriter::new(Vec::new());
+    for (i, want) in wants.iter().enumerate() {
+        if i == 0 {
+            request.write_line(&format!(
+                "want {} {}",
+                want.to_hex(),
+                capabilities.join(" ")
+            ))?;
+        } else {
+            request.write_line(&format!("want {}", want.to_hex()))?;
+        }
+    }
+    request.write_flush()?;
+    for have in haves {
+        request.write_line(&format!("have {}", have.to_hex()))?;
+    }
+    request.write_line("done")?;
+
+    let response = service_request(
+        url,
+        "git-upload-pack",
+        ProtocolVersion::V0,
+        request.into_inner(),
+    )?;
+    let mut reader = PktLineReader::new(response);
+
+    // Without multi_ack the server answers `done` with a single ACK or NAK
+    match reader.expect_packet()? {
+        Packet::Data(line) if line.starts_with(b"ERR ") => return Err(remote_error(&line[4..])),
+        Packet::Data(line) if trim_newline(&line) == b"NAK" || line.starts_with(b"ACK ") => {}
+        _ => return Err(invalid("Missing ACK/NAK from remote")),
+    }
+
+    let mut pack = Vec::new();
+    if advertisement.has_capability("side-band-64k") {
+        SidebandReader::new(&mut reader, io::stderr()).read_to_end(&mut pack)?;
+    } else {
+        reader.get_mut().read_to_end(&mut pack)?;
+    }
+    Ok(pack)
+}
+
+fn fetch_pack_v2(
+    url: &str,
+    advertisement: &RefAdvertisement,
+    wants: &[Hash],
+    haves: &[Hash],
+) -> io::Result<Vec<u8>> {
+    if !advertisement.has_capability("fetch") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Remote does not support fetch",
+        ));
+    }
+
+    let mut request = PktLineWriter::new(Vec::new());
+    request.write_line("command=fetch")?;
+    request.write_line(AGENT)?;
+    request.write_delim()?;
+    request.write_line("ofs-delta")?;
+    for want in wants {
+        request.write_line(&format!("want {}", want.to_hex()))?;
+    }
+    for have in haves {
+        request.write_line(&format!("have {}", have.to_hex()))?;
+    }
+    request.write_line("done")?;
+    request.write_flush()?;
+
+    let response = service_request(
+        url,
+        "git-upload-pack",
+        ProtocolVersion::V2,
+        request.into_inner(),
+    )?;
+    let mut reader = PktLineReader::new(response);
+
+    // The response is a series of sections, the packfile always coming last
+    loop {
+        let header = match reader.expect_packet()? {
+            Packet::Data(line) => line,
+            Packet::Flush | Packet::ResponseEnd => return Ok(Vec::new()),
+            Packet::Delim => continue,
+        };
+        match trim_newline(&header) {
+            b"packfile" => {
+                let mut pack = Vec::new();
+                SidebandReader::new(&mut reader, io::stderr()).read_to_end(&mut pack)?;
+                return Ok(pack);
+            }
+            line if line.starts_with(b"ERR ") => return Err(remote_error(&line[4..])),
+            // acknowledgments, shallow-info and wanted-refs carry nothing we need
+            _ => {
+                let (_, terminator) = reader.read_section()?;
+                if terminator != Packet::Delim {
+                    return Ok(Vec::new());
+                }
+            }
+        }
+    }
+}
+
+/// One ref to update on the remote; a zero `new` hash deletes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushCommand {
+    pub old: Hash,
+    pub new: Hash,
+    pub name: String,
+}
+
+/// What the remote made of a push, from `report-status`.
+#[derive(Debug, Clone, Default)]
+pub struct PushReport {
+    /// Why the pack could not be unpacked, if it failed.
+    pub unpack_error: Option<String>,
+    /// Refs the remote refused to update, with its reason.
+    pub rejected: Vec<(String, String)>,
+}
+
+/// Send ref update `commands` and the `pack` holding the objects they
+/// need to `git-receive-pack`.
+pub fn send_pack(
+    url: &str,
+    advertisement: &RefAdvertisement,
+    commands: &[PushCommand],
+    pack: &[u8],
+) -> io::Result<PushReport> {
+    let mut capabilities = Vec::new();
+    for cap in ["report-status", "side-band-64k"] {
+        if advertisement.has_capability(cap) {
+            capabilities.push(cap);
+        }
+    }
+    capabilities.push(AGENT);
+
+    let deleting = commands.iter().any(|command| command.new == Hash::zero());
+    if deleting && !advertisement.has_capability("delete-refs") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Remote does not support deleting refs",
+        ));
+    }
+
+    let mut request = PktLineWriter::new(Vec::new());
+    for (i, command) in commands.iter().enumerate() {
+        let line = format!(
+            "{} {} {}",
+            command.old.to_hex(),
+            command.new.to_hex(),
+            command.name
+        );
+        if i == 0 {
+            request.write_line(&format!("{}\0{}", line, capabilities.join(" ")))?;
+        } else {
+            request.write_line(&line)?;
+        }
+    }
+    request.write_flush()?;
+    let mut body = request.into_inner();
+    // A push that only deletes refs carries no pack
+    if commands.iter().any(|command| command.new != Hash::zero()) {
+        body.extend_from_slice(pack);
+    }
+
+    let response = service_request(url, "git-receive-pack", ProtocolVersion::V0, body)?;
+    let mut reader = PktLineReader::new(response);
+    if !advertisement.has_capability("report-status") {
+        return Ok(PushReport::default());
+    }
+
+    let lines = if advertisement.has_capability("side-band-64k") {
+        let mut status = Vec::new();
+        SidebandReader::new(&mut reader, io::stderr()).read_to_end(&mut status)?;
+        PktLineReader::new(status.as_slice()).read_section()?.0
+    } else {
+        reader.read_section()?.0
+    };
+    parse_report_status(&lines)
+}
+
+fn parse_report_status(lines: &[Vec<u8>]) -> io::Result<PushReport> {
+    let mut lines = lines
+        .iter()
+        .map(|line| String::from_utf8_lossy(trim_newline(line)).to_string());
+    let unpack = lines
+        .next()
+        .and_then(|line| line.strip_prefix("unpack ").map(str::to_string))
+        .ok_or_else(|| invalid("Missing unpack status from remote"))?;
+
+    let mut report = PushReport {
+        unpack_error: (unpack != "ok").then_some(unpack),
+        rejected: Vec::new(),
+    };
+    for line in lines {
+        if line.starts_with("ok ") {
+            continue;
+        }
+        let rejection = line
+            .strip_prefix("ng ")
+            .and_then(|rest| rest.split_once(' '))
+            .ok_or_else(|| invalid("Invalid ref status from remote"))?;
+        report
+            .rejected
+            .push((rejection.0.to_string(), rejection.1.to_string()));
+    }
+    Ok(report)
+}
@@ -0,0 +1,460 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::ignore::Ignore;
+use crate::index::{Index, IndexEntry, IndexLock};
+use crate::objects::Object;
+use crate::refs::read_ref;
+use crate::repository::Repository;
+use crate::status;
+use crate::{index_path, read_object, read_tree, store_work_file, work_tree_mode};
+
+/*
+add, rm and mv change the index, and rm and mv the work tree along with it.
+Each holds the index lock for the whole command and writes the new index
+once at the end. rm and mv check every path before touching the work tree,
+so a command they refuse changes nothing.
+*/
+
+/// Whether an index path is `pathspec` or lies below it; the empty
+/// pathspec is the whole work tree.
+fn in_pathspec(pathspec: &str, path: &str) -> bool {
+    pathspec.is_empty()
+        || path == pathspec
+        || path
+            .strip_prefix(pathspec)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Stage the files matching each pathspec, and the removal of tracked files
+/// that are gone from the work tree. Untracked ignored files are left out
+/// unless `force` is set; naming one explicitly is an error.
+pub fn add(repo: &Repository, pathspecs: &[&str], force: bool) -> io::Result<()> {
+    let work_tree = repo.work_tree()?;
+    let index_file = repo.path("index");
+    let lock = IndexLock::acquire(&index_file)?;
+    let mut index = Index::load(&index_file)?;
+    let mut ignore = if force {
+        None
+    } else {
+        Some(Ignore::new(repo)?)
+    };
+
+    let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+
+    let mut ignored = Vec::new();
+    for pathspec in pathspecs {
+        let path = index_path(repo, pathspec)?;
+        let mut matched = false;
+        if let Ok(metadata) = fs::symlink_metadata(work_tree.join(&path)) {
+            if is_untracked_ignored(&index, ignore.as_mut(), &path, metadata.is_dir())? {
+                ignored.push(*pathspec);
+                continue;
+            }
+            add_path(repo, &mut index, ignore.as_mut(), filemode, &path)?;
+            matched = true;
+        }
+
+        let deleted: Vec<String> = index
+            .entries()
+            .iter()
+            .filter(|entry| in_pathspec(&path, &entry.path))
+            .filter(|entry| fs::symlink_metadata(work_tree.join(&entry.path)).is_err())
+            .map(|entry| entry.path.clone())
+            .collect();
+        for path in &deleted {
+            index.remove(path);
+            matched = true;
+        }
+
+        if !matched {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("pathspec '{}' did not match any files", pathspec),
+            ));
+        }
+    }
+
+    lock.commit(&index)?;
+    if !ignored.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The following paths are ignored by one of your .gitignore files:\n{}\nhint: Use -f if you really want to add them.",
+                ignored.join("\n")
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `path` is ignored and nothing at or below it is tracked; tracked
+/// files stay tracked whatever the ignore rules say.
+fn is_untracked_ignored(
+    index: &Index,
+    ignore: Option<&mut Ignore>,
+    path: &str,
+    is_dir: bool,
+) -> io::Result<bool> {
+    let Some(ignore) = ignore else {
+        return Ok(false);
+    };
+    if path.is_empty()
+        || index
+            .entries()
+            .iter()
+            .any(|entry| in_pathspec(path, &entry.path))
+    {
+        return Ok(false);
+    }
+    ignore.is_ignored(path, is_dir)
+}
+
+/// Add the file at `path`, or every file below it if it is a directory.
+fn add_path(
+    repo: &Repository,
+    index: &mut Index,
+    mut ignore: Option<&mut Ignore>,
+    filemode: bool,
+    path: &str,
+) -> io::Result<()> {
+    let full_path = repo.work_tree()?.join(path);
+    let metadata = fs::symlink_metadata(&full_path)?;
+    if metadata.is_dir() {
+        // A nested repository is recorded as the commit it has checked out
+        if let Some(nested) = (!path.is_empty())
+            .then(|| Repository::at_work_tree(&full_path))
+            .flatten()
+        {
+            let commit = read_ref(&nested, "HEAD")?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}/' does not have a commit checked out", path),
+                )
+            })?;
+            index.add(IndexEntry::new(
+                path.to_string(),
+                0o160000,
+                commit,
+                &metadata,
+            ));
+            return Ok(());
+        }
+        for entry in fs::read_dir(&full_path)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|name| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("path is not valid UTF-8: {:?}", name),
+                )
+            })?;
+            if name == ".git" {
+                continue;
+            }
+            let child = match path {
+                "" => name,
+                _ => format!("{}/{}", path, name),
+            };
+            let is_dir = entry.file_type()?.is_dir();
+            if is_untracked_ignored(index, ignore.as_deref_mut(), &child, is_dir)? {
+                continue;
+            }
+            add_path(repo, index, ignore.as_deref_mut(), filemode, &child)?;
+        }
+        return Ok(());
+    }
+
+    // Unchanged files keep their entry without being hashed again
+    if index.get(path).is_some_and(|entry| {
+        index.stat_matches(entry, &metadata)
+            && (!filemode || entry.mode == work_tree_mode(&metadata))
+    }) {
+        return Ok(());
+    }
+    let mut mode = work_tree_mode(&metadata);
+    if !filemode && mode != 0o120000 {
+        // The executable bit is not trusted; keep what the index says
+        mode = index.get(path).map_or(0o100644, |entry| entry.mode);
+    }
+    let hash = store_work_file(repo, &full_path, &metadata)?;
+    index.add(IndexEntry::new(path.to_string(), mode, hash, &metadata));
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct RmOptions {
+    /// Only unstage; keep the files in the work tree.
+    pub cached: bool,
+    pub recursive: bool,
+    /// Remove files even if they differ from the index.
+    pub force: bool,
+    pub quiet: bool,
+}
+
+pub fn rm(repo: &Repository, pathspecs: &[&str], options: &RmOptions) -> io::Result<()> {
+    let work_tree = repo.work_tree()?;
+    let index_file = repo.path("index");
+    let lock = IndexLock::acquire(&index_file)?;
+    let mut index = Index::load(&index_file)?;
+
+    let mut removals = Vec::new();
+    for pathspec in pathspecs {
+        let path = index_path(repo, pathspec)?;
+        let matches: Vec<&IndexEntry> = index
+            .entries()
+            .iter()
+            .filter(|entry| in_pathspec(&path, &entry.path))
+            .collect();
+        if matches.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("pathspec '{}' did not match any files", pathspec),
+            ));
+        }
+        if !options.recursive && matches.iter().any(|entry| entry.path != path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not removing '{}' recursively without -r", pathspec),
+            ));
+        }
+        removals.extend(matches.into_iter().cloned());
+    }
+    removals.sort_by(|a, b| a.path.cmp(&b.path));
+    removals.dedup_by(|a, b| a.path == b.path);
+
+    // A submodule's history would go with its work tree
+    if !options.cached {
+        let embedded: Vec<String> = removals
+            .iter()
+            .filter(|entry| entry.mode == 0o160000)
+            .filter(|entry| work_tree.join(&entry.path).join(".git").is_dir())
+            .map(|entry| format!("    {}", entry.path))
+            .collect();
+        if !embedded.is_empty() {
+            return Err(io::Error::other(format!(
+                "the following submodule (or one of its nested submodules)\nuses a .git directory:\n{}\n(use 'rm -rf' if you really want to remove it including all of its history)",
+                embedded.join("\n")
+            )));
+        }
+    }
+
+    // Refuse to throw away work that is not in HEAD, as git does
+    if !options.force {
+        let mut head_tree = BTreeMap::new();
+        if let Some(head) = read_ref(repo, "HEAD")? {
+            if let Object::Commit(commit) = read_object(repo, &head)? {
+                read_tree(repo, &commit.tree, "", &mut head_tree)?;
+            }
+        }
+        let filemode = repo.config()?.get_bool("core.filemode")?.unwrap_or(true);
+
+        let (mut both, mut staged, mut local) = (Vec::new(), Vec::new(), Vec::new());
+        for entry in &removals {
+            let full_path = work_tree.join(&entry.path);
+            // A file already gone from the work tree has nothing to lose
+            let local_changes = if entry.mode == 0o160000 {
+                match Repository::at_work_tree(&full_path) {
+                    Some(nested) => read_ref(&nested, "HEAD")? != Some(entry.hash),
+                    None => continue,
+                }
+            } else {
+                match status::worktree_metadata(&full_path)? {
+                    Some(metadata) => {
+                        status::file_mode(&metadata, entry.mode, filemode) != entry.mode
+                            || (!index.stat_matches(entry, &metadata)
+                                && status::hash_file(&full_path, &metadata)? != entry.hash)
+                    }
+                    None => continue,
+                }
+            };
+            let staged_changes = head_tree.get(&entry.path) != Some(&(entry.mode, entry.hash));
+
+            if local_changes && staged_changes {
+                if !options.cached || !entry.is_intent_to_add() {
+                    both.push(entry.path.as_str());
+                }
+            } else if !options.cached {
+                if staged_changes {
+                    staged.push(entry.path.as_str());
+                }
+                if local_changes {
+                    local.push(entry.path.as_str());
+                }
+            }
+        }
+
+        let mut problems = Vec::new();
+        for (paths, singular, plural, hint) in [
+            (
+                &both,
+                "file has staged content different from both the\nfile and the HEAD",
+                "files have staged content different from both the\nfile and the HEAD",
+                "(use -f to force removal)",
+            ),
+            (
+                &staged,
+                "file has changes staged in the index",
+                "files have changes staged in the index",
+                "(use --cached to keep the file, or -f to force removal)",
+            ),
+            (
+                &local,
+                "file has local modifications",
+                "files have local modifications",
+                "(use --cached to keep the file, or -f to force removal)",
+            ),
+        ] {
+            if paths.is_empty() {
+                continue;
+            }
+            let what = if paths.len() == 1 { singular } else { plural };
+            let list: Vec<String> = paths.iter().map(|path| format!("    {}", path)).collect();
+            problems.push(format!(
+                "the following {}:\n{}\n{}",
+                what,
+                list.join("\n"),
+                hint
+            ));
+        }
+        if !problems.is_empty() {
+            return Err(io::Error::other(problems.join("\n")));
+        }
+    }
+
+    for entry in &removals {
+        index.remove(&entry.path);
+        if !options.quiet {
+            println!("rm '{}'", entry.path);
+        }
+        if options.cached {
+            continue;
+        }
+        let full_path = work_tree.join(&entry.path);
+        let removed = match fs::symlink_metadata(&full_path) {
+            // A submodule's work tree, whose git directory lives elsewhere
+            Ok(metadata) if metadata.is_dir() && entry.mode == 0o160000 => {
+                fs::remove_dir_all(&full_path)
+            }
+            _ => fs::remove_file(&full_path),
+        };
+        match removed {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        // Leave no empty directories behind
+        for dir in full_path.ancestors().skip(1) {
+            if dir == work_tree || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+
+    lock.commit(&index)
+}
+
+/// Move or rename tracked files and directories, carrying their index
+/// entries along. Several sources are moved into `destination`, which must
+/// then be a directory.
+pub fn mv(repo: &Repository, sources: &[&str], destination: &str, force: bool) -> io::Result<()> {
+    let work_tree = repo.work_tree()?;
+    let index_file = repo.path("index");
+    let lock = IndexLock::acquire(&index_file)?;
+    let mut index = Index::load(&index_file)?;
+
+    let destination_path = index_path(repo, destination)?;
+    let into_directory = work_tree.join(&destination_path).is_dir();
+    if sources.len() > 1 && !into_directory {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("destination '{}' is not a directory", destination),
+        ));
+    }
+
+    // Check every move before touching anything
+    let mut moves: Vec<(String, String, Vec<IndexEntry>)> = Vec::new();
+    for source in sources {
+        let source_path = index_path(repo, source)?;
+        let target = if into_directory {
+            let name = source_path.rsplit('/').next().unwrap_or_default();
+            match destination_path.as_str() {
+                "" => name.to_string(),
+                dir => format!("{}/{}", dir, name),
+            }
+        } else {
+            destination_path.clone()
+        };
+        let fail = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}, source={}, destination={}", reason, source_path, target),
+            )
+        };
+
+        let entries: Vec<IndexEntry> = index
+            .entries()
+            .iter()
+            .filter(|entry| !source_path.is_empty() && in_pathspec(&source_path, &entry.path))
+            .cloned()
+            .collect();
+        if entries.is_empty() {
+            return Err(fail("not under version control"));
+        }
+        if in_pathspec(&source_path, &target) {
+            return Err(fail("can not move directory into itself"));
+        }
+        let source_full = work_tree.join(&source_path);
+        let target_full = work_tree.join(&target);
+        if fs::symlink_metadata(&source_full).is_err() {
+            return Err(fail("bad source"));
+        }
+        if fs::symlink_metadata(&target_full).is_ok() && (!force || target_full.is_dir()) {
+            return Err(fail("destination exists"));
+        }
+        if !target_full.parent().is_some_and(Path::is_dir) {
+            return Err(fail("destination directory does not exist"));
+        }
+        if moves.iter().any(|(_, other, _)| *other == target) {
+            return Err(fail("multiple sources for the same target"));
+        }
+        moves.push((source_path, target, entries));
+    }
+
+    // Put back whatever was moved if a later step fails
+    let undo = |done: &[(String, String, Vec<IndexEntry>)]| {
+        for (source, target, _) in done.iter().rev() {
+            let _ = fs::rename(work_tree.join(target), work_tree.join(source));
+        }
+    };
+    for (done, (source, target, _)) in moves.iter().enumerate() {
+        if let Err(e) = fs::rename(work_tree.join(source), work_tree.join(target)) {
+            undo(&moves[..done]);
+            return Err(e);
+        }
+    }
+
+    let result = move_entries(work_tree, &mut index, &moves).and_then(|_| lock.commit(&index));
+    if result.is_err() {
+        undo(&moves);
+    }
+    result
+}
+
+/// Re-add the index entries of each `(source, target, entries)` move under
+/// the target, with the stat data of the moved files.
+fn move_entries(
+    work_tree: &Path,
+    index: &mut Index,
+    moves: &[(String, String, Vec<IndexEntry>)],
+) -> io::Result<()> {
+    for (source, target, entries) in moves {
+        for entry in entries {
+            let path = format!("{}{}", target, &entry.path[source.len()..]);
+            let metadata = fs::symlink_metadata(work_tree.join(&path))?;
+            index.remove(&entry.path);
+            index.add(IndexEntry::new(path, entry.mode, entry.hash, &metadata));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,206 @@
+/*
+Find the repository the way git does: walk up from the current directory,
+honour GIT_DIR, GIT_WORK_TREE and GIT_CEILING_DIRECTORIES, and follow
+`gitdir:` files. Each case runs a command from some directory and checks
+that it sees the same refs and work tree as git. Skipped when git is missing.
+*/
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const BINARY: &str = env!("CARGO_BIN_EXE_codecrafters-git");
+
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn new(name: &str) -> ScratchDir {
+        let path = std::env::temp_dir().join(format!(
+            "codecrafters-git-discovery-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        // Symlinked temp dirs would make paths differ from what git sees
+        ScratchDir(path.canonicalize().unwrap())
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn git_available() -> bool {
+    Command::new("git").arg("--version").output().is_ok()
+}
+
+fn run(program: &str, dir: &Path, env: &[(&str, &Path)], args: &[&str]) -> Output {
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "Test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "Test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_INDEX_FILE")
+        .env_remove("GIT_WORK_TREE")
+        .env_remove("GIT_CEILING_DIRECTORIES");
+    for (name, value) in env {
+        command.env(name, value);
+    }
+    command.output().unwrap()
+}
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = run("git", dir, &[], args);
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Run `args` with both git and us from `dir`, checking that both succeed
+/// with the same output, or both fail.
+fn same_as_git(dir: &Path, env: &[(&str, &Path)], args: &[&str]) -> Option<String> {
+    let theirs = run("git", dir, env, args);
+    let ours = run(BINARY, dir, env, args);
+    assert_eq!(
+        ours.status.success(),
+        theirs.status.success(),
+        "{:?} in {} with {:?}: ours said {}, git said {}",
+        args,
+        dir.display(),
+        env,
+        String::from_utf8_lossy(&ours.stderr),
+        String::from_utf8_lossy(&theirs.stderr)
+    );
+    if !theirs.status.success() {
+        return None;
+    }
+    let theirs = String::from_utf8(theirs.stdout).unwrap();
+    assert_eq!(
+        String::from_utf8(ours.stdout).unwrap(),
+        theirs,
+        "{:?} in {} with {:?}",
+        args,
+        dir.display(),
+        env
+    );
+    Some(theirs)
+}
+
+/// A repository at `dir` with one commit and an untracked file in `sub/`.
+fn make_repo(dir: &Path, args: &[&str]) {
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    git(dir, &[&["init", "-q", "-b", "main"], args].concat());
+    fs::write(dir.join("tracked"), "one\n").unwrap();
+    git(dir, &["add", "tracked"]);
+    git(dir, &["commit", "-q", "-m", "initial"]);
+    fs::write(dir.join("sub/untracked"), "new\n").unwrap();
+}
+
+const SHOW_REF: &[&str] = &["show-ref"];
+const STATUS: &[&str] = &["status", "--porcelain"];
+
+#[test]
+fn walks_up_to_the_repository() {
+    if !git_available() {
+        return;
+    }
+    let scratch = ScratchDir::new("walk");
+    let repo = scratch.0.join("repo");
+    make_repo(&repo, &[]);
+    fs::create_dir_all(repo.join("sub/deeper")).unwrap();
+
+    for dir in [repo.clone(), repo.join("sub"), repo.join("sub/deeper")] {
+        assert!(same_as_git(&dir, &[], SHOW_REF).is_some());
+        assert_eq!(same_as_git(&dir, &[], STATUS).as_deref(), Some("?? sub/\n"));
+    }
+    // Inside the git directory there is no work tree, but refs are found
+    assert!(same_as_git(&repo.join(".git/refs"), &[], SHOW_REF).is_some());
+    // Outside any repository both fail
+    assert_eq!(same_as_git(&scratch.0, &[], SHOW_REF), None);
+}
+
+#[test]
+fn git_dir_and_work_tree_override_the_walk() {
+    if !git_available() {
+        return;
+    }
+    let scratch = ScratchDir::new("env");
+    let repo = scratch.0.join("repo");
+    let elsewhere = scratch.0.join("elsewhere");
+    make_repo(&repo, &[]);
+    make_repo(&elsewhere, &[]);
+    fs::write(elsewhere.join("tracked"), "changed\n").unwrap();
+    let git_dir = repo.join(".git");
+
+    // GIT_DIR alone makes the current directory the work tree
+    let found = same_as_git(&elsewhere, &[("GIT_DIR", &git_dir)], STATUS);
+    assert_eq!(found.as_deref(), Some(" M tracked\n?? sub/\n"));
+
+    // GIT_WORK_TREE moves it, wherever the command runs
+    for dir in [&elsewhere, &scratch.0] {
+        let env = [("GIT_DIR", git_dir.as_path()), ("GIT_WORK_TREE", &repo)];
+        assert_eq!(same_as_git(dir, &env, STATUS).as_deref(), Some("?? sub/\n"));
+    }
+
+    // A GIT_DIR that is not a repository is an error, not a reason to search
+    let missing = scratch.0.join("missing");
+    assert_eq!(same_as_git(&repo, &[("GIT_DIR", &missing)], SHOW_REF), None);
+}
+
+#[test]
+fn ceiling_directories_stop_the_walk() {
+    if !git_available() {
+        return;
+    }
+    let scratch = ScratchDir::new("ceiling");
+    let repo = scratch.0.join("repo");
+    make_repo(&repo, &[]);
+    let sub = repo.join("sub");
+
+    // The walk never enters a ceiling, so the repository above is not found
+    assert_eq!(
+        same_as_git(&sub, &[("GIT_CEILING_DIRECTORIES", &repo)], SHOW_REF),
+        None
+    );
+    // A ceiling above the repository, or the directory itself, does not hide it
+    for ceiling in [&scratch.0, &sub] {
+        let env = [("GIT_CEILING_DIRECTORIES", ceiling.as_path())];
+        assert!(same_as_git(&sub, &env, SHOW_REF).is_some());
+    }
+}
+
+#[test]
+fn gitfiles_point_at_the_git_directory() {
+    if !git_available() {
+        return;
+    }
+    let scratch = ScratchDir::new("gitfile");
+    let work = scratch.0.join("work");
+    let store = scratch.0.join("store.git");
+    make_repo(&work, &["--separate-git-dir", store.to_str().unwrap()]);
+    assert!(work.join(".git").is_file());
+
+    for dir in [work.clone(), work.join("sub")] {
+        assert!(same_as_git(&dir, &[], SHOW_REF).is_some());
+        assert_eq!(same_as_git(&dir, &[], STATUS).as_deref(), Some("?? sub/\n"));
+    }
+
+    // A relative gitdir is relative to the directory holding the file
+    fs::write(work.join(".git"), "gitdir: ../store.git\n").unwrap();
+    assert!(same_as_git(&work.join("sub"), &[], SHOW_REF).is_some());
+
+    // A gitfile pointing nowhere is an error
+    fs::write(work.join(".git"), "gitdir: ../missing.git\n").unwrap();
+    assert_eq!(same_as_git(&work, &[], SHOW_REF), None);
+}
@@ -0,0 +1,375 @@
+/*
+Clone and push over smart HTTP against a local stand-in server. The server
+runs `git upload-pack` and `git receive-pack` in stateless RPC mode, the way
+`git http-backend` does, so these tests are skipped when git is missing.
+*/
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+const BINARY: &str = env!("CARGO_BIN_EXE_codecrafters-git");
+
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn new(name: &str) -> ScratchDir {
+        let path = std::env::temp_dir().join(format!(
+            "codecrafters-git-http-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        ScratchDir(path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn git_available() -> bool {
+    Command::new("git").arg("--version").output().is_ok()
+}
+
+fn command(program: &str, dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "Test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "Test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_INDEX_FILE")
+        .env_remove("GIT_WORK_TREE");
+    command
+}
+
+fn check(output: Output, what: &str) -> String {
+    assert!(
+        output.status.success(),
+        "{} failed: {}",
+        what,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = command("git", dir, args).output().unwrap();
+    check(output, &format!("git {:?}", args))
+}
+
+/// Run git with `input` on its stdin.
+fn git_with_input(dir: &Path, args: &[&str], input: &[u8]) -> String {
+    let mut child = command("git", dir, args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    check(
+        child.wait_with_output().unwrap(),
+        &format!("git {:?}", args),
+    )
+}
+
+fn ours(dir: &Path, args: &[&str]) -> String {
+    let output = command(BINARY, dir, args).output().unwrap();
+    check(output, &format!("codecrafters-git {:?}", args))
+}
+
+/// Serve the bare repositories under `root` on a free local port, speaking
+/// protocol v2 for fetches when the client asks for it and `v2` is set.
+fn serve(root: &Path, v2: bool) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let root = root.to_path_buf();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let root = root.clone();
+            thread::spawn(move || handle(stream, &root, v2));
+        }
+    });
+    format!("http://127.0.0.1:{}", port)
+}
+
+fn handle(mut stream: TcpStream, root: &Path, v2: bool) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut content_length = 0;
+    let mut git_protocol = None;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').unwrap();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.trim().parse().unwrap(),
+            "git-protocol" => git_protocol = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).unwrap();
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap(), parts.next().unwrap());
+    let git_protocol = git_protocol.filter(|_| v2);
+    let (service, repo, advertise) = match method {
+        "GET" => {
+            let (path, query) = target.split_once('?').unwrap();
+            let service = query.strip_prefix("service=").unwrap();
+            (service, path.strip_suffix("/info/refs").unwrap(), true)
+        }
+        _ => {
+            let (path, service) = target.rsplit_once('/').unwrap();
+            (service, path, false)
+        }
+    };
+
+    let mut backend = Command::new("git");
+    backend
+        .arg(service.strip_prefix("git-").unwrap())
+        .arg("--stateless-rpc");
+    if advertise {
+        backend.arg("--advertise-refs");
+    }
+    backend
+        .arg(root.join(repo.trim_start_matches('/')))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    if let Some(protocol) = &git_protocol {
+        backend.env("GIT_PROTOCOL", protocol);
+    }
+    let mut child = backend.spawn().unwrap();
+    child.stdin.take().unwrap().write_all(&body).unwrap();
+    let output = child.wait_with_output().unwrap().stdout;
+
+    // Version 0 advertisements start with the service announcement
+    let mut response = Vec::new();
+    if advertise && git_protocol.is_none() {
+        let announcement = format!("# service={}\n", service);
+        write!(
+            response,
+            "{:04x}{}0000",
+            announcement.len() + 4,
+            announcement
+        )
+        .unwrap();
+    }
+    response.extend_from_slice(&output);
+    let kind = if advertise { "advertisement" } else { "result" };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/x-{}-{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        service,
+        kind,
+        response.len()
+    )
+    .unwrap();
+    stream.write_all(&response).unwrap();
+}
+
+/// A bare repository `origin.git` under `root` with a few branches and tags.
+fn make_origin(root: &Path) -> PathBuf {
+    let work = root.join("work");
+    fs::create_dir(&work).unwrap();
+    git(&work, &["init", "-q", "-b", "main"]);
+    fs::create_dir(work.join("src")).unwrap();
+    fs::write(work.join("README"), "hello\n").unwrap();
+    fs::write(work.join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+    git(&work, &["add", "."]);
+    git(&work, &["commit", "-q", "-m", "first"]);
+    git(&work, &["tag", "-a", "-m", "v1", "v1"]);
+    // Enough shared content for deltas to show up in the pack
+    let lines: String = (0..500).map(|i| format!("line {}\n", i)).collect();
+    fs::write(work.join("big.txt"), &lines).unwrap();
+    git(&work, &["add", "."]);
+    git(&work, &["commit", "-q", "-m", "second"]);
+    git(&work, &["checkout", "-q", "-b", "other"]);
+    fs::write(work.join("big.txt"), format!("{}one more\n", lines)).unwrap();
+    git(&work, &["commit", "-q", "-am", "third"]);
+    git(&work, &["checkout", "-q", "main"]);
+
+    let origin = root.join("origin.git");
+    git(
+        root,
+        &["clone", "-q", "--bare", "work", origin.to_str().unwrap()],
+    );
+    origin
+}
+
+fn clone_matches_origin(v2: bool) {
+    if !git_available() {
+        return;
+    }
+    let dir = ScratchDir::new(if v2 { "clone-v2" } else { "clone-v0" });
+    let origin = make_origin(&dir.0);
+    let url = format!("{}/origin.git", serve(&dir.0, v2));
+
+    ours(&dir.0, &["clone", &url, "copy"]);
+    let copy = dir.0.join("copy");
+    assert_eq!(
+        git(&copy, &["rev-parse", "HEAD"]),
+        git(&origin, &["rev-parse", "main"])
+    );
+    assert_eq!(git(&copy, &["symbolic-ref", "HEAD"]), "refs/heads/main\n");
+    assert_eq!(
+        git(
+            &copy,
+            &[
+                "for-each-ref",
+                "--format=%(objectname) %(refname:lstrip=3)",
+                "refs/remotes/origin/main",
+                "refs/remotes/origin/other"
+            ]
+        ),
+        git(
+            &origin,
+            &[
+                "for-each-ref",
+                "--format=%(objectname) %(refname:lstrip=2)",
+                "refs/heads"
+            ]
+        )
+    );
+    assert_eq!(
+        git(&copy, &["rev-parse", "v1"]),
+        git(&origin, &["rev-parse", "v1"])
+    );
+    assert_eq!(git(&copy, &["status", "--porcelain"]), "");
+    git(&copy, &["fsck", "--strict", "--no-progress"]);
+
+    ours(&dir.0, &["clone", "-b", "other", &url, "other"]);
+    let other = dir.0.join("other");
+    assert_eq!(
+        git(&other, &["rev-parse", "HEAD"]),
+        git(&origin, &["rev-parse", "other"])
+    );
+    assert!(fs::read_to_string(other.join("big.txt"))
+        .unwrap()
+        .ends_with("one more\n"));
+}
+
+#[test]
+fn clone_over_protocol_v2() {
+    clone_matches_origin(true);
+}
+
+#[test]
+fn clone_over_protocol_v0() {
+    clone_matches_origin(false);
+}
+
+#[test]
+fn push_updates_and_creates_branches() {
+    if !git_available() {
+        return;
+    }
+    let dir = ScratchDir::new("push");
+    let origin = make_origin(&dir.0);
+    let url = format!("{}/origin.git", serve(&dir.0, true));
+    ours(&dir.0, &["clone", &url, "copy"]);
+    let copy = dir.0.join("copy");
+
+    let lines: String = (0..500).map(|i| format!("line {}\n", i * 2)).collect();
+    fs::write(copy.join("big.txt"), lines).unwrap();
+    git(&copy, &["commit", "-q", "-am", "edit"]);
+    ours(&copy, &["push"]);
+    assert_eq!(
+        git(&origin, &["rev-parse", "main"]),
+        git(&copy, &["rev-parse", "HEAD"])
+    );
+
+    git(&copy, &["checkout", "-q", "-b", "topic"]);
+    fs::write(copy.join("new.txt"), "new\n").unwrap();
+    git(&copy, &["add", "new.txt"]);
+    git(&copy, &["commit", "-q", "-m", "topic"]);
+    ours(&copy, &["push", "origin", "topic"]);
+    assert_eq!(
+        git(&origin, &["rev-parse", "topic"]),
+        git(&copy, &["rev-parse", "HEAD"])
+    );
+    git(&origin, &["fsck", "--strict", "--no-progress"]);
+
+    // A non-fast-forward update is refused without force
+    git(&copy, &["reset", "-q", "--hard", "HEAD~2"]);
+    let output = command(BINARY, &copy, &["push", "origin", "topic"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_ne!(
+        git(&origin, &["rev-parse", "topic"]),
+        git(&copy, &["rev-parse", "HEAD"])
+    );
+}
+
+/// Store a tree with the given `(mode, name, hash)` entries as-is, bypassing
+/// the checks git makes on names.
+fn write_raw_tree(repo: &Path, entries: &[(&str, &str, &str)]) -> String {
+    let mut body = Vec::new();
+    for (mode, name, hash) in entries {
+        body.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+        let hash = hash.trim();
+        for i in (0..hash.len()).step_by(2) {
+            body.push(u8::from_str_radix(&hash[i..i + 2], 16).unwrap());
+        }
+    }
+    let args = ["hash-object", "-t", "tree", "--literally", "-w", "--stdin"];
+    git_with_input(repo, &args, &body).trim().to_string()
+}
+
+#[test]
+fn clone_refuses_trees_that_escape_the_work_tree() {
+    if !git_available() {
+        return;
+    }
+    let dir = ScratchDir::new("clone-evil");
+    let origin = dir.0.join("evil.git");
+    git(&dir.0, &["init", "-q", "--bare", "-b", "main", "evil.git"]);
+    let blob = git_with_input(&origin, &["hash-object", "-w", "--stdin"], b"pwned\n");
+    let hook = write_raw_tree(&origin, &[("100755", "post-checkout", &blob)]);
+    let hooks = write_raw_tree(&origin, &[("40000", "hooks", &hook)]);
+    let escape = write_raw_tree(&origin, &[("100644", "escaped", &blob)]);
+    let url = format!("{}/evil.git", serve(&dir.0, true));
+
+    for (name, tree) in [
+        (
+            "dotdot",
+            write_raw_tree(&origin, &[("40000", "..", &escape)]),
+        ),
+        (
+            "dotgit",
+            write_raw_tree(&origin, &[("40000", ".git", &hooks)]),
+        ),
+        (
+            "dotgit-case",
+            write_raw_tree(&origin, &[("40000", ".GIT", &hooks)]),
+        ),
+    ] {
+        let commit = git(&origin, &["commit-tree", "-m", name, &tree]);
+        git(&origin, &["update-ref", "refs/heads/main", commit.trim()]);
+
+        let output = command(BINARY, &dir.0, &["clone", &url, name])
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{} was checked out", name);
+        assert!(!dir.0.join("escaped").exists());
+        assert!(!dir.0.join(name).join(".git/hooks/post-checkout").exists());
+    }
+}
@@ -0,0 +1,119 @@
+/*
+Compare `status` porcelain output with real git on a work tree holding every
+kind of entry: staged, unstaged, both, renamed, deleted, untracked and
+ignored. Skipped when git is missing.
+*/
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const BINARY: &str = env!("CARGO_BIN_EXE_codecrafters-git");
+
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn new(name: &str) -> ScratchDir {
+        let path = std::env::temp_dir().join(format!(
+            "codecrafters-git-status-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        ScratchDir(path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn git_available() -> bool {
+    Command::new("git").arg("--version").output().is_ok()
+}
+
+fn run(program: &str, dir: &Path, args: &[&str]) -> String {
+    let output: Output = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "Test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "Test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("HOME", dir)
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_INDEX_FILE")
+        .env_remove("GIT_WORK_TREE")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{} {:?} failed: {}",
+        program,
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    run("git", dir, args)
+}
+
+fn write(dir: &Path, path: &str, contents: &str) {
+    let path = dir.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn porcelain_output_matches_git() {
+    if !git_available() {
+        return;
+    }
+    let scratch = ScratchDir::new("porcelain");
+    let dir = scratch.0.as_path();
+    git(dir, &["init", "-q", "-b", "main"]);
+    write(dir, ".gitignore", "*.log\nbuild/\n");
+    write(dir, "staged", "one\n");
+    write(dir, "unstaged", "one\n");
+    write(dir, "both", "one\n");
+    write(dir, "deleted", "one\n");
+    write(
+        dir,
+        "old/name",
+        "contents that are long enough to be renamed\n",
+    );
+    git(dir, &["add", "."]);
+    git(dir, &["commit", "-q", "-m", "initial"]);
+
+    write(dir, "staged", "two\n");
+    write(dir, "both", "two\n");
+    git(dir, &["add", "staged", "both"]);
+    write(dir, "both", "three\n");
+    write(dir, "unstaged", "two\n");
+    fs::remove_file(dir.join("deleted")).unwrap();
+    git(dir, &["mv", "old/name", "new-name"]);
+    write(dir, "added", "new\n");
+    git(dir, &["add", "added"]);
+    write(dir, "untracked", "new\n");
+    write(dir, "fresh/nested/file", "new\n");
+    write(dir, "debug.log", "noise\n");
+    write(dir, "build/out", "noise\n");
+
+    for args in [
+        &["status", "--porcelain"][..],
+        &["status", "--porcelain", "--ignored"],
+        &["status", "--porcelain", "-uall"],
+        &["status", "--porcelain", "-uno"],
+        &["status", "--porcelain=v2"],
+        &["status", "--porcelain=v2", "--branch", "--ignored"],
+        &["status", "--porcelain=v2", "-uall"],
+    ] {
+        assert_eq!(run(BINARY, dir, args), git(dir, args), "{:?}", args);
+    }
+}